@@ -14,9 +14,14 @@
 //! - `BENCH_FRANKENPHP_BIN=/path/to/frankenphp` — Path to FrankenPHP binary
 //! - `BENCH_FPM_ONLY=1` — Benchmark only php-fpm
 //! - `BENCH_FRANKENPHP_ONLY=1` — Benchmark only FrankenPHP
+//!
+//! The `phase_breakdown` pass prints where the embedded SAPI's time goes,
+//! averaged from `ExecutionResult::metrics()`.
 
 mod shared;
 
+use std::time::Duration;
+
 use criterion::{
     black_box, criterion_group, criterion_main, Criterion, Throughput,
 };
@@ -132,6 +137,69 @@ fn bench_large_output(c: &mut Criterion) {
     run_suite(c, &SUITES[3]);
 }
 
+fn bench_phase_breakdown(_c: &mut Criterion) {
+    shared::worker::maybe_run_worker();
+
+    if !shared::should_run_ripht_sapi() {
+        return;
+    }
+
+    const RUNS: u32 = 200;
+    let mut backend = SapiBackend::new();
+
+    println!("\nphase breakdown (mean of {RUNS} runs, microseconds)");
+    println!(
+        "{:<14} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>10}",
+        "suite",
+        "context",
+        "startup",
+        "script",
+        "headers",
+        "shutdown",
+        "total",
+        "out bytes"
+    );
+
+    for suite in SUITES {
+        let mut sum = [Duration::ZERO; 6];
+        let mut output_bytes = 0;
+
+        for _ in 0..RUNS {
+            let Some(result) =
+                backend.run(suite.script, suite.method, suite.body)
+            else {
+                continue;
+            };
+            let m = result.metrics();
+
+            for (total, phase) in sum.iter_mut().zip([
+                m.context,
+                m.startup,
+                m.script,
+                m.send_headers,
+                m.shutdown,
+                m.total,
+            ]) {
+                *total += phase;
+            }
+            output_bytes = m.output_bytes;
+        }
+
+        let us = |d: Duration| d.as_secs_f64() * 1e6 / f64::from(RUNS);
+        println!(
+            "{:<14} {:>8.1} {:>8.1} {:>8.1} {:>8.1} {:>8.1} {:>8.1} {:>10}",
+            suite.name,
+            us(sum[0]),
+            us(sum[1]),
+            us(sum[2]),
+            us(sum[3]),
+            us(sum[4]),
+            us(sum[5]),
+            output_bytes
+        );
+    }
+}
+
 fn bench_ipc_echo(c: &mut Criterion) {
     shared::worker::maybe_run_worker();
 
@@ -155,6 +223,7 @@ criterion_group!(
     bench_json_api,
     bench_post_json,
     bench_large_output,
+    bench_phase_breakdown,
     bench_ipc_echo,
 );
 
@@ -1,7 +1,7 @@
 use super::backend::Backend;
 use super::env::scripts_dir;
 use super::protocol::Method;
-use ripht_php_sapi::{RiphtSapi, WebRequest};
+use ripht_php_sapi::{ExecutionResult, RiphtSapi, WebRequest};
 
 pub struct SapiBackend {
     sapi: RiphtSapi,
@@ -13,25 +13,14 @@ impl SapiBackend {
             sapi: RiphtSapi::instance(),
         }
     }
-}
-
-impl Default for SapiBackend {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
-impl Backend for SapiBackend {
-    fn name(&self) -> &'static str {
-        "rust_sapi"
-    }
 
-    fn execute(
+    /// Runs `script` and returns the full result, metrics included.
+    pub fn run(
         &mut self,
         script: &str,
         method: Method,
         body: Option<&[u8]>,
-    ) -> Option<Vec<u8>> {
+    ) -> Option<ExecutionResult> {
         let script_path = scripts_dir().join(script);
 
         let mut builder = match method {
@@ -53,7 +42,29 @@ impl Backend for SapiBackend {
         let ctx = builder
             .build(&script_path)
             .ok()?;
-        let result = self.sapi.execute(ctx).ok()?;
+
+        self.sapi.execute(ctx).ok()
+    }
+}
+
+impl Default for SapiBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Backend for SapiBackend {
+    fn name(&self) -> &'static str {
+        "rust_sapi"
+    }
+
+    fn execute(
+        &mut self,
+        script: &str,
+        method: Method,
+        body: Option<&[u8]>,
+    ) -> Option<Vec<u8>> {
+        let result = self.run(script, method, body)?;
 
         Some(result.body().to_vec())
     }
@@ -13,7 +13,8 @@ mod shared;
 use criterion::{
     black_box, criterion_group, criterion_main, Criterion, Throughput,
 };
-use shared::{Backend, Method, SapiBackend};
+use ripht_php_sapi::{RiphtSapi, WebRequest};
+use shared::{scripts_dir, Backend, Method, SapiBackend};
 
 fn bench_simple_request_throughput(c: &mut Criterion) {
     shared::worker::maybe_run_worker();
@@ -30,6 +31,54 @@ fn bench_simple_request_throughput(c: &mut Criterion) {
     group.finish();
 }
 
+/// Compares a fresh executor per request against one reused executor,
+/// whose context pool recycles per-request allocations.
+fn bench_executor_reuse(c: &mut Criterion) {
+    shared::worker::maybe_run_worker();
+
+    let sapi = RiphtSapi::instance();
+    let script_path = scripts_dir().join("hello.php");
+
+    let mut group = c.benchmark_group("executor_reuse");
+    group.throughput(Throughput::Elements(1));
+
+    group.bench_function("fresh_executor", |b| {
+        b.iter(|| {
+            let ctx = WebRequest::get()
+                .build(&script_path)
+                .expect("build");
+            black_box(sapi.execute(ctx))
+        })
+    });
+
+    let executor = sapi
+        .executor()
+        .expect("executor");
+
+    group.bench_function("pooled_executor", |b| {
+        b.iter(|| {
+            let ctx = WebRequest::get()
+                .build(&script_path)
+                .expect("build");
+            black_box(executor.execute(ctx))
+        })
+    });
+
+    group.finish();
+
+    let stats = executor.pool_stats();
+    eprintln!(
+        "pooled_executor: {} contexts created, {} reused ({:.1}% hit rate), \
+         {} buffers allocated, {} reused, {} CStrings reused",
+        stats.contexts_created,
+        stats.contexts_reused,
+        stats.context_hit_rate() * 100.0,
+        stats.buffers_allocated,
+        stats.buffers_reused,
+        stats.cstrings_reused,
+    );
+}
+
 fn bench_file_io_throughput(c: &mut Criterion) {
     shared::worker::maybe_run_worker();
 
@@ -48,6 +97,7 @@ fn bench_file_io_throughput(c: &mut Criterion) {
 criterion_group!(
     benches,
     bench_simple_request_throughput,
+    bench_executor_reuse,
     bench_file_io_throughput
 );
 
@@ -22,6 +22,12 @@
 //! make && make install INSTALL_ROOT=/path/to/prefix
 //! ```
 //!
+//! # Thread Safety (ZTS)
+//!
+//! Builds configured with `--enable-zts` are detected from `main/php_config.h`
+//! and enable the `php_zts` cfg, which switches SAPI globals to thread-local
+//! lookups and exposes `ExecutorPool`.
+//!
 //! # Documentation Builds
 //!
 //! When `DOCS_RS` is set (docs.rs builds), this script skips all PHP discovery/linking.
@@ -33,6 +39,7 @@ fn main() {
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-env-changed=RIPHT_PHP_SAPI_PREFIX");
     println!("cargo:rustc-check-cfg=cfg(bindgen_available)");
+    println!("cargo:rustc-check-cfg=cfg(php_zts)");
 
     if env::var("DOCS_RS").is_ok() {
         println!("cargo:warning=Building docs - skipping PHP linking");
@@ -70,11 +77,36 @@ fn main() {
     println!("cargo:rustc-link-lib=static=php");
     println!("Linking against: {}", libphp_path.display());
 
+    if is_zts_build(&prefix) {
+        println!("Detected ZTS build of PHP");
+        println!("cargo:rustc-cfg=php_zts");
+    }
+
     link_php_dependencies(&lib_dir);
     link_platform_libraries();
     generate_bindgen_validation(&prefix);
 }
 
+/// Checks `php_config.h` for `#define ZTS 1`.
+fn is_zts_build(prefix: &Path) -> bool {
+    let candidates = [
+        prefix.join("include/php/main/php_config.h"),
+        prefix.join("php/main/php_config.h"),
+    ];
+
+    candidates
+        .iter()
+        .find_map(|path| std::fs::read_to_string(path).ok())
+        .is_some_and(|config| {
+            config.lines().any(|line| {
+                let mut parts = line.split_whitespace();
+                parts.next() == Some("#define")
+                    && parts.next() == Some("ZTS")
+                    && parts.next() == Some("1")
+            })
+        })
+}
+
 fn find_php_prefix() -> Option<PathBuf> {
     if let Ok(prefix) = env::var("RIPHT_PHP_SAPI_PREFIX") {
         let path = PathBuf::from(&prefix);
@@ -223,6 +255,7 @@ fn generate_bindgen_validation(php_prefix: &Path) {
         .allowlist_type("_zend_llist")
         .allowlist_type("_zend_llist_element")
         .allowlist_type("_sapi_request_parse_body_context")
+        .allowlist_type("_php_core_globals")
         .opaque_type("_zval_struct")
         .opaque_type("_zend_array")
         .opaque_type("_zend_object")
@@ -0,0 +1,52 @@
+//! Combining ready-made [`ExecutionHooks`] with tuples and [`HookChain`].
+//!
+//! Tuples fan lifecycle events out to each hook in order. A header is kept
+//! only if every hook accepts it; output is consumed if any hook consumes it.
+//!
+//! Run: `cargo run --example hooks_chain`
+
+use std::path::PathBuf;
+
+use ripht_php_sapi::{
+    HeaderPolicy, HookChain, MessageCollector, RiphtSapi, SyslogLevel,
+    TimingRecorder, WebRequest,
+};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let sapi = RiphtSapi::instance();
+
+    let scripts =
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/php_scripts");
+
+    let timing = TimingRecorder::new();
+    let messages = MessageCollector::new().with_min_level(SyslogLevel::Warning);
+    let policy = HeaderPolicy::new()
+        .with_denied_header("X-Powered-By")
+        .with_max_value_len(1024);
+
+    let exec = WebRequest::get().build(scripts.join("headers.php"))?;
+    let result = sapi.execute_with_hooks(
+        exec,
+        (timing.clone(), messages.clone(), policy.clone()),
+    )?;
+
+    println!("Status: {}", result.status_code());
+    println!("Timings: {:?}", timing.last());
+    println!("Rejected headers: {:?}", policy.rejected());
+
+    // Same hooks, assembled at runtime.
+    let hooks = HookChain::new()
+        .with(timing.clone())
+        .with(messages.clone());
+
+    let exec = WebRequest::get().build(scripts.join("errors.php"))?;
+    let result = sapi.execute_with_hooks(exec, hooks)?;
+
+    println!("Status: {}", result.status_code());
+
+    for message in messages.take() {
+        println!("[{:?}] {}", message.level, message.message);
+    }
+
+    Ok(())
+}
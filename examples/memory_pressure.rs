@@ -4,7 +4,9 @@
 
 use std::path::PathBuf;
 
-use ripht_php_sapi::{RiphtSapi, WebRequest};
+use ripht_php_sapi::{ExecutionError, RiphtSapi, WebRequest};
+
+const MB: f64 = 1024.0 * 1024.0;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let sapi = RiphtSapi::instance();
@@ -28,20 +30,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             .with_uri(format!("/?{}", query))
             .build(&script)?;
         let result = sapi.execute(exec)?;
+        let usage = result.memory_usage();
 
-        if let Ok(json) =
-            serde_json::from_slice::<serde_json::Value>(&result.body())
-        {
-            let peak = json["peak_after"]
-                .as_u64()
-                .unwrap_or(0) as f64
-                / 1024.0
-                / 1024.0;
-
-            println!("{:.<30} peak={:.1}MB", name, peak);
-        } else {
-            println!("{:.<30} {}", name, result.status_code());
-        }
+        println!(
+            "{:.<30} peak={:.1}MB end={:.1}MB",
+            name,
+            usage.peak as f64 / MB,
+            usage.current as f64 / MB
+        );
     }
 
     println!("\nRepeated allocation cycles:");
@@ -52,17 +48,32 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             .build(&script)?;
         let result = sapi.execute(exec)?;
 
-        if let Ok(json) =
-            serde_json::from_slice::<serde_json::Value>(&result.body())
-        {
-            let peak = json["peak_after"]
-                .as_u64()
-                .unwrap_or(0) as f64
-                / 1024.0
-                / 1024.0;
+        println!(
+            "  Cycle {}: peak={:.1}MB",
+            i,
+            result.memory_usage().peak as f64 / MB
+        );
+    }
+
+    println!("\nWith a 4MB memory limit:");
+
+    let exec = WebRequest::get()
+        .with_uri("/?action=allocate&size=10000")
+        .build(&script)?
+        .memory_limit(4 * 1024 * 1024);
 
-            println!("  Cycle {}: peak={:.1}MB", i, peak);
+    match sapi.execute(exec) {
+        Err(ExecutionError::MemoryLimitExceeded { limit, peak_usage }) => {
+            println!(
+                "  Aborted: limit={:.1}MB peak={:.1}MB",
+                limit as f64 / MB,
+                peak_usage as f64 / MB
+            );
         }
+        other => println!(
+            "  Unexpected outcome: {:?}",
+            other.map(|r| r.status_code())
+        ),
     }
 
     Ok(())
@@ -0,0 +1,46 @@
+//! Stores PHP sessions in a Rust-side map instead of session files.
+//!
+//! Run: `cargo run --example session_store --features session`
+
+use std::path::PathBuf;
+
+use ripht_php_sapi::{InMemorySessionStore, RiphtSapi, WebRequest};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let store = InMemorySessionStore::new();
+    let sapi = RiphtSapi::builder()
+        .with_session_store(store.clone())
+        .build()?;
+
+    let script_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/php_scripts")
+        .join("session.php");
+
+    let mut cookie = None;
+
+    for visit in 1..=3 {
+        let mut request = WebRequest::get();
+        if let Some(cookie) = &cookie {
+            request = request.with_raw_cookie_header(cookie);
+        }
+
+        let result = sapi.execute(request.build(&script_path)?)?;
+
+        cookie = cookie.or_else(|| {
+            result
+                .all_headers()
+                .find(|h| {
+                    h.name()
+                        .eq_ignore_ascii_case("Set-Cookie")
+                })
+                .and_then(|h| h.value().split(';').next())
+                .map(str::to_string)
+        });
+
+        println!("Visit {visit}: {}", result.body_string());
+    }
+
+    println!("Sessions in store: {}", store.len());
+
+    Ok(())
+}
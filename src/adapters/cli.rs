@@ -151,6 +151,11 @@ impl CliRequest {
         self
     }
 
+    /// Builds the request for `script_path`.
+    ///
+    /// Besides plain scripts this accepts a `.phar` archive, which runs its
+    /// stub, or a `phar://` path to an entry inside one, e.g.
+    /// `phar:///opt/app.phar/public/index.php`.
     pub fn build(
         self,
         script_path: impl AsRef<Path>,
@@ -166,7 +171,7 @@ impl CliRequest {
             "Building CLI request"
         );
 
-        if !script_path.exists() {
+        if !crate::sapi::script_exists(&script_path) {
             return Err(CliRequestError::ScriptNotFound(script_path));
         }
 
@@ -207,6 +212,12 @@ impl CliRequest {
             env_vars: self.env_vars,
             ini_overrides: self.ini_overrides,
             log_to_stderr: true,
+            buffer_policy: None,
+            max_response_size: None,
+            security_policy: None,
+            memory_limit: None,
+            request_time: None,
+            random_seed: None,
         })
     }
 }
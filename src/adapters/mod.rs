@@ -185,7 +185,7 @@ pub trait PhpSapiAdapter {
             .as_ref()
             .to_path_buf();
 
-        if !path.exists() {
+        if !crate::sapi::script_exists(&path) {
             return Err(AdapterError::ScriptNotFound(path));
         }
 
@@ -373,6 +373,11 @@ impl WebRequest {
         self
     }
 
+    /// Builds the request for `script_path`.
+    ///
+    /// Besides plain scripts this accepts a `.phar` archive, which runs its
+    /// stub, or a `phar://` path to an entry inside one, e.g.
+    /// `phar:///opt/app.phar/public/index.php`.
     pub fn build(
         self,
         script_path: impl AsRef<Path>,
@@ -392,7 +397,7 @@ impl WebRequest {
             .as_ref()
             .to_path_buf();
 
-        if !script_path.exists() {
+        if !crate::sapi::script_exists(&script_path) {
             return Err(WebRequestError::ScriptNotFound(script_path));
         }
 
@@ -480,6 +485,12 @@ impl WebRequest {
             env_vars: self.env_vars,
             ini_overrides: self.ini_overrides,
             log_to_stderr: false,
+            buffer_policy: None,
+            max_response_size: None,
+            security_policy: None,
+            memory_limit: None,
+            request_time: None,
+            random_seed: None,
         })
     }
 }
@@ -0,0 +1,212 @@
+//! Output buffer sizing and limits.
+
+use std::sync::OnceLock;
+
+const MIN_BUFFER_SIZE: usize = 4096;
+const DEFAULT_BUFFER_SIZE: usize = 65536;
+
+/// How the output buffer grows once its capacity is exhausted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BufferGrowth {
+    /// Quadruple the capacity.
+    X4,
+    /// Double the capacity.
+    X2,
+    /// Grow in fixed steps of the given number of bytes.
+    Fixed(usize),
+}
+
+/// What happens when output exceeds [`BufferPolicy::with_max_size`].
+///
+/// Either way PHP is told the connection was aborted, so the script stops
+/// at its next output unless it called `ignore_user_abort(true)`; shutdown
+/// functions still run and later output is discarded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum OverflowAction {
+    /// Abort the request and fail with `ExecutionError::OutputLimitExceeded`.
+    #[default]
+    Abort,
+    /// Keep the output up to the limit, discard the rest and mark the result
+    /// as truncated (see `ExecutionResult::is_truncated`).
+    Truncate,
+}
+
+/// Output buffer configuration.
+///
+/// Set engine-wide with `EngineBuilder::with_buffer_policy` or per request
+/// with [`ExecutionContext::buffer_policy`](super::ExecutionContext::buffer_policy).
+/// Without either, [`BufferPolicy::from_env`] applies.
+///
+/// ```
+/// use ripht_php_sapi::{BufferGrowth, BufferPolicy, OverflowAction};
+///
+/// let policy = BufferPolicy::new()
+///     .with_initial_capacity(8 * 1024)
+///     .with_growth(BufferGrowth::X2)
+///     .with_max_size(4 * 1024 * 1024)
+///     .with_overflow(OverflowAction::Truncate);
+///
+/// assert_eq!(policy.max_size(), Some(4 * 1024 * 1024));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferPolicy {
+    initial_capacity: usize,
+    growth: BufferGrowth,
+    max_size: Option<usize>,
+    on_overflow: OverflowAction,
+}
+
+impl Default for BufferPolicy {
+    fn default() -> Self {
+        Self {
+            initial_capacity: DEFAULT_BUFFER_SIZE,
+            growth: BufferGrowth::X4,
+            max_size: None,
+            on_overflow: OverflowAction::Abort,
+        }
+    }
+}
+
+impl BufferPolicy {
+    /// 64 KiB initial capacity, 4x growth, no size limit.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads `SAPI_INIT_BUF` (bytes, at least 4096) and `SAPI_BUF_GROWTH`
+    /// (`x4`, `x2` or `fixed32k`), falling back to the defaults.
+    #[must_use]
+    pub fn from_env() -> Self {
+        let initial_capacity = std::env::var("SAPI_INIT_BUF")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|&n| n >= MIN_BUFFER_SIZE)
+            .unwrap_or(DEFAULT_BUFFER_SIZE);
+
+        let growth = match std::env::var("SAPI_BUF_GROWTH")
+            .ok()
+            .as_deref()
+        {
+            Some("x2") | Some("X2") => BufferGrowth::X2,
+            Some("fixed32k") => BufferGrowth::Fixed(32 * 1024),
+            _ => BufferGrowth::X4,
+        };
+
+        Self {
+            initial_capacity,
+            growth,
+            ..Self::default()
+        }
+    }
+
+    /// Environment policy, read once per process.
+    pub(crate) fn env_default() -> Self {
+        static ENV_POLICY: OnceLock<BufferPolicy> = OnceLock::new();
+
+        *ENV_POLICY.get_or_init(Self::from_env)
+    }
+
+    #[must_use]
+    pub fn with_initial_capacity(mut self, bytes: usize) -> Self {
+        self.initial_capacity = bytes;
+        self
+    }
+
+    #[must_use]
+    pub fn with_growth(mut self, growth: BufferGrowth) -> Self {
+        self.growth = growth;
+        self
+    }
+
+    /// Hard cap on the response body size in bytes. Applies to streamed
+    /// output as well.
+    #[must_use]
+    pub fn with_max_size(mut self, bytes: usize) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    #[must_use]
+    pub fn with_overflow(mut self, action: OverflowAction) -> Self {
+        self.on_overflow = action;
+        self
+    }
+
+    pub fn initial_capacity(&self) -> usize {
+        self.initial_capacity
+    }
+
+    pub fn growth(&self) -> BufferGrowth {
+        self.growth
+    }
+
+    pub fn max_size(&self) -> Option<usize> {
+        self.max_size
+    }
+
+    pub fn on_overflow(&self) -> OverflowAction {
+        self.on_overflow
+    }
+
+    /// Capacity to grow to when `required` bytes don't fit in `current`.
+    pub(crate) fn grow(&self, current: usize, required: usize) -> usize {
+        let grown = match self.growth {
+            BufferGrowth::X4 => current
+                .saturating_mul(4)
+                .max(required.saturating_add(self.initial_capacity)),
+            BufferGrowth::X2 => current
+                .saturating_mul(2)
+                .max(required.saturating_add(self.initial_capacity)),
+            BufferGrowth::Fixed(step) => {
+                let step = step.max(1);
+                let missing = required.saturating_sub(current);
+                current.saturating_add(missing.div_ceil(step) * step)
+            }
+        };
+
+        // Never reserve past the cap; the tail beyond it is never written.
+        match self.max_size {
+            Some(max) => grown.min(max),
+            None => grown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_growth_strategies() {
+        let x4 = BufferPolicy::new().with_initial_capacity(4096);
+        assert_eq!(x4.grow(4096, 5000), 16384);
+
+        let x2 = x4.with_growth(BufferGrowth::X2);
+        assert_eq!(x2.grow(4096, 5000), 9096);
+
+        let fixed = x4.with_growth(BufferGrowth::Fixed(1000));
+        assert_eq!(fixed.grow(4096, 6500), 7096);
+    }
+
+    #[test]
+    fn test_growth_respects_max_size() {
+        let policy = BufferPolicy::new()
+            .with_initial_capacity(4096)
+            .with_max_size(10_000);
+
+        assert_eq!(policy.grow(4096, 5000), 10_000);
+    }
+
+    #[test]
+    fn test_defaults() {
+        let policy = BufferPolicy::default();
+
+        assert_eq!(policy.initial_capacity(), DEFAULT_BUFFER_SIZE);
+        assert_eq!(policy.growth(), BufferGrowth::X4);
+        assert_eq!(policy.max_size(), None);
+        assert_eq!(policy.on_overflow(), OverflowAction::Abort);
+    }
+}
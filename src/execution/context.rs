@@ -1,7 +1,9 @@
 use std::ffi::CString;
 use std::fmt;
 use std::path::PathBuf;
+use std::time::SystemTime;
 
+use super::{BufferPolicy, SecurityPolicy};
 use crate::sapi::ServerVars;
 use crate::ExecutionError;
 
@@ -17,6 +19,18 @@ pub struct ExecutionContext {
     pub env_vars: Vec<(String, String)>,
     pub ini_overrides: Vec<(String, String)>,
     pub log_to_stderr: bool,
+    /// Overrides the engine's output buffer policy for this request.
+    pub buffer_policy: Option<BufferPolicy>,
+    /// Response body cap in bytes, over the effective buffer policy.
+    pub max_response_size: Option<usize>,
+    /// Overrides the engine's security policy for this request.
+    pub security_policy: Option<SecurityPolicy>,
+    /// `memory_limit` for this request, in bytes.
+    pub memory_limit: Option<usize>,
+    /// Fixed `REQUEST_TIME` / `REQUEST_TIME_FLOAT` instead of the real clock.
+    pub request_time: Option<SystemTime>,
+    /// Seed for `mt_rand()` / `rand()`, applied before the script runs.
+    pub random_seed: Option<u32>,
 }
 
 impl ExecutionContext {
@@ -28,6 +42,12 @@ impl ExecutionContext {
             env_vars: Vec::new(),
             ini_overrides: Vec::new(),
             log_to_stderr: false,
+            buffer_policy: None,
+            max_response_size: None,
+            security_policy: None,
+            memory_limit: None,
+            request_time: None,
+            random_seed: None,
         }
     }
 
@@ -89,6 +109,65 @@ impl ExecutionContext {
         self
     }
 
+    pub fn buffer_policy(mut self, policy: BufferPolicy) -> Self {
+        self.buffer_policy = Some(policy);
+        self
+    }
+
+    /// Caps the response body at `bytes`, keeping the rest of the buffer
+    /// policy (the engine's, unless one was set on this context).
+    pub fn max_response_size(mut self, bytes: usize) -> Self {
+        self.max_response_size = Some(bytes);
+        self
+    }
+
+    pub fn security_policy(mut self, policy: SecurityPolicy) -> Self {
+        self.security_policy = Some(policy);
+        self
+    }
+
+    /// Caps the request's memory. Exhausting it fails the execution with
+    /// `ExecutionError::MemoryLimitExceeded`. Takes precedence over a
+    /// `memory_limit` INI override.
+    pub fn memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    /// Pins `$_SERVER['REQUEST_TIME']` and `REQUEST_TIME_FLOAT` to `time`,
+    /// e.g. for snapshot tests. `time()`, `microtime()` and `date()` still
+    /// read the system clock.
+    pub fn request_time(mut self, time: SystemTime) -> Self {
+        self.server_vars
+            .request_time_at(time);
+        self.request_time = Some(time);
+        self
+    }
+
+    /// Seeds the Mersenne Twister behind `mt_rand()`, `rand()`, `shuffle()`
+    /// and `array_rand()` so their sequence is reproducible. `random_int()`
+    /// and `random_bytes()` use the OS CSPRNG and can't be seeded.
+    pub fn random_seed(mut self, seed: u32) -> Self {
+        self.random_seed = Some(seed);
+        self
+    }
+
+    /// Buffer policy for the request: this context's or `engine_default`,
+    /// capped at `max_response_size`.
+    pub(crate) fn resolve_buffer_policy(
+        &self,
+        engine_default: impl FnOnce() -> BufferPolicy,
+    ) -> BufferPolicy {
+        let policy = self
+            .buffer_policy
+            .unwrap_or_else(engine_default);
+
+        match self.max_response_size {
+            Some(bytes) => policy.with_max_size(bytes),
+            None => policy,
+        }
+    }
+
     pub fn path_as_cstring(&self) -> Result<CString, ExecutionError> {
         let path_str = self
             .script_path
@@ -0,0 +1,503 @@
+//! Fan-out of lifecycle events to several hooks.
+//!
+//! Tuples of hooks and [`HookChain`] share the same semantics:
+//!
+//! - Notifications reach every hook, in order. `on_response_head` edits
+//!   pass along the chain, so each hook sees its predecessors' changes.
+//! - `on_request_received` stops at the first hook that responds; later hooks
+//!   never see that request.
+//! - `on_header` keeps a header only if every hook accepts it. The first
+//!   rejection wins and later hooks never see that header.
+//! - `on_output` reaches every hook; the output is consumed
+//!   ([`OutputAction::Done`]) if any hook returns `Done`.
+//! - `is_connection_alive` is true only while every hook reports it alive.
+
+use std::path::Path;
+
+use super::{ExecutionHooks, OutputAction, RequestAction};
+use crate::execution::{
+    ExecutionContext, ExecutionMessage, ExecutionResult, InformationalResponse,
+    ResponseHead,
+};
+
+/// An ordered, dynamically sized list of hooks.
+///
+/// Use a tuple such as `(TimingRecorder, HeaderPolicy)` when the set of hooks
+/// is fixed; it avoids boxing and stays `Send` when its members are.
+///
+/// ```
+/// use ripht_php_sapi::{HeaderPolicy, HookChain, MessageCollector, TimingRecorder};
+///
+/// let timing = TimingRecorder::new();
+/// let messages = MessageCollector::new();
+///
+/// let hooks = HookChain::new()
+///     .with(timing.clone())
+///     .with(messages.clone())
+///     .with(HeaderPolicy::new().with_denied_header("X-Powered-By"));
+///
+/// assert_eq!(hooks.len(), 3);
+/// ```
+#[derive(Default)]
+pub struct HookChain {
+    hooks: Vec<Box<dyn ExecutionHooks>>,
+}
+
+impl HookChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a hook; it runs after the hooks already in the chain.
+    #[must_use]
+    pub fn with(mut self, hook: impl ExecutionHooks + 'static) -> Self {
+        self.push(hook);
+        self
+    }
+
+    pub fn push(&mut self, hook: impl ExecutionHooks + 'static) {
+        self.hooks
+            .push(Box::new(hook));
+    }
+
+    pub fn len(&self) -> usize {
+        self.hooks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+}
+
+impl ExecutionHooks for HookChain {
+    fn on_request_received(
+        &mut self,
+        ctx: &mut ExecutionContext,
+    ) -> RequestAction {
+        for hook in &mut self.hooks {
+            if let RequestAction::Respond(result) =
+                hook.on_request_received(ctx)
+            {
+                return RequestAction::Respond(result);
+            }
+        }
+
+        RequestAction::Continue
+    }
+
+    fn on_context_created(&mut self) {
+        self.hooks
+            .iter_mut()
+            .for_each(|h| h.on_context_created());
+    }
+
+    fn on_request_starting(&mut self) {
+        self.hooks
+            .iter_mut()
+            .for_each(|h| h.on_request_starting());
+    }
+
+    fn on_request_started(&mut self) {
+        self.hooks
+            .iter_mut()
+            .for_each(|h| h.on_request_started());
+    }
+
+    fn on_script_executing(&mut self, script_path: &Path) {
+        self.hooks
+            .iter_mut()
+            .for_each(|h| h.on_script_executing(script_path));
+    }
+
+    fn on_script_executed(&mut self, success: bool) {
+        self.hooks
+            .iter_mut()
+            .for_each(|h| h.on_script_executed(success));
+    }
+
+    fn on_output(&mut self, data: &[u8]) -> OutputAction {
+        self.hooks
+            .iter_mut()
+            .fold(OutputAction::Continue, |action, h| {
+                combine_output(action, h.on_output(data))
+            })
+    }
+
+    fn on_flush(&mut self) {
+        self.hooks
+            .iter_mut()
+            .for_each(|h| h.on_flush());
+    }
+
+    fn on_response_head(&mut self, head: &mut ResponseHead) {
+        self.hooks
+            .iter_mut()
+            .for_each(|h| h.on_response_head(head));
+    }
+
+    fn on_informational(&mut self, response: &InformationalResponse) {
+        self.hooks
+            .iter_mut()
+            .for_each(|h| h.on_informational(response));
+    }
+
+    fn on_header(&mut self, name: &str, value: &str) -> bool {
+        self.hooks
+            .iter_mut()
+            .all(|h| h.on_header(name, value))
+    }
+
+    fn on_status(&mut self, code: u16) {
+        self.hooks
+            .iter_mut()
+            .for_each(|h| h.on_status(code));
+    }
+
+    fn on_php_message(&mut self, message: &ExecutionMessage) {
+        self.hooks
+            .iter_mut()
+            .for_each(|h| h.on_php_message(message));
+    }
+
+    fn is_connection_alive(&self) -> bool {
+        self.hooks
+            .iter()
+            .all(|h| h.is_connection_alive())
+    }
+
+    fn on_request_finishing(&mut self) {
+        self.hooks
+            .iter_mut()
+            .for_each(|h| h.on_request_finishing());
+    }
+
+    fn on_request_finished(&mut self, result: &ExecutionResult) {
+        self.hooks
+            .iter_mut()
+            .for_each(|h| h.on_request_finished(result));
+    }
+}
+
+impl<H: ExecutionHooks + ?Sized> ExecutionHooks for Box<H> {
+    fn on_request_received(
+        &mut self,
+        ctx: &mut ExecutionContext,
+    ) -> RequestAction {
+        (**self).on_request_received(ctx)
+    }
+
+    fn on_context_created(&mut self) {
+        (**self).on_context_created()
+    }
+
+    fn on_request_starting(&mut self) {
+        (**self).on_request_starting()
+    }
+
+    fn on_request_started(&mut self) {
+        (**self).on_request_started()
+    }
+
+    fn on_script_executing(&mut self, script_path: &Path) {
+        (**self).on_script_executing(script_path)
+    }
+
+    fn on_script_executed(&mut self, success: bool) {
+        (**self).on_script_executed(success)
+    }
+
+    fn on_output(&mut self, data: &[u8]) -> OutputAction {
+        (**self).on_output(data)
+    }
+
+    fn on_flush(&mut self) {
+        (**self).on_flush()
+    }
+
+    fn on_response_head(&mut self, head: &mut ResponseHead) {
+        (**self).on_response_head(head)
+    }
+
+    fn on_informational(&mut self, response: &InformationalResponse) {
+        (**self).on_informational(response)
+    }
+
+    fn on_header(&mut self, name: &str, value: &str) -> bool {
+        (**self).on_header(name, value)
+    }
+
+    fn on_status(&mut self, code: u16) {
+        (**self).on_status(code)
+    }
+
+    fn on_php_message(&mut self, message: &ExecutionMessage) {
+        (**self).on_php_message(message)
+    }
+
+    fn is_connection_alive(&self) -> bool {
+        (**self).is_connection_alive()
+    }
+
+    fn on_request_finishing(&mut self) {
+        (**self).on_request_finishing()
+    }
+
+    fn on_request_finished(&mut self, result: &ExecutionResult) {
+        (**self).on_request_finished(result)
+    }
+}
+
+fn combine_output(a: OutputAction, b: OutputAction) -> OutputAction {
+    match (a, b) {
+        (OutputAction::Continue, OutputAction::Continue) => {
+            OutputAction::Continue
+        }
+        _ => OutputAction::Done,
+    }
+}
+
+macro_rules! impl_hooks_for_tuple {
+    ($($name:ident . $idx:tt),+) => {
+        impl<$($name: ExecutionHooks),+> ExecutionHooks for ($($name,)+) {
+            fn on_request_received(
+                &mut self,
+                ctx: &mut ExecutionContext,
+            ) -> RequestAction {
+                $(
+                    if let RequestAction::Respond(result) =
+                        self.$idx.on_request_received(ctx)
+                    {
+                        return RequestAction::Respond(result);
+                    }
+                )+
+
+                RequestAction::Continue
+            }
+
+            fn on_context_created(&mut self) {
+                $(self.$idx.on_context_created();)+
+            }
+
+            fn on_request_starting(&mut self) {
+                $(self.$idx.on_request_starting();)+
+            }
+
+            fn on_request_started(&mut self) {
+                $(self.$idx.on_request_started();)+
+            }
+
+            fn on_script_executing(&mut self, script_path: &Path) {
+                $(self.$idx.on_script_executing(script_path);)+
+            }
+
+            fn on_script_executed(&mut self, success: bool) {
+                $(self.$idx.on_script_executed(success);)+
+            }
+
+            fn on_output(&mut self, data: &[u8]) -> OutputAction {
+                let action = OutputAction::Continue;
+                $(let action = combine_output(action, self.$idx.on_output(data));)+
+                action
+            }
+
+            fn on_flush(&mut self) {
+                $(self.$idx.on_flush();)+
+            }
+
+            fn on_response_head(&mut self, head: &mut ResponseHead) {
+                $(self.$idx.on_response_head(head);)+
+            }
+
+            fn on_informational(&mut self, response: &InformationalResponse) {
+                $(self.$idx.on_informational(response);)+
+            }
+
+            fn on_header(&mut self, name: &str, value: &str) -> bool {
+                $(self.$idx.on_header(name, value))&&+
+            }
+
+            fn on_status(&mut self, code: u16) {
+                $(self.$idx.on_status(code);)+
+            }
+
+            fn on_php_message(&mut self, message: &ExecutionMessage) {
+                $(self.$idx.on_php_message(message);)+
+            }
+
+            fn is_connection_alive(&self) -> bool {
+                $(self.$idx.is_connection_alive())&&+
+            }
+
+            fn on_request_finishing(&mut self) {
+                $(self.$idx.on_request_finishing();)+
+            }
+
+            fn on_request_finished(&mut self, result: &ExecutionResult) {
+                $(self.$idx.on_request_finished(result);)+
+            }
+        }
+    };
+}
+
+impl_hooks_for_tuple!(A.0, B.1);
+impl_hooks_for_tuple!(A.0, B.1, C.2);
+impl_hooks_for_tuple!(A.0, B.1, C.2, D.3);
+impl_hooks_for_tuple!(A.0, B.1, C.2, D.3, E.4);
+impl_hooks_for_tuple!(A.0, B.1, C.2, D.3, E.4, F.5);
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::execution::NoOpHooks;
+
+    struct Recorder {
+        name: &'static str,
+        log: Rc<RefCell<Vec<String>>>,
+        accept_headers: bool,
+        output: OutputAction,
+    }
+
+    impl Recorder {
+        fn new(name: &'static str, log: &Rc<RefCell<Vec<String>>>) -> Self {
+            Self {
+                name,
+                log: Rc::clone(log),
+                accept_headers: true,
+                output: OutputAction::Continue,
+            }
+        }
+    }
+
+    impl ExecutionHooks for Recorder {
+        fn on_status(&mut self, code: u16) {
+            self.log
+                .borrow_mut()
+                .push(format!("{}:{code}", self.name));
+        }
+
+        fn on_header(&mut self, name: &str, _value: &str) -> bool {
+            self.log
+                .borrow_mut()
+                .push(format!("{}:{name}", self.name));
+            self.accept_headers
+        }
+
+        fn on_output(&mut self, _data: &[u8]) -> OutputAction {
+            self.log
+                .borrow_mut()
+                .push(format!("{}:output", self.name));
+            self.output
+        }
+    }
+
+    #[test]
+    fn test_tuple_fans_out_in_order() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut hooks = (Recorder::new("a", &log), Recorder::new("b", &log));
+
+        hooks.on_status(404);
+
+        assert_eq!(*log.borrow(), ["a:404", "b:404"]);
+    }
+
+    struct Gate;
+
+    impl ExecutionHooks for Gate {
+        fn on_request_received(
+            &mut self,
+            ctx: &mut ExecutionContext,
+        ) -> RequestAction {
+            if ctx
+                .server_vars
+                .get("HTTP_AUTHORIZATION")
+                .is_some()
+            {
+                return RequestAction::Continue;
+            }
+
+            RequestAction::Respond(ExecutionResult::new(
+                401,
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+            ))
+        }
+    }
+
+    struct Tagger;
+
+    impl ExecutionHooks for Tagger {
+        fn on_request_received(
+            &mut self,
+            ctx: &mut ExecutionContext,
+        ) -> RequestAction {
+            ctx.server_vars
+                .set("X_TAGGED", "1");
+            RequestAction::Continue
+        }
+    }
+
+    #[test]
+    fn test_first_response_short_circuits() {
+        let mut ctx = ExecutionContext::script("/index.php");
+
+        let action = (Gate, Tagger).on_request_received(&mut ctx);
+
+        assert!(matches!(
+            action,
+            RequestAction::Respond(ref r) if r.status_code() == 401
+        ));
+        assert!(ctx
+            .server_vars
+            .get("X_TAGGED")
+            .is_none());
+
+        let mut ctx = ctx.var("HTTP_AUTHORIZATION", "Bearer t");
+
+        assert!(matches!(
+            HookChain::new()
+                .with(Gate)
+                .with(Tagger)
+                .on_request_received(&mut ctx),
+            RequestAction::Continue
+        ));
+        assert_eq!(
+            ctx.server_vars
+                .get("X_TAGGED"),
+            Some("1")
+        );
+    }
+
+    #[test]
+    fn test_header_rejection_short_circuits() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut first = Recorder::new("a", &log);
+        first.accept_headers = false;
+
+        let mut hooks = HookChain::new()
+            .with(first)
+            .with(Recorder::new("b", &log));
+
+        assert!(!hooks.on_header("X-Test", "1"));
+        assert_eq!(*log.borrow(), ["a:X-Test"]);
+    }
+
+    #[test]
+    fn test_output_done_if_any_hook_is_done() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut first = Recorder::new("a", &log);
+        first.output = OutputAction::Done;
+
+        let mut hooks = (first, Recorder::new("b", &log));
+
+        assert_eq!(hooks.on_output(b"body"), OutputAction::Done);
+        assert_eq!(*log.borrow(), ["a:output", "b:output"]);
+        assert_eq!(
+            (NoOpHooks, NoOpHooks).on_output(b"body"),
+            OutputAction::Continue
+        );
+    }
+}
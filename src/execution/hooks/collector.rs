@@ -0,0 +1,84 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use super::ExecutionHooks;
+use crate::execution::{ExecutionMessage, SyslogLevel};
+
+/// Collects PHP errors, warnings and notices across requests.
+///
+/// Clones share the same storage, so one clone can be passed to the
+/// executor while another reads the messages back.
+#[derive(Debug, Clone, Default)]
+pub struct MessageCollector {
+    messages: Arc<Mutex<Vec<ExecutionMessage>>>,
+    min_level: Option<SyslogLevel>,
+}
+
+impl MessageCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps only messages at `level` or more severe.
+    #[must_use]
+    pub fn with_min_level(mut self, level: SyslogLevel) -> Self {
+        self.min_level = Some(level);
+        self
+    }
+
+    /// Messages collected so far.
+    pub fn messages(&self) -> Vec<ExecutionMessage> {
+        self.lock().clone()
+    }
+
+    /// Returns the collected messages and clears the collector.
+    pub fn take(&self) -> Vec<ExecutionMessage> {
+        std::mem::take(&mut *self.lock())
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<ExecutionMessage>> {
+        self.messages
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl ExecutionHooks for MessageCollector {
+    fn on_php_message(&mut self, message: &ExecutionMessage) {
+        // Lower syslog values are more severe.
+        let wanted = self
+            .min_level
+            .is_none_or(|min| message.level as i32 <= min as i32);
+
+        if wanted {
+            self.lock()
+                .push(message.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_min_level_filters_messages() {
+        let collector =
+            MessageCollector::new().with_min_level(SyslogLevel::Warning);
+        let mut hooks = collector.clone();
+
+        hooks.on_php_message(&ExecutionMessage::new(SyslogLevel::Error, "e"));
+        hooks.on_php_message(&ExecutionMessage::new(SyslogLevel::Notice, "n"));
+
+        assert_eq!(collector.len(), 1);
+        assert_eq!(collector.take()[0].message, "e");
+        assert!(collector.is_empty());
+    }
+}
@@ -0,0 +1,118 @@
+use std::sync::{Arc, Mutex};
+
+use super::ExecutionHooks;
+use crate::execution::ResponseHeader;
+
+/// Drops response headers that break a policy.
+///
+/// Header names are matched case-insensitively. Once an allow list is set,
+/// only listed headers pass; the deny list always applies. Rejected headers
+/// can be read back through [`HeaderPolicy::rejected`], shared by clones.
+///
+/// ```
+/// use ripht_php_sapi::HeaderPolicy;
+///
+/// let policy = HeaderPolicy::new()
+///     .with_denied_header("X-Powered-By")
+///     .with_max_value_len(4096);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct HeaderPolicy {
+    denied: Vec<String>,
+    allowed: Option<Vec<String>>,
+    max_value_len: Option<usize>,
+    rejected: Arc<Mutex<Vec<ResponseHeader>>>,
+}
+
+impl HeaderPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn with_denied_header(mut self, name: impl Into<String>) -> Self {
+        self.denied.push(name.into());
+        self
+    }
+
+    /// Adds `name` to the allow list, switching the policy to allow-list mode.
+    #[must_use]
+    pub fn with_allowed_header(mut self, name: impl Into<String>) -> Self {
+        self.allowed
+            .get_or_insert_with(Vec::new)
+            .push(name.into());
+        self
+    }
+
+    /// Rejects headers whose value is longer than `bytes`.
+    #[must_use]
+    pub fn with_max_value_len(mut self, bytes: usize) -> Self {
+        self.max_value_len = Some(bytes);
+        self
+    }
+
+    /// Whether a header passes the policy.
+    pub fn permits(&self, name: &str, value: &str) -> bool {
+        let listed = |list: &[String]| {
+            list.iter()
+                .any(|n| n.eq_ignore_ascii_case(name))
+        };
+
+        !listed(&self.denied)
+            && self
+                .allowed
+                .as_deref()
+                .is_none_or(listed)
+            && self
+                .max_value_len
+                .is_none_or(|max| value.len() <= max)
+    }
+
+    /// Headers rejected so far.
+    pub fn rejected(&self) -> Vec<ResponseHeader> {
+        self.rejected
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+}
+
+impl ExecutionHooks for HeaderPolicy {
+    fn on_header(&mut self, name: &str, value: &str) -> bool {
+        if self.permits(name, value) {
+            return true;
+        }
+
+        self.rejected
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(ResponseHeader::new(name, value));
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deny_list_is_case_insensitive() {
+        let mut policy = HeaderPolicy::new().with_denied_header("X-Powered-By");
+
+        assert!(!policy.on_header("x-powered-by", "PHP/8.4"));
+        assert!(policy.on_header("Content-Type", "text/html"));
+        assert_eq!(policy.rejected().len(), 1);
+    }
+
+    #[test]
+    fn test_allow_list_and_value_length() {
+        let policy = HeaderPolicy::new()
+            .with_allowed_header("Content-Type")
+            .with_max_value_len(16);
+
+        assert!(policy.permits("content-type", "text/plain"));
+        assert!(!policy.permits("Content-Type", "x".repeat(17).as_str()));
+        assert!(!policy.permits("Set-Cookie", "a=1"));
+    }
+}
@@ -1,6 +1,20 @@
+//! Request lifecycle callbacks and composable hook implementations.
+
+mod chain;
+mod collector;
+mod header_policy;
+mod timing;
+
 use std::path::Path;
 
+pub use chain::HookChain;
+pub use collector::MessageCollector;
+pub use header_policy::HeaderPolicy;
+pub use timing::{RequestTimings, TimingRecorder};
+
+use super::context::ExecutionContext;
 use super::message::ExecutionMessage;
+use super::response_head::{InformationalResponse, ResponseHead};
 use super::result::ExecutionResult;
 
 /// What to do with the PHP output.
@@ -11,6 +25,18 @@ pub enum OutputAction {
     Done,
 }
 
+/// Whether to run the request or answer it without starting PHP.
+#[derive(Debug)]
+#[non_exhaustive]
+// Returned once per request; boxing would only add an allocation.
+#[allow(clippy::large_enum_variant)]
+pub enum RequestAction {
+    Continue,
+    /// Skip PHP entirely and return this result (auth rejection,
+    /// maintenance mode, cached responses, ...).
+    Respond(ExecutionResult),
+}
+
 pub struct NoOpHooks;
 
 impl ExecutionHooks for NoOpHooks {}
@@ -45,6 +71,19 @@ where
 /// Default implementations allow for selective participation.
 /// Method names are purposefully self-explanatory/self-documenting.
 pub trait ExecutionHooks {
+    /// Called with the request before PHP sees it.
+    ///
+    /// Hooks may add server vars, env or INI overrides, rewrite the script
+    /// path, or short-circuit with [`RequestAction::Respond`].
+    fn on_request_received(
+        &mut self,
+        ctx: &mut ExecutionContext,
+    ) -> RequestAction {
+        let _ = ctx;
+
+        RequestAction::Continue
+    }
+
     /// Called after server context is created.
     fn on_context_created(&mut self) {}
     fn on_request_starting(&mut self) {}
@@ -70,6 +109,20 @@ pub trait ExecutionHooks {
     /// Called when PHP flushes output.
     fn on_flush(&mut self) {}
 
+    /// Called when PHP sends the response head, before any body output.
+    ///
+    /// Rewrite header values or override the status here; streamed responses
+    /// see the result too.
+    fn on_response_head(&mut self, head: &mut ResponseHead) {
+        let _ = head;
+    }
+
+    /// Called when PHP sends a 1xx header block (e.g. `103 Early Hints`),
+    /// as it happens, ahead of the final response.
+    fn on_informational(&mut self, response: &InformationalResponse) {
+        let _ = response;
+    }
+
     /// Called for each response header. Return false to suppress the header.
     fn on_header(&mut self, name: &str, value: &str) -> bool {
         let _ = (name, value);
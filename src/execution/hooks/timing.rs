@@ -0,0 +1,130 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use super::ExecutionHooks;
+use crate::execution::ExecutionResult;
+
+/// Phase durations of the last request seen by a [`TimingRecorder`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RequestTimings {
+    /// Context creation until PHP request startup completed.
+    pub startup: Duration,
+    /// Script execution.
+    pub script: Duration,
+    /// Script end until the result was built (request shutdown included).
+    pub shutdown: Duration,
+    /// Context creation until the result was built.
+    pub total: Duration,
+}
+
+#[derive(Debug, Default)]
+struct Marks {
+    created: Option<Instant>,
+    started: Option<Instant>,
+    executing: Option<Instant>,
+    executed: Option<Instant>,
+    timings: Option<RequestTimings>,
+}
+
+/// Records how long each request phase took.
+///
+/// Clones share the same recording, so keep one clone and pass the other to
+/// the executor:
+///
+/// ```no_run
+/// use ripht_php_sapi::{RiphtSapi, TimingRecorder, WebRequest};
+///
+/// let timing = TimingRecorder::new();
+/// let ctx = WebRequest::get().build("/var/www/index.php").expect("build");
+///
+/// RiphtSapi::instance()
+///     .execute_with_hooks(ctx, timing.clone())
+///     .expect("execute");
+///
+/// println!("{:?}", timing.last());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TimingRecorder {
+    marks: Arc<Mutex<Marks>>,
+}
+
+impl TimingRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Timings of the most recently finished request.
+    pub fn last(&self) -> Option<RequestTimings> {
+        self.marks().timings
+    }
+
+    fn marks(&self) -> MutexGuard<'_, Marks> {
+        self.marks
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl ExecutionHooks for TimingRecorder {
+    fn on_context_created(&mut self) {
+        *self.marks() = Marks {
+            created: Some(Instant::now()),
+            ..Marks::default()
+        };
+    }
+
+    fn on_request_started(&mut self) {
+        self.marks().started = Some(Instant::now());
+    }
+
+    fn on_script_executing(&mut self, _script_path: &std::path::Path) {
+        self.marks().executing = Some(Instant::now());
+    }
+
+    fn on_script_executed(&mut self, _success: bool) {
+        self.marks().executed = Some(Instant::now());
+    }
+
+    fn on_request_finished(&mut self, _result: &ExecutionResult) {
+        let now = Instant::now();
+        let mut marks = self.marks();
+
+        let since =
+            |from: Option<Instant>, to: Option<Instant>| match (from, to) {
+                (Some(from), Some(to)) => to.saturating_duration_since(from),
+                _ => Duration::ZERO,
+            };
+
+        marks.timings = Some(RequestTimings {
+            startup: since(marks.created, marks.started),
+            script: since(marks.executing, marks.executed),
+            shutdown: since(marks.executed, Some(now)),
+            total: since(marks.created, Some(now)),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clones_share_timings() {
+        let timing = TimingRecorder::new();
+        let mut hooks = timing.clone();
+
+        assert!(timing.last().is_none());
+
+        hooks.on_context_created();
+        hooks.on_request_started();
+        hooks.on_script_executing(std::path::Path::new("/a.php"));
+        hooks.on_script_executed(true);
+        hooks.on_request_finished(&ExecutionResult::default());
+
+        let timings = timing
+            .last()
+            .expect("timings recorded");
+        assert!(timings.total >= timings.script);
+    }
+}
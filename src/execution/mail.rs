@@ -0,0 +1,120 @@
+/// A message a script sent with `mail()`, captured instead of being handed
+/// to `sendmail_path`.
+///
+/// See `EngineBuilder::with_mail_sink`. Header names are matched
+/// case-insensitively.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MailMessage {
+    to: Vec<String>,
+    subject: String,
+    headers: Vec<(String, String)>,
+    body: String,
+    additional_params: Option<String>,
+}
+
+impl MailMessage {
+    /// `to` is split on commas, like PHP's recipient list.
+    pub fn new(
+        to: &str,
+        subject: impl Into<String>,
+        body: impl Into<String>,
+    ) -> Self {
+        Self {
+            to: to
+                .split(',')
+                .map(str::trim)
+                .filter(|addr| !addr.is_empty())
+                .map(String::from)
+                .collect(),
+            subject: subject.into(),
+            body: body.into(),
+            ..Self::default()
+        }
+    }
+
+    #[must_use]
+    pub fn with_header(
+        mut self,
+        name: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Self {
+        self.headers
+            .push((name.into(), value.into()));
+        self
+    }
+
+    /// Adds headers from a raw `Name: value` block as passed to `mail()`.
+    /// Folded continuation lines are joined to the previous header.
+    #[must_use]
+    pub fn with_raw_headers(mut self, raw: &str) -> Self {
+        for line in raw.lines() {
+            if line.starts_with([' ', '\t']) {
+                if let Some((_, value)) = self.headers.last_mut() {
+                    value.push(' ');
+                    value.push_str(line.trim());
+                }
+                continue;
+            }
+
+            if let Some((name, value)) = line.split_once(':') {
+                self = self.with_header(name.trim(), value.trim());
+            }
+        }
+        self
+    }
+
+    /// Extra sendmail flags (`mail()`'s fifth argument).
+    #[must_use]
+    pub fn with_additional_params(mut self, params: impl Into<String>) -> Self {
+        self.additional_params = Some(params.into());
+        self
+    }
+
+    pub fn to(&self) -> &[String] {
+        &self.to
+    }
+
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    /// First value of header `name`.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    pub fn additional_params(&self) -> Option<&str> {
+        self.additional_params
+            .as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recipients_and_raw_headers() {
+        let message =
+            MailMessage::new("a@example.com, b@example.com", "Hi", "")
+                .with_raw_headers(
+                "From: app@example.com\r\nX-Tags: one,\r\n two\r\nbogus\r\n",
+            );
+
+        assert_eq!(message.to(), ["a@example.com", "b@example.com"]);
+        assert_eq!(message.header("from"), Some("app@example.com"));
+        assert_eq!(message.header("X-Tags"), Some("one, two"));
+        assert_eq!(message.headers().len(), 2);
+    }
+}
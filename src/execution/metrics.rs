@@ -0,0 +1,30 @@
+use std::time::Duration;
+
+/// Where a request's time and output went, measured by the executor.
+///
+/// Unlike [`TimingRecorder`](super::TimingRecorder) this needs no hooks and
+/// splits out the PHP request startup and shutdown calls. All zero for
+/// results that didn't come from PHP (e.g. answered by a hook).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ExecutionMetrics {
+    /// Turning the `ExecutionContext` into the SAPI's server context.
+    pub context: Duration,
+    /// `php_request_startup` plus per-request INI overrides.
+    pub startup: Duration,
+    /// Script execution.
+    pub script: Duration,
+    /// Inside the `send_headers` callback. Overlaps `script` (or `shutdown`
+    /// when the script produced no output).
+    pub send_headers: Duration,
+    /// `php_request_shutdown`: shutdown functions, destructors, output flush.
+    pub shutdown: Duration,
+    /// From the executor receiving the request until the result was built.
+    pub total: Duration,
+    /// Bytes PHP wrote and the SAPI accepted, buffered or streamed.
+    pub output_bytes: usize,
+    /// Bytes in the result body (zero when streaming).
+    pub body_bytes: usize,
+    /// Bytes in the final response header names and values.
+    pub header_bytes: usize,
+}
@@ -1,11 +1,27 @@
+mod buffer;
 mod context;
 mod header;
 mod hooks;
+mod mail;
 mod message;
+mod metrics;
+mod response_head;
 mod result;
+mod security;
+mod status_line;
 
+pub use buffer::{BufferGrowth, BufferPolicy, OverflowAction};
 pub use context::ExecutionContext;
 pub use header::ResponseHeader;
-pub use hooks::{ExecutionHooks, NoOpHooks, OutputAction, StreamingCallback};
+pub use hooks::{
+    ExecutionHooks, HeaderPolicy, HookChain, MessageCollector, NoOpHooks,
+    OutputAction, RequestAction, RequestTimings, StreamingCallback,
+    TimingRecorder,
+};
+pub use mail::MailMessage;
 pub use message::{ExecutionMessage, SyslogLevel};
-pub use result::ExecutionResult;
+pub use metrics::ExecutionMetrics;
+pub use response_head::{InformationalResponse, ResponseHead};
+pub use result::{ExecutionResult, MemoryUsage};
+pub use security::{SecurityPolicy, SecurityViolation};
+pub use status_line::StatusLine;
@@ -0,0 +1,177 @@
+use super::header::ResponseHeader;
+
+/// Status line and headers of a response, as they are about to be sent.
+///
+/// Handed to [`ExecutionHooks::on_response_head`](super::ExecutionHooks::on_response_head)
+/// when PHP sends its headers. Header names are matched case-insensitively.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseHead {
+    status: u16,
+    headers: Vec<ResponseHeader>,
+}
+
+impl ResponseHead {
+    pub fn new(status: u16, headers: Vec<ResponseHeader>) -> Self {
+        Self { status, headers }
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    pub fn set_status(&mut self, code: u16) {
+        self.status = code;
+    }
+
+    pub fn headers(&self) -> &[ResponseHeader] {
+        &self.headers
+    }
+
+    /// First value of `name`.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|h| {
+                h.name()
+                    .eq_ignore_ascii_case(name)
+            })
+            .map(|h| h.value())
+    }
+
+    /// Replaces every `name` header with a single one.
+    pub fn set_header(
+        &mut self,
+        name: impl Into<String>,
+        value: impl Into<String>,
+    ) {
+        let header = ResponseHeader::new(name, value);
+
+        self.remove_header(header.name());
+        self.headers.push(header);
+    }
+
+    /// Adds a header, keeping existing ones with the same name.
+    pub fn append_header(
+        &mut self,
+        name: impl Into<String>,
+        value: impl Into<String>,
+    ) {
+        self.headers
+            .push(ResponseHeader::new(name, value));
+    }
+
+    /// Removes every `name` header, returning whether any existed.
+    pub fn remove_header(&mut self, name: &str) -> bool {
+        let before = self.headers.len();
+
+        self.headers.retain(|h| {
+            !h.name()
+                .eq_ignore_ascii_case(name)
+        });
+
+        self.headers.len() != before
+    }
+
+    pub(crate) fn into_parts(self) -> (u16, Vec<ResponseHeader>) {
+        (self.status, self.headers)
+    }
+}
+
+/// A 1xx header block sent ahead of the final response, e.g. `103 Early Hints`.
+///
+/// PHP sends one when the script flushes headers while the response code is
+/// informational:
+///
+/// ```php
+/// http_response_code(103);
+/// header('Link: </app.css>; rel=preload; as=style');
+/// flush();
+///
+/// http_response_code(200);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InformationalResponse {
+    status: u16,
+    headers: Vec<ResponseHeader>,
+}
+
+impl InformationalResponse {
+    pub fn new(status: u16, headers: Vec<ResponseHeader>) -> Self {
+        Self { status, headers }
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    pub fn headers(&self) -> &[ResponseHeader] {
+        &self.headers
+    }
+
+    /// Every value of `name`, e.g. all `Link` preload hints.
+    pub fn header_values<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> impl Iterator<Item = &'a str> + 'a {
+        self.headers
+            .iter()
+            .filter(move |h| {
+                h.name()
+                    .eq_ignore_ascii_case(name)
+            })
+            .map(|h| h.value())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_header_replaces_all() {
+        let mut head = ResponseHead::new(
+            200,
+            vec![
+                ResponseHeader::new("Set-Cookie", "a=1"),
+                ResponseHeader::new("set-cookie", "b=2"),
+            ],
+        );
+
+        head.set_header("Set-Cookie", "c=3");
+
+        assert_eq!(head.headers().len(), 1);
+        assert_eq!(head.header("SET-COOKIE"), Some("c=3"));
+    }
+
+    #[test]
+    fn test_informational_header_values() {
+        let hints = InformationalResponse::new(
+            103,
+            vec![
+                ResponseHeader::new("Link", "</a.css>; rel=preload"),
+                ResponseHeader::new("link", "</b.js>; rel=preload"),
+            ],
+        );
+
+        assert_eq!(
+            hints
+                .header_values("LINK")
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_append_and_remove() {
+        let mut head = ResponseHead::new(302, Vec::new());
+
+        head.append_header("Vary", "Accept");
+        head.append_header("Vary", "Cookie");
+        head.set_status(307);
+
+        assert_eq!(head.headers().len(), 2);
+        assert!(head.remove_header("vary"));
+        assert!(!head.remove_header("vary"));
+        assert_eq!(head.status(), 307);
+    }
+}
@@ -1,5 +1,33 @@
 use super::header::ResponseHeader;
+use super::mail::MailMessage;
 use super::message::{ExecutionMessage, SyslogLevel};
+use super::metrics::ExecutionMetrics;
+use super::response_head::InformationalResponse;
+use super::security::SecurityViolation;
+use super::status_line::StatusLine;
+
+/// Body storage: reference-counted with the `bytes` feature, so clones and
+/// conversions into `Bytes` don't copy.
+#[cfg(feature = "bytes")]
+type Body = bytes::Bytes;
+#[cfg(not(feature = "bytes"))]
+type Body = Vec<u8>;
+
+/// Zend memory manager usage at the end of a request, in bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MemoryUsage {
+    /// Memory still allocated when the script finished.
+    pub current: usize,
+    /// Highest usage during the request (`memory_get_peak_usage()`).
+    pub peak: usize,
+}
+
+impl MemoryUsage {
+    pub fn new(current: usize, peak: usize) -> Self {
+        Self { current, peak }
+    }
+}
 
 /// Result of PHP script execution.
 ///
@@ -9,12 +37,20 @@ use super::message::{ExecutionMessage, SyslogLevel};
 #[derive(Debug, Clone)]
 pub struct ExecutionResult {
     status: u16,
-    body: Vec<u8>,
+    body: Body,
     headers: Vec<ResponseHeader>,
     messages: Vec<ExecutionMessage>,
+    truncated: bool,
+    status_line: Option<StatusLine>,
+    informational: Vec<InformationalResponse>,
+    security_violations: Vec<SecurityViolation>,
+    memory_usage: MemoryUsage,
+    metrics: ExecutionMetrics,
+    mail: Vec<MailMessage>,
 }
 
 impl ExecutionResult {
+    #[cfg_attr(not(feature = "bytes"), allow(clippy::useless_conversion))]
     pub fn new(
         status: u16,
         body: Vec<u8>,
@@ -23,18 +59,116 @@ impl ExecutionResult {
     ) -> Self {
         Self {
             status,
-            body,
+            body: body.into(),
             headers,
             messages,
+            truncated: false,
+            status_line: None,
+            informational: Vec::new(),
+            security_violations: Vec::new(),
+            memory_usage: MemoryUsage::default(),
+            metrics: ExecutionMetrics::default(),
+            mail: Vec::new(),
+        }
+    }
+
+    /// Attaches the status line the script sent; ignored if its code
+    /// doesn't match the result's status.
+    pub fn with_status_line(mut self, status_line: StatusLine) -> Self {
+        if status_line.code() == self.status {
+            self.status_line = Some(status_line);
+        }
+        self
+    }
+
+    pub(crate) fn with_sent_status_line(
+        self,
+        status_line: Option<StatusLine>,
+    ) -> Self {
+        match status_line {
+            Some(line) => self.with_status_line(line),
+            None => self,
         }
     }
 
+    pub(crate) fn with_informational(
+        mut self,
+        informational: Vec<InformationalResponse>,
+    ) -> Self {
+        self.informational = informational;
+        self
+    }
+
+    /// 1xx header blocks sent before the final response, in order.
+    pub fn informational(&self) -> &[InformationalResponse] {
+        &self.informational
+    }
+
+    pub(crate) fn with_security_violations(
+        mut self,
+        violations: Vec<SecurityViolation>,
+    ) -> Self {
+        self.security_violations = violations;
+        self
+    }
+
+    /// Operations the security policy blocked, in order.
+    pub fn security_violations(&self) -> &[SecurityViolation] {
+        &self.security_violations
+    }
+
+    pub(crate) fn with_memory_usage(mut self, usage: MemoryUsage) -> Self {
+        self.memory_usage = usage;
+        self
+    }
+
+    /// Memory the request used; zero for results not produced by PHP.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.memory_usage
+    }
+
+    pub(crate) fn with_metrics(mut self, metrics: ExecutionMetrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Per-phase durations and byte counts of the request.
+    pub fn metrics(&self) -> &ExecutionMetrics {
+        &self.metrics
+    }
+
+    pub(crate) fn with_mail(mut self, mail: Vec<MailMessage>) -> Self {
+        self.mail = mail;
+        self
+    }
+
+    /// Messages the script sent with `mail()` to the engine's mail sink.
+    pub fn mail(&self) -> &[MailMessage] {
+        &self.mail
+    }
+
+    pub(crate) fn with_truncated(mut self, truncated: bool) -> Self {
+        self.truncated = truncated;
+        self
+    }
+
+    /// Whether the body was cut off at the buffer policy's size limit.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
     pub fn body(&self) -> Vec<u8> {
-        self.body.to_owned()
+        self.body.to_vec()
     }
 
+    /// Borrows the body without copying it.
+    pub fn body_bytes(&self) -> &[u8] {
+        &self.body
+    }
+
+    #[cfg_attr(not(feature = "bytes"), allow(clippy::useless_conversion))]
     pub fn take_body(&mut self) -> Vec<u8> {
-        std::mem::take(&mut self.body)
+        std::mem::take(&mut self.body).into()
     }
 
     pub fn body_string(&self) -> String {
@@ -53,6 +187,19 @@ impl ExecutionResult {
         self.status
     }
 
+    /// Status line sent via `header("HTTP/1.1 ...")`, if the script sent one.
+    pub fn status_line(&self) -> Option<&StatusLine> {
+        self.status_line.as_ref()
+    }
+
+    /// Custom reason phrase from the status line, if any.
+    pub fn reason_phrase(&self) -> Option<&str> {
+        self.status_line
+            .as_ref()
+            .map(StatusLine::reason)
+            .filter(|reason| !reason.is_empty())
+    }
+
     pub fn has_errors(&self) -> bool {
         self.messages
             .iter()
@@ -124,25 +271,69 @@ impl Default for ExecutionResult {
     fn default() -> Self {
         Self {
             status: 200,
-            body: Vec::new(),
+            body: Body::new(),
             headers: Vec::new(),
             messages: Vec::new(),
+            truncated: false,
+            status_line: None,
+            informational: Vec::new(),
+            security_violations: Vec::new(),
+            memory_usage: MemoryUsage::default(),
+            metrics: ExecutionMetrics::default(),
+            mail: Vec::new(),
         }
     }
 }
 
+#[cfg(feature = "bytes")]
+impl ExecutionResult {
+    /// Shares the body; the clone is a reference count bump.
+    pub fn body_shared(&self) -> bytes::Bytes {
+        self.body.clone()
+    }
+
+    /// Takes the body as `Bytes` without copying.
+    pub fn take_body_bytes(&mut self) -> bytes::Bytes {
+        std::mem::take(&mut self.body)
+    }
+}
+
 #[cfg(feature = "http")]
 impl ExecutionResult {
     pub fn into_http_response(self) -> http::Response<Vec<u8>> {
+        self.into_http_response_with(Vec::new())
+    }
+
+    /// Builds an `http::Response<Bytes>` around the shared body, ready for
+    /// hyper and friends without another copy.
+    #[cfg(feature = "bytes")]
+    pub fn into_http_response_bytes(self) -> http::Response<bytes::Bytes> {
+        self.into_http_response_with(bytes::Bytes::new())
+    }
+
+    /// The script's [`StatusLine`], if any, sets the response version and is
+    /// stored in the response extensions so proxies can reproduce the
+    /// reason phrase.
+    fn into_http_response_with<B>(self, empty: B) -> http::Response<B>
+    where
+        Body: Into<B>,
+    {
         let mut builder = http::Response::builder().status(self.status);
 
         for h in &self.headers {
             builder = builder.header(h.name(), h.value());
         }
 
+        if let Some(status_line) = self.status_line {
+            if let Some(version) = status_line.http_version() {
+                builder = builder.version(version);
+            }
+            builder = builder.extension(status_line);
+        }
+
         builder
-            .body(self.body)
-            .unwrap_or_else(|_| http::Response::new(Vec::new()))
+            .body(self.body.into())
+            .unwrap_or_else(|_| http::Response::new(empty))
     }
 }
 
@@ -152,3 +343,54 @@ impl From<ExecutionResult> for http::Response<Vec<u8>> {
         res.into_http_response()
     }
 }
+
+#[cfg(all(feature = "http", feature = "bytes"))]
+impl From<ExecutionResult> for http::Response<bytes::Bytes> {
+    fn from(res: ExecutionResult) -> Self {
+        res.into_http_response_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result() -> ExecutionResult {
+        ExecutionResult::new(
+            200,
+            b"hello".to_vec(),
+            vec![ResponseHeader::new("Content-Type", "text/plain")],
+            Vec::new(),
+        )
+    }
+
+    #[test]
+    fn test_body_bytes_borrows() {
+        let mut result = result();
+
+        assert_eq!(result.body_bytes(), b"hello");
+        assert_eq!(result.take_body(), b"hello");
+        assert!(result.body_bytes().is_empty());
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn test_shared_body_is_not_copied() {
+        let result = result();
+        let shared = result.body_shared();
+
+        assert_eq!(shared.as_ptr(), result.body_bytes().as_ptr());
+    }
+
+    #[cfg(all(feature = "http", feature = "bytes"))]
+    #[test]
+    fn test_http_response_bytes() {
+        let result = result();
+        let ptr = result.body_bytes().as_ptr();
+
+        let response: http::Response<bytes::Bytes> = result.into();
+
+        assert_eq!(response.body().as_ptr(), ptr);
+        assert_eq!(response.headers()["content-type"], "text/plain");
+    }
+}
@@ -0,0 +1,402 @@
+//! Per-request hardening: filesystem roots, disabled functions and classes,
+//! remote URL access and upload size.
+
+use std::fmt;
+use std::path::PathBuf;
+
+#[cfg(windows)]
+const PATH_SEPARATOR: &str = ";";
+#[cfg(not(windows))]
+const PATH_SEPARATOR: &str = ":";
+
+/// Functions calls never reach `zend_execute_internal` for: the ones the
+/// compiler turns into opcodes, and PHP 8.4's frameless functions.
+const UNINTERCEPTABLE_FUNCTIONS: &[&str] = &[
+    // Compiled to opcodes.
+    "array_key_exists",
+    "array_slice",
+    "boolval",
+    "call_user_func",
+    "call_user_func_array",
+    "chr",
+    "count",
+    "defined",
+    "doubleval",
+    "floatval",
+    "func_get_args",
+    "func_num_args",
+    "get_called_class",
+    "get_class",
+    "gettype",
+    "in_array",
+    "intval",
+    "is_array",
+    "is_bool",
+    "is_double",
+    "is_float",
+    "is_int",
+    "is_integer",
+    "is_long",
+    "is_null",
+    "is_object",
+    "is_resource",
+    "is_scalar",
+    "is_string",
+    "ord",
+    "sizeof",
+    "sprintf",
+    "strlen",
+    "strval",
+    // Frameless (PHP 8.4).
+    "basename",
+    "class_exists",
+    "dirname",
+    "implode",
+    "ltrim",
+    "max",
+    "min",
+    "preg_match",
+    "preg_replace",
+    "property_exists",
+    "rtrim",
+    "str_contains",
+    "str_replace",
+    "str_starts_with",
+    "strpos",
+    "strtr",
+    "substr",
+    "trim",
+];
+
+/// Restrictions applied to a request.
+///
+/// Set engine-wide with `EngineBuilder::with_security_policy` or per request
+/// with [`ExecutionContext::security_policy`](super::ExecutionContext::security_policy);
+/// a request's own policy replaces the engine's. Directives PHP only reads at
+/// startup (`disable_functions`, `allow_url_fopen`, ...) are enforced per
+/// request by the SAPI, and INI overrides on the context can't loosen them.
+///
+/// Functions PHP calls without going through the SAPI (`strlen`, `count`,
+/// `in_array`, `str_replace`, ...) can't be disabled; a policy naming one is
+/// rejected when the engine starts or the request executes.
+///
+/// ```
+/// use ripht_php_sapi::SecurityPolicy;
+///
+/// let policy = SecurityPolicy::new()
+///     .with_open_basedir("/srv/tenant-a")
+///     .with_disabled_functions(["exec", "shell_exec", "proc_open"])
+///     .with_disabled_class("ReflectionClass")
+///     .with_allow_url_fopen(false)
+///     .with_max_upload_size(2 * 1024 * 1024);
+///
+/// assert!(policy.disables_function("EXEC"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SecurityPolicy {
+    open_basedir: Vec<PathBuf>,
+    disabled_functions: Vec<String>,
+    disabled_classes: Vec<String>,
+    allow_url_fopen: Option<bool>,
+    allow_url_include: Option<bool>,
+    max_upload_size: Option<u64>,
+}
+
+impl SecurityPolicy {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a directory scripts may access files under (`open_basedir`).
+    #[must_use]
+    pub fn with_open_basedir(mut self, root: impl Into<PathBuf>) -> Self {
+        self.open_basedir
+            .push(root.into());
+        self
+    }
+
+    #[must_use]
+    pub fn with_disabled_function(mut self, name: impl Into<String>) -> Self {
+        self.disabled_functions
+            .push(name.into());
+        self
+    }
+
+    #[must_use]
+    pub fn with_disabled_functions<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.disabled_functions
+            .extend(
+                names
+                    .into_iter()
+                    .map(Into::into),
+            );
+        self
+    }
+
+    /// Blocks instantiating an internal class (subclasses included) and
+    /// calling its methods.
+    #[must_use]
+    pub fn with_disabled_class(mut self, name: impl Into<String>) -> Self {
+        self.disabled_classes
+            .push(name.into());
+        self
+    }
+
+    #[must_use]
+    pub fn with_allow_url_fopen(mut self, allow: bool) -> Self {
+        self.allow_url_fopen = Some(allow);
+        self
+    }
+
+    #[must_use]
+    pub fn with_allow_url_include(mut self, allow: bool) -> Self {
+        self.allow_url_include = Some(allow);
+        self
+    }
+
+    /// Largest accepted uploaded file (`upload_max_filesize`), in bytes.
+    #[must_use]
+    pub fn with_max_upload_size(mut self, bytes: u64) -> Self {
+        self.max_upload_size = Some(bytes);
+        self
+    }
+
+    pub fn open_basedir(&self) -> &[PathBuf] {
+        &self.open_basedir
+    }
+
+    pub fn disabled_functions(&self) -> &[String] {
+        &self.disabled_functions
+    }
+
+    pub fn disabled_classes(&self) -> &[String] {
+        &self.disabled_classes
+    }
+
+    pub fn allow_url_fopen(&self) -> Option<bool> {
+        self.allow_url_fopen
+    }
+
+    pub fn allow_url_include(&self) -> Option<bool> {
+        self.allow_url_include
+    }
+
+    pub fn max_upload_size(&self) -> Option<u64> {
+        self.max_upload_size
+    }
+
+    /// Whether calling function `name` is blocked (case-insensitive).
+    pub fn disables_function(&self, name: &str) -> bool {
+        self.disabled_functions
+            .iter()
+            .any(|f| f.eq_ignore_ascii_case(name))
+    }
+
+    /// Whether calls into class `name` are blocked (case-insensitive).
+    pub fn disables_class(&self, name: &str) -> bool {
+        let name = name.trim_start_matches('\\');
+
+        self.disabled_classes
+            .iter()
+            .any(|c| {
+                c.trim_start_matches('\\')
+                    .eq_ignore_ascii_case(name)
+            })
+    }
+
+    pub(crate) fn disables_calls(&self) -> bool {
+        !self
+            .disabled_functions
+            .is_empty()
+            || !self
+                .disabled_classes
+                .is_empty()
+    }
+
+    /// First disabled function the SAPI can't intercept, if any.
+    pub(crate) fn uninterceptable_function(&self) -> Option<&str> {
+        self.disabled_functions
+            .iter()
+            .map(|name| name.trim_start_matches('\\'))
+            .find(|name| {
+                UNINTERCEPTABLE_FUNCTIONS
+                    .iter()
+                    .any(|f| f.eq_ignore_ascii_case(name))
+            })
+    }
+
+    /// INI directives this policy sets, applied at request activation.
+    pub(crate) fn ini_entries(&self) -> Vec<(&'static str, String)> {
+        let flag = |on: bool| if on { "1" } else { "0" }.to_string();
+        let mut entries = Vec::new();
+
+        if !self.open_basedir.is_empty() {
+            let roots: Vec<_> = self
+                .open_basedir
+                .iter()
+                .map(|root| root.to_string_lossy())
+                .collect();
+            entries.push(("open_basedir", roots.join(PATH_SEPARATOR)));
+        }
+        if let Some(allow) = self.allow_url_fopen {
+            entries.push(("allow_url_fopen", flag(allow)));
+        }
+        if let Some(allow) = self.allow_url_include {
+            entries.push(("allow_url_include", flag(allow)));
+        }
+        if let Some(bytes) = self.max_upload_size {
+            entries.push(("upload_max_filesize", bytes.to_string()));
+        }
+
+        entries
+    }
+
+    /// Whether INI directive `key` is controlled by this policy.
+    pub(crate) fn governs(&self, key: &str) -> bool {
+        self.ini_entries()
+            .iter()
+            .any(|(directive, _)| directive.eq_ignore_ascii_case(key))
+    }
+}
+
+/// A blocked operation, reported in `ExecutionResult::security_violations`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SecurityViolation {
+    /// Call to a function the policy disables.
+    DisabledFunction { name: String },
+    /// Call into a class the policy disables.
+    DisabledClass { name: String },
+    /// File access outside the `open_basedir` roots.
+    OpenBasedir { path: String },
+    /// Remote access refused by `allow_url_fopen` / `allow_url_include`.
+    UrlAccess { wrapper: String, directive: String },
+}
+
+impl SecurityViolation {
+    /// Recognizes PHP's own warnings for `open_basedir` and URL access.
+    pub(crate) fn from_php_message(message: &str) -> Option<Self> {
+        if let Some((_, rest)) =
+            message.split_once("open_basedir restriction in effect. File(")
+        {
+            let (path, _) = rest.split_once(") is not within")?;
+
+            return Some(Self::OpenBasedir {
+                path: path.to_string(),
+            });
+        }
+
+        let (before, after) = message.split_once(
+            " wrapper is disabled in the server configuration by ",
+        )?;
+        let wrapper = before
+            .rsplit(' ')
+            .next()?
+            .trim_end_matches("://");
+        let directive = after.split('=').next()?;
+
+        Some(Self::UrlAccess {
+            wrapper: wrapper.to_string(),
+            directive: directive.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for SecurityViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DisabledFunction { name } => {
+                write!(f, "{name}() has been disabled by the security policy")
+            }
+            Self::DisabledClass { name } => {
+                write!(
+                    f,
+                    "Class {name} has been disabled by the security policy"
+                )
+            }
+            Self::OpenBasedir { path } => {
+                write!(f, "File {path} is outside the open_basedir roots")
+            }
+            Self::UrlAccess { wrapper, directive } => {
+                write!(f, "{wrapper}:// access refused by {directive}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ini_entries() {
+        let policy = SecurityPolicy::new()
+            .with_open_basedir("/srv/a")
+            .with_open_basedir("/tmp")
+            .with_allow_url_include(false)
+            .with_max_upload_size(1024);
+
+        assert_eq!(
+            policy.ini_entries(),
+            [
+                ("open_basedir", format!("/srv/a{PATH_SEPARATOR}/tmp")),
+                ("allow_url_include", "0".to_string()),
+                ("upload_max_filesize", "1024".to_string()),
+            ]
+        );
+        assert!(policy.governs("OPEN_BASEDIR"));
+        assert!(!policy.governs("allow_url_fopen"));
+    }
+
+    #[test]
+    fn test_disabled_names_are_case_insensitive() {
+        let policy = SecurityPolicy::new()
+            .with_disabled_function("Exec")
+            .with_disabled_class("\\ReflectionClass");
+
+        assert!(policy.disables_function("exec"));
+        assert!(policy.disables_class("reflectionclass"));
+        assert!(!policy.disables_class("ReflectionMethod"));
+    }
+
+    #[test]
+    fn test_uninterceptable_functions() {
+        let policy = SecurityPolicy::new().with_disabled_functions(["exec"]);
+        assert_eq!(policy.uninterceptable_function(), None);
+
+        let policy = policy.with_disabled_function("\\In_Array");
+        assert_eq!(policy.uninterceptable_function(), Some("In_Array"));
+    }
+
+    #[test]
+    fn test_violations_from_php_warnings() {
+        assert_eq!(
+            SecurityViolation::from_php_message(
+                "PHP Warning:  file_get_contents(): open_basedir restriction in effect. \
+                 File(/etc/passwd) is not within the allowed path(s): (/srv) in /srv/a.php on line 2"
+            ),
+            Some(SecurityViolation::OpenBasedir {
+                path: "/etc/passwd".to_string()
+            })
+        );
+        assert_eq!(
+            SecurityViolation::from_php_message(
+                "PHP Warning:  include(): http:// wrapper is disabled in the server \
+                 configuration by allow_url_include=0 in /srv/a.php on line 3"
+            ),
+            Some(SecurityViolation::UrlAccess {
+                wrapper: "http".to_string(),
+                directive: "allow_url_include".to_string()
+            })
+        );
+        assert_eq!(
+            SecurityViolation::from_php_message("PHP Notice:  Undefined index"),
+            None
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use std::fmt;
+
+/// HTTP status line sent by the script, e.g. `header("HTTP/1.1 418 I'm a teapot")`.
+///
+/// Keeps the protocol version and reason phrase that `http_response_code`
+/// alone loses, so proxies can reproduce PHP's exact response line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusLine {
+    version: String,
+    code: u16,
+    reason: String,
+}
+
+impl StatusLine {
+    pub fn new(
+        version: impl Into<String>,
+        code: u16,
+        reason: impl Into<String>,
+    ) -> Self {
+        Self {
+            version: version.into(),
+            code,
+            reason: reason.into(),
+        }
+    }
+
+    /// Parses `HTTP/<version> <code> [reason]`.
+    pub fn parse(line: &str) -> Option<Self> {
+        let line = line.trim();
+
+        if !line
+            .get(..5)?
+            .eq_ignore_ascii_case("HTTP/")
+        {
+            return None;
+        }
+
+        let (version, rest) = line.split_once(' ')?;
+        let rest = rest.trim_start();
+        let (code, reason) = rest
+            .split_once(' ')
+            .unwrap_or((rest, ""));
+
+        if code.len() != 3
+            || !code
+                .bytes()
+                .all(|b| b.is_ascii_digit())
+        {
+            return None;
+        }
+
+        Some(Self {
+            version: version.to_string(),
+            code: code.parse().ok()?,
+            reason: reason.trim().to_string(),
+        })
+    }
+
+    /// Protocol version as sent, e.g. `HTTP/1.1`.
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn code(&self) -> u16 {
+        self.code
+    }
+
+    /// Reason phrase as sent; empty when the script omitted it.
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+
+    #[cfg(feature = "http")]
+    pub fn http_version(&self) -> Option<http::Version> {
+        match self
+            .version
+            .to_ascii_uppercase()
+            .as_str()
+        {
+            "HTTP/0.9" => Some(http::Version::HTTP_09),
+            "HTTP/1.0" => Some(http::Version::HTTP_10),
+            "HTTP/1.1" => Some(http::Version::HTTP_11),
+            "HTTP/2" | "HTTP/2.0" => Some(http::Version::HTTP_2),
+            "HTTP/3" | "HTTP/3.0" => Some(http::Version::HTTP_3),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for StatusLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.version, self.code)?;
+
+        if !self.reason.is_empty() {
+            write!(f, " {}", self.reason)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_custom_reason() {
+        let line = StatusLine::parse("HTTP/1.1 418 I'm a teapot").unwrap();
+
+        assert_eq!(line.version(), "HTTP/1.1");
+        assert_eq!(line.code(), 418);
+        assert_eq!(line.reason(), "I'm a teapot");
+        assert_eq!(line.to_string(), "HTTP/1.1 418 I'm a teapot");
+    }
+
+    #[test]
+    fn test_parse_without_reason() {
+        let line = StatusLine::parse("HTTP/1.0 204").unwrap();
+
+        assert_eq!(line.code(), 204);
+        assert_eq!(line.reason(), "");
+        assert_eq!(line.to_string(), "HTTP/1.0 204");
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed() {
+        assert!(StatusLine::parse("Status: 200").is_none());
+        assert!(StatusLine::parse("HTTP/1.1 2000 Nope").is_none());
+        assert!(StatusLine::parse("HTTP/1.1").is_none());
+    }
+}
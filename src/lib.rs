@@ -37,19 +37,42 @@ pub use adapters::{
     WebRequest, WebRequestError,
 };
 
-pub use sapi::{ExecutionError, Executor, RiphtSapi, SapiError};
+pub use sapi::{
+    BodyParser, EngineBuilder, EngineState, ExecutionError, Executor, HttpCall,
+    HttpMock, InputAction, InputFilter, InputSource, MailCollector, MailSink,
+    MemoryFs, MockResponse, PhpThread, PoolStats, PostValue, RiphtSapi,
+    SapiError, StreamStat, StreamWrapper, VirtualFs, WrapperStream,
+};
+
+#[cfg(feature = "serde")]
+pub use sapi::JsonBodyParser;
+
+#[cfg(php_zts)]
+pub use sapi::ExecutorPool;
+
+#[cfg(unix)]
+pub use sapi::PreforkExecutor;
+
+#[cfg(feature = "session")]
+pub use sapi::{InMemorySessionStore, SessionStore};
 
 pub use execution::{
-    ExecutionContext, ExecutionHooks, ExecutionMessage, ExecutionResult,
-    NoOpHooks, OutputAction, ResponseHeader, StreamingCallback, SyslogLevel,
+    BufferGrowth, BufferPolicy, ExecutionContext, ExecutionHooks,
+    ExecutionMessage, ExecutionMetrics, ExecutionResult, HeaderPolicy,
+    HookChain, InformationalResponse, MailMessage, MemoryUsage,
+    MessageCollector, NoOpHooks, OutputAction, OverflowAction, RequestAction,
+    RequestTimings, ResponseHead, ResponseHeader, SecurityPolicy,
+    SecurityViolation, StatusLine, StreamingCallback, SyslogLevel,
+    TimingRecorder,
 };
 
 pub mod prelude {
     pub use crate::{
-        AdapterError, CliRequest, CliRequestError, ExecutionContext,
-        ExecutionHooks, ExecutionMessage, ExecutionResult, Executor, Method,
-        NoOpHooks, OutputAction, PhpSapiAdapter, ResponseHeader, RiphtSapi,
-        SapiError, StreamingCallback, SyslogLevel, WebRequest, WebRequestError,
+        AdapterError, BufferPolicy, CliRequest, CliRequestError, EngineBuilder,
+        ExecutionContext, ExecutionHooks, ExecutionMessage, ExecutionResult,
+        Executor, HookChain, Method, NoOpHooks, OutputAction, PhpSapiAdapter,
+        PhpThread, RequestAction, ResponseHeader, RiphtSapi, SapiError,
+        StreamingCallback, SyslogLevel, WebRequest, WebRequestError,
     };
 
     #[cfg(feature = "http")]
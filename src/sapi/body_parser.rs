@@ -0,0 +1,386 @@
+//! Request body parsers for content types PHP doesn't handle.
+//!
+//! PHP only fills `$_POST` for `application/x-www-form-urlencoded` and
+//! `multipart/form-data` bodies. A [`BodyParser`] registered with
+//! [`EngineBuilder::with_body_parser()`] turns other bodies, such as JSON,
+//! into the same array before the script runs. `php://input` still returns
+//! the raw body.
+//!
+//! [`EngineBuilder::with_body_parser()`]: super::EngineBuilder::with_body_parser
+
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+#[cfg(feature = "tracing")]
+use tracing::warn;
+
+use super::callbacks::get_context;
+use super::ffi;
+
+/// A parsed request body value, as it will appear in `$_POST`.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum PostValue {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    /// PHP strings are bytes; use `From<&str>` for text.
+    String(Vec<u8>),
+    /// Becomes an array with keys `0..n`.
+    List(Vec<PostValue>),
+    /// Becomes an array with these keys, in order. Numeric string keys
+    /// become integer keys, as in any PHP array.
+    Map(Vec<(String, PostValue)>),
+}
+
+impl From<&str> for PostValue {
+    fn from(value: &str) -> Self {
+        Self::String(value.as_bytes().to_vec())
+    }
+}
+
+impl From<String> for PostValue {
+    fn from(value: String) -> Self {
+        Self::String(value.into_bytes())
+    }
+}
+
+impl From<i64> for PostValue {
+    fn from(value: i64) -> Self {
+        Self::Int(value)
+    }
+}
+
+impl From<f64> for PostValue {
+    fn from(value: f64) -> Self {
+        Self::Float(value)
+    }
+}
+
+impl From<bool> for PostValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<serde_json::Value> for PostValue {
+    fn from(value: serde_json::Value) -> Self {
+        use serde_json::Value;
+
+        match value {
+            Value::Null => Self::Null,
+            Value::Bool(b) => Self::Bool(b),
+            Value::Number(n) => match n.as_i64() {
+                Some(i) => Self::Int(i),
+                // Like `json_decode()`, integers past i64 become floats.
+                None => Self::Float(n.as_f64().unwrap_or(0.0)),
+            },
+            Value::String(s) => Self::from(s),
+            Value::Array(items) => Self::List(
+                items
+                    .into_iter()
+                    .map(Self::from)
+                    .collect(),
+            ),
+            Value::Object(fields) => Self::Map(
+                fields
+                    .into_iter()
+                    .map(|(key, value)| (key, Self::from(value)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Parses `POST` bodies of one content type.
+///
+/// Called during request startup on the thread running the request.
+/// `content_type` is the full header value, parameters included. A
+/// [`PostValue::Map`] or [`PostValue::List`] fills `$_POST`; other values
+/// and errors leave it empty.
+pub trait BodyParser: Send + Sync {
+    fn parse(&self, content_type: &str, body: &[u8]) -> io::Result<PostValue>;
+}
+
+impl<F> BodyParser for F
+where
+    F: Fn(&str, &[u8]) -> io::Result<PostValue> + Send + Sync,
+{
+    fn parse(&self, content_type: &str, body: &[u8]) -> io::Result<PostValue> {
+        self(content_type, body)
+    }
+}
+
+/// [`BodyParser`] for JSON bodies, decoded like `json_decode($body, true)`.
+///
+/// ```
+/// use ripht_php_sapi::{JsonBodyParser, RiphtSapi};
+///
+/// let builder =
+///     RiphtSapi::builder().with_body_parser("application/json", JsonBodyParser);
+/// ```
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonBodyParser;
+
+#[cfg(feature = "serde")]
+impl BodyParser for JsonBodyParser {
+    fn parse(&self, _content_type: &str, body: &[u8]) -> io::Result<PostValue> {
+        serde_json::from_slice::<serde_json::Value>(body)
+            .map(PostValue::from)
+            .map_err(io::Error::from)
+    }
+}
+
+/// Shared parser handle kept in the engine configuration.
+#[derive(Clone)]
+pub(crate) struct BodyParserHandle(pub(crate) Arc<dyn BodyParser>);
+
+impl fmt::Debug for BodyParserHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BodyParserHandle(..)")
+    }
+}
+
+/// A parser and the SAPI post entry that routes its content type to it.
+struct Registered {
+    // Lowercased, without parameters: the key PHP looks up.
+    mime: String,
+    entry: ffi::sapi_post_entry,
+    parser: Arc<dyn BodyParser>,
+    // Target of `entry.content_type`.
+    _content_type: CString,
+}
+
+static REGISTERED: RwLock<Vec<Registered>> = RwLock::new(Vec::new());
+
+// Checked before every request, so kept apart from the lock.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Sets the parsers requests use; replaces any installed before.
+pub(crate) fn install(parsers: &[(String, BodyParserHandle)]) {
+    let registered = parsers
+        .iter()
+        .filter_map(|(content_type, handle)| {
+            let mime = mime_type(content_type);
+            let c_mime = CString::new(mime.as_str()).ok()?;
+
+            let entry = ffi::sapi_post_entry {
+                content_type: c_mime.as_ptr() as *mut c_char,
+                content_type_len: mime.len() as u32,
+                // `php://input` reads the body from the SAPI on demand.
+                post_reader: None,
+                post_handler: Some(ripht_post_handler),
+            };
+
+            Some(Registered {
+                mime,
+                entry,
+                parser: Arc::clone(&handle.0),
+                _content_type: c_mime,
+            })
+        })
+        .collect();
+
+    *REGISTERED
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = registered;
+
+    ACTIVE.store(!parsers.is_empty(), Ordering::Release);
+}
+
+/// Makes the calling thread's SAPI globals route the parsers' content
+/// types to them, replacing PHP's handlers for the same types.
+///
+/// Call before `php_request_startup`, which reads the body. Content types
+/// are per-thread in ZTS builds, hence the check on every request.
+pub(crate) unsafe fn activate() {
+    if !ACTIVE.load(Ordering::Acquire) {
+        return;
+    }
+
+    let registered = REGISTERED
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let known = std::ptr::addr_of!(ffi::sg().known_post_content_types);
+
+    for parser in registered.iter() {
+        let existing = ffi::zend_hash_str_find(
+            known,
+            parser.entry.content_type,
+            parser.mime.len(),
+        );
+
+        if !existing.is_null() {
+            let entry = ffi::zval_ptr(existing) as *const ffi::sapi_post_entry;
+            if (*entry).content_type == parser.entry.content_type {
+                continue;
+            }
+            ffi::sapi_unregister_post_entry(&parser.entry);
+        }
+
+        let _result = ffi::sapi_register_post_entry(&parser.entry);
+
+        #[cfg(feature = "tracing")]
+        if _result == ffi::FAILURE {
+            warn!(content_type = parser.mime, "Body parser not registered");
+        }
+    }
+}
+
+/// `text/JSON; charset=utf-8` -> `text/json`.
+fn mime_type(content_type: &str) -> String {
+    content_type
+        .split([';', ','])
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+fn parser_for(mime: &str) -> Option<Arc<dyn BodyParser>> {
+    REGISTERED
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .iter()
+        .find(|parser| parser.mime == mime)
+        .map(|parser| Arc::clone(&parser.parser))
+}
+
+/// Fills `$_POST` (`arg`, an empty array) from the request body.
+unsafe extern "C" fn ripht_post_handler(
+    content_type: *mut c_char,
+    arg: *mut c_void,
+) {
+    if content_type.is_null() || arg.is_null() {
+        return;
+    }
+
+    let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let Some(ctx_ptr) = get_context() else {
+            return;
+        };
+        let content_type = CStr::from_ptr(content_type).to_string_lossy();
+        let Some(parser) = parser_for(&mime_type(&content_type)) else {
+            return;
+        };
+
+        let body = &(*ctx_ptr).post_data;
+        let post_max_size = ffi::sg().post_max_size;
+        if post_max_size > 0 && body.len() as i64 > post_max_size {
+            #[cfg(feature = "tracing")]
+            warn!(len = body.len(), "Request body exceeds post_max_size");
+            return;
+        }
+
+        match parser.parse(&content_type, body) {
+            Ok(PostValue::List(items)) => {
+                fill_list(arg as *mut ffi::zval, &items)
+            }
+            Ok(PostValue::Map(fields)) => {
+                fill_map(arg as *mut ffi::zval, &fields)
+            }
+            Ok(_) => {}
+            Err(_err) => {
+                #[cfg(feature = "tracing")]
+                warn!(error = %_err, %content_type, "Request body not parsed");
+            }
+        }
+    }));
+}
+
+unsafe fn fill_list(array: *mut ffi::zval, items: &[PostValue]) {
+    let table = ffi::zval_ptr(array) as *mut ffi::HashTable;
+
+    for item in items {
+        let mut value = to_zval(item);
+        ffi::zend_hash_next_index_insert(table, &mut value);
+    }
+}
+
+unsafe fn fill_map(array: *mut ffi::zval, fields: &[(String, PostValue)]) {
+    for (key, item) in fields {
+        let mut value = to_zval(item);
+        ffi::add_assoc_zval_ex(
+            array,
+            key.as_ptr() as *const c_char,
+            key.len(),
+            &mut value,
+        );
+    }
+}
+
+unsafe fn to_zval(value: &PostValue) -> ffi::zval {
+    let mut zv: ffi::zval = std::mem::zeroed();
+
+    match value {
+        PostValue::Null => ffi::zval_set_null(&mut zv),
+        PostValue::Bool(b) => ffi::zval_set_bool(&mut zv, *b),
+        PostValue::Int(i) => ffi::zval_set_long(&mut zv, *i),
+        PostValue::Float(f) => ffi::zval_set_double(&mut zv, *f),
+        PostValue::String(bytes) => ffi::zval_set_str(
+            &mut zv,
+            ffi::zend_string_concat2(
+                bytes.as_ptr() as *const c_char,
+                bytes.len(),
+                c"".as_ptr(),
+                0,
+            ),
+        ),
+        PostValue::List(items) => {
+            ffi::zval_set_array(&mut zv, ffi::_zend_new_array_0());
+            fill_list(&mut zv, items);
+        }
+        PostValue::Map(fields) => {
+            ffi::zval_set_array(&mut zv, ffi::_zend_new_array_0());
+            fill_map(&mut zv, fields);
+        }
+    }
+
+    zv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mime_type_drops_parameters() {
+        assert_eq!(
+            mime_type("Application/JSON; charset=utf-8"),
+            "application/json"
+        );
+        assert_eq!(mime_type("application/msgpack"), "application/msgpack");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_parser_builds_post_values() {
+        let value = JsonBodyParser
+            .parse(
+                "application/json",
+                br#"{"name":"Jane","tags":["a",1],"big":18446744073709551615}"#,
+            )
+            .unwrap();
+
+        assert_eq!(
+            value,
+            PostValue::Map(vec![
+                ("big".into(), PostValue::Float(u64::MAX as f64)),
+                ("name".into(), "Jane".into()),
+                (
+                    "tags".into(),
+                    PostValue::List(vec!["a".into(), PostValue::Int(1)])
+                ),
+            ])
+        );
+        assert!(JsonBodyParser
+            .parse("application/json", b"{")
+            .is_err());
+    }
+}
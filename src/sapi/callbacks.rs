@@ -4,7 +4,9 @@
 //!
 //! All callbacks share these invariants:
 //!
-//! - **Threading**: NTS build only. One request executes at a time.
+//! - **Threading**: One request executes at a time per thread. NTS builds run
+//!   every request on the engine's owner thread; ZTS builds resolve
+//!   `sapi_globals` through the calling thread's TSRM storage.
 //! - **Context lifetime**: `sapi_globals.server_context` is valid only during
 //!   request execution (between `php_request_startup` and `php_request_shutdown`).
 //! - **Panic safety**: Callbacks wrap Rust code in `catch_unwind` to prevent
@@ -15,14 +17,19 @@
 
 use std::ffi::{CStr, CString};
 use std::os::raw::{c_char, c_double, c_int, c_uint, c_void};
+use std::time::Instant;
 
 #[cfg(feature = "tracing")]
 use tracing::{debug, error, info, trace, warn};
 
 use super::ffi;
-use super::server_context::ServerContext;
+use super::input_filter;
+use super::policy;
+use super::server_context::{epoch_seconds, ServerContext};
 use super::SERVER_SOFTWARE;
-use crate::execution::{ExecutionMessage, ResponseHeader};
+use crate::execution::{
+    ExecutionMessage, InformationalResponse, ResponseHeader, StatusLine,
+};
 
 const HTTP_STATUS_MIN: i32 = 100;
 const HTTP_STATUS_MAX: i32 = 599;
@@ -31,7 +38,7 @@ const HTTP_STATUS_FALLBACK: u16 = 500;
 /// Returns the [`ServerContext`] pointer if valid. See module docs for safety.
 #[inline]
 pub(crate) unsafe fn get_context() -> Option<*mut ServerContext> {
-    let ptr = ffi::sapi_globals.server_context as *mut ServerContext;
+    let ptr = ffi::sg().server_context as *mut ServerContext;
     if ptr.is_null() {
         return None;
     }
@@ -60,7 +67,14 @@ pub unsafe extern "C" fn ripht_sapi_shutdown(
 }
 
 #[no_mangle]
-pub extern "C" fn ripht_sapi_activate() -> c_int {
+pub unsafe extern "C" fn ripht_sapi_activate() -> c_int {
+    if let Some(ctx_ptr) = get_context() {
+        if let Some(policy) = &(*ctx_ptr).security_policy {
+            policy::apply_ini(policy);
+            policy::guard_classes(policy);
+        }
+    }
+
     ffi::SUCCESS
 }
 
@@ -79,7 +93,7 @@ pub unsafe extern "C" fn ripht_sapi_ub_write(
         return 0;
     }
 
-    if ffi::sapi_globals.headers_sent == 0 {
+    if ffi::sg().headers_sent == 0 {
         ffi::sapi_send_headers();
     }
 
@@ -93,10 +107,26 @@ pub unsafe extern "C" fn ripht_sapi_ub_write(
         #[cfg(feature = "tracing")]
         trace!(bytes_written = str_length, "Output captured");
 
-        (*ctx_ptr).write_output(bytes)
+        let written = (*ctx_ptr).write_output(bytes);
+        let overflowed =
+            (*ctx_ptr).output_limit_exceeded || (*ctx_ptr).truncated;
+        let abort = overflowed
+            && !std::mem::replace(&mut (*ctx_ptr).connection_aborted, true);
+
+        (written, abort)
     }));
 
-    result.unwrap_or(0)
+    let (written, abort) = result.unwrap_or((0, false));
+
+    if abort {
+        #[cfg(feature = "tracing")]
+        warn!("Output limit exceeded, aborting request");
+
+        // May longjmp out of this frame; nothing with a destructor is live here.
+        ffi::php_handle_aborted_connection();
+    }
+
+    written
 }
 
 /// Flush output callback.
@@ -105,8 +135,23 @@ pub unsafe extern "C" fn ripht_sapi_flush(_server_context: *mut c_void) {
     #[cfg(feature = "tracing")]
     trace!("Flush called");
 
-    if ffi::sapi_globals.headers_sent == 0 {
-        ffi::sapi_send_headers();
+    if ffi::sg().headers_sent == 0 {
+        let sapi_headers = std::ptr::addr_of_mut!(ffi::sg().sapi_headers);
+
+        match get_context() {
+            // An explicit flush() sends a pending 1xx on its own, leaving the
+            // final head open for the script to change.
+            Some(ctx_ptr)
+                if is_informational((*sapi_headers).http_response_code) =>
+            {
+                let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(
+                    || send_informational(ctx_ptr, sapi_headers),
+                ));
+            }
+            _ => {
+                ffi::sapi_send_headers();
+            }
+        }
     }
 
     let Some(ctx_ptr) = get_context() else {
@@ -118,6 +163,34 @@ pub unsafe extern "C" fn ripht_sapi_flush(_server_context: *mut c_void) {
     }));
 }
 
+fn is_informational(status: c_int) -> bool {
+    (100..200).contains(&status)
+}
+
+/// Records the pending 1xx head as an informational response and resets
+/// the status to 200 for the final response.
+unsafe fn send_informational(
+    ctx_ptr: *mut ServerContext,
+    sapi_headers: *mut ffi::sapi_headers_struct,
+) {
+    let status = (*sapi_headers).http_response_code;
+    let headers = header_list(sapi_headers)
+        .into_iter()
+        .filter(|h| {
+            !h.name()
+                .eq_ignore_ascii_case("Content-Type")
+        })
+        .collect();
+
+    #[cfg(feature = "tracing")]
+    debug!(status, "Informational response sent");
+
+    (*ctx_ptr)
+        .add_informational(InformationalResponse::new(status as u16, headers));
+
+    (*sapi_headers).http_response_code = 200;
+}
+
 /// Send all response headers callback.
 #[no_mangle]
 pub unsafe extern "C" fn ripht_sapi_send_headers(
@@ -127,12 +200,21 @@ pub unsafe extern "C" fn ripht_sapi_send_headers(
         return ffi::SAPI_HEADER_SEND_FAILED;
     }
 
+    let started = Instant::now();
+
     let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
         let Some(ctx_ptr) = get_context() else {
             return ffi::SAPI_HEADER_SEND_FAILED;
         };
 
+        // Output is about to go out, so the final head has to follow the 1xx
+        // now: failing the send would make PHP disable output.
+        if is_informational((*sapi_headers).http_response_code) {
+            send_informational(ctx_ptr, sapi_headers);
+        }
+
         let status = (*sapi_headers).http_response_code;
+
         let status_code: u16 =
             if !(HTTP_STATUS_MIN..=HTTP_STATUS_MAX).contains(&status) {
                 HTTP_STATUS_FALLBACK
@@ -145,6 +227,17 @@ pub unsafe extern "C" fn ripht_sapi_send_headers(
             .response_headers
             .clear();
 
+        let status_line = (*sapi_headers).http_status_line;
+        (*ctx_ptr).status_line = if status_line.is_null() {
+            None
+        } else {
+            CStr::from_ptr(status_line)
+                .to_str()
+                .ok()
+                .and_then(StatusLine::parse)
+                .filter(|line| line.code() == status_code)
+        };
+
         // Iterate PHP's header list
         let mut elem = (*sapi_headers).headers.head;
         while !elem.is_null() {
@@ -154,12 +247,47 @@ pub unsafe extern "C" fn ripht_sapi_send_headers(
             elem = (*elem).next;
         }
 
+        (*ctx_ptr).apply_response_head_hooks();
+        (*sapi_headers).http_response_code = (*ctx_ptr).status_code() as c_int;
+
         ffi::SAPI_HEADER_SENT_SUCCESSFULLY
     }));
 
+    if let Some(ctx_ptr) = get_context() {
+        (*ctx_ptr)
+            .metrics
+            .send_headers += started.elapsed();
+    }
+
     result.unwrap_or(ffi::SAPI_HEADER_SEND_FAILED)
 }
 
+/// Parses PHP's pending header list without touching the context.
+unsafe fn header_list(
+    sapi_headers: *const ffi::sapi_headers_struct,
+) -> Vec<ResponseHeader> {
+    let mut headers = Vec::new();
+
+    let mut elem = (*sapi_headers).headers.head;
+    while !elem.is_null() {
+        let header =
+            &*((*elem).data.as_ptr() as *const ffi::sapi_header_struct);
+
+        if !header.header.is_null() && header.header_len > 0 {
+            let bytes = std::slice::from_raw_parts(
+                header.header as *const u8,
+                header.header_len,
+            );
+
+            headers.extend(ResponseHeader::parse(bytes));
+        }
+
+        elem = (*elem).next;
+    }
+
+    headers
+}
+
 /// Send single response header callback.
 #[no_mangle]
 pub unsafe extern "C" fn ripht_sapi_send_header(
@@ -249,10 +377,9 @@ pub unsafe extern "C" fn ripht_sapi_register_server_variables(
 
         let ctx = &*ctx_ptr;
         for (name, value) in ctx.server_vars() {
-            ffi::php_register_variable_safe(
-                name.as_ptr(),
-                value.as_ptr(),
-                value.as_bytes().len(),
+            input_filter::register_server_variable(
+                name,
+                value.as_bytes(),
                 track_vars_array,
             );
         }
@@ -346,14 +473,10 @@ pub unsafe extern "C" fn ripht_sapi_get_request_time(
     }
 
     let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-        use std::time::{SystemTime, UNIX_EPOCH};
-
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .map(|d| d.as_secs_f64())
-            .unwrap_or(0.0);
+        let pinned = get_context().and_then(|ctx_ptr| (*ctx_ptr).request_time);
 
-        *request_time = now;
+        *request_time = pinned
+            .unwrap_or_else(|| epoch_seconds(std::time::SystemTime::now()));
         ffi::SUCCESS
     }));
 
@@ -393,7 +516,7 @@ mod tests {
     use super::*;
 
     unsafe fn get_context_for_test() -> Option<*mut ServerContext> {
-        let ptr = super::ffi::sapi_globals.server_context as *mut ServerContext;
+        let ptr = super::ffi::sg().server_context as *mut ServerContext;
 
         if ptr.is_null() {
             return None;
@@ -468,7 +591,7 @@ mod tests {
     #[test]
     fn test_get_context_null_handling() {
         unsafe {
-            super::ffi::sapi_globals.server_context = std::ptr::null_mut();
+            super::ffi::sg().server_context = std::ptr::null_mut();
 
             let result = get_context_for_test();
 
@@ -485,8 +608,7 @@ mod tests {
             let ctx = Box::new(ServerContext::new());
             let ctx_ptr = Box::into_raw(ctx);
 
-            super::ffi::sapi_globals.server_context =
-                ctx_ptr as *mut std::ffi::c_void;
+            super::ffi::sg().server_context = ctx_ptr as *mut std::ffi::c_void;
 
             let result = get_context_for_test();
 
@@ -496,7 +618,7 @@ mod tests {
             );
 
             let _ = Box::from_raw(ctx_ptr);
-            super::ffi::sapi_globals.server_context = std::ptr::null_mut();
+            super::ffi::sg().server_context = std::ptr::null_mut();
         }
     }
 
@@ -559,8 +681,7 @@ mod tests {
         unsafe {
             let ctx = Box::new(ServerContext::new());
             let ctx_ptr = Box::into_raw(ctx);
-            super::ffi::sapi_globals.server_context =
-                ctx_ptr as *mut std::ffi::c_void;
+            super::ffi::sg().server_context = ctx_ptr as *mut std::ffi::c_void;
 
             let mut headers = super::ffi::sapi_headers_struct {
                 http_response_code: -1,
@@ -582,7 +703,7 @@ mod tests {
             );
 
             let _ = Box::from_raw(ctx_ptr);
-            super::ffi::sapi_globals.server_context = std::ptr::null_mut();
+            super::ffi::sg().server_context = std::ptr::null_mut();
         }
     }
 
@@ -630,8 +751,7 @@ mod tests {
         unsafe {
             let ctx = Box::new(ServerContext::new());
             let ctx_ptr = Box::into_raw(ctx);
-            super::ffi::sapi_globals.server_context =
-                ctx_ptr as *mut std::ffi::c_void;
+            super::ffi::sg().server_context = ctx_ptr as *mut std::ffi::c_void;
 
             let mut header = super::ffi::sapi_header_struct::default();
             let header_str = b"InvalidHeaderNoColon\r\n";
@@ -649,7 +769,7 @@ mod tests {
             );
 
             let _ = Box::from_raw(ctx_ptr);
-            super::ffi::sapi_globals.server_context = std::ptr::null_mut();
+            super::ffi::sg().server_context = std::ptr::null_mut();
         }
     }
 
@@ -669,8 +789,7 @@ mod tests {
         unsafe {
             let ctx = Box::new(ServerContext::new());
             let ctx_ptr = Box::into_raw(ctx);
-            super::ffi::sapi_globals.server_context =
-                ctx_ptr as *mut std::ffi::c_void;
+            super::ffi::sg().server_context = ctx_ptr as *mut std::ffi::c_void;
 
             let mut header = super::ffi::sapi_header_struct::default();
             let header_str = b": value\r\n";
@@ -688,7 +807,7 @@ mod tests {
             );
 
             let _ = Box::from_raw(ctx_ptr);
-            super::ffi::sapi_globals.server_context = std::ptr::null_mut();
+            super::ffi::sg().server_context = std::ptr::null_mut();
         }
     }
 
@@ -708,8 +827,7 @@ mod tests {
         unsafe {
             let ctx = Box::new(ServerContext::new());
             let ctx_ptr = Box::into_raw(ctx);
-            super::ffi::sapi_globals.server_context =
-                ctx_ptr as *mut std::ffi::c_void;
+            super::ffi::sg().server_context = ctx_ptr as *mut std::ffi::c_void;
 
             let mut header = super::ffi::sapi_header_struct::default();
             let header_str = b"X-Empty-Value:\r\n";
@@ -732,7 +850,7 @@ mod tests {
             assert_eq!(headers[0].value(), "");
 
             let _ = Box::from_raw(ctx_ptr);
-            super::ffi::sapi_globals.server_context = std::ptr::null_mut();
+            super::ffi::sg().server_context = std::ptr::null_mut();
         }
     }
 
@@ -752,8 +870,7 @@ mod tests {
         unsafe {
             let ctx = Box::new(ServerContext::new());
             let ctx_ptr = Box::into_raw(ctx);
-            super::ffi::sapi_globals.server_context =
-                ctx_ptr as *mut std::ffi::c_void;
+            super::ffi::sg().server_context = ctx_ptr as *mut std::ffi::c_void;
 
             let mut header = super::ffi::sapi_header_struct::default();
             let header_str = b"X-Trailing-Colon:\r\n";
@@ -771,7 +888,7 @@ mod tests {
             );
 
             let _ = Box::from_raw(ctx_ptr);
-            super::ffi::sapi_globals.server_context = std::ptr::null_mut();
+            super::ffi::sg().server_context = std::ptr::null_mut();
         }
     }
 }
@@ -0,0 +1,284 @@
+//! Recycling of per-request allocations.
+//!
+//! Each execution needs a boxed [`ServerContext`], an output buffer, header
+//! and message vectors, and a `CString` per server variable. [`ContextPool`]
+//! keeps those allocations alive between requests run on the same
+//! [`Executor`](super::Executor).
+
+use std::ffi::CString;
+
+use super::default_buffer_policy;
+use super::server_context::ServerContext;
+use crate::execution::ExecutionContext;
+
+/// Contexts kept for reuse. An executor runs one request at a time, so a
+/// couple covers nested/streaming use without hoarding memory.
+const MAX_POOLED_CONTEXTS: usize = 2;
+
+/// Spare `CString` buffers kept for reuse.
+const MAX_POOLED_CSTRINGS: usize = 256;
+
+/// Output buffers above this capacity are dropped instead of pooled.
+const MAX_RETAINED_BUFFER: usize = 1024 * 1024;
+
+/// Allocation counters for an [`Executor`](super::Executor)'s context pool.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PoolStats {
+    /// Server contexts allocated because the pool was empty.
+    pub contexts_created: u64,
+    /// Server contexts taken from the pool.
+    pub contexts_reused: u64,
+    /// Output buffers allocated (fresh contexts, and pooled contexts whose
+    /// buffer went to the previous result).
+    pub buffers_allocated: u64,
+    /// Requests that wrote into a pooled context's existing buffer.
+    pub buffers_reused: u64,
+    /// `CString`s built in recycled storage.
+    pub cstrings_reused: u64,
+}
+
+impl PoolStats {
+    /// Fraction of requests served by a pooled context, from 0.0 to 1.0.
+    pub fn context_hit_rate(&self) -> f64 {
+        let total = self.contexts_created + self.contexts_reused;
+
+        if total == 0 {
+            0.0
+        } else {
+            self.contexts_reused as f64 / total as f64
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct ContextPool {
+    // Boxed: the context's address is handed to PHP via `server_context`.
+    #[allow(clippy::vec_box)]
+    contexts: Vec<Box<ServerContext>>,
+    cstring_bufs: Vec<Vec<u8>>,
+    stats: PoolStats,
+}
+
+impl ContextPool {
+    pub(crate) fn stats(&self) -> PoolStats {
+        self.stats
+    }
+
+    /// Returns a context populated from `ctx`, reusing pooled storage.
+    pub(crate) fn acquire(
+        &mut self,
+        ctx: ExecutionContext,
+    ) -> Box<ServerContext> {
+        let mut server_ctx = match self.contexts.pop() {
+            Some(server_ctx) => {
+                self.stats.contexts_reused += 1;
+                server_ctx
+            }
+            None => {
+                self.stats.contexts_created += 1;
+                self.stats.buffers_allocated += 1;
+                return Box::from(ctx);
+            }
+        };
+
+        let policy = ctx.resolve_buffer_policy(default_buffer_policy);
+        server_ctx.buffer_policy = policy;
+
+        let initial_cap = policy.initial_capacity();
+        if server_ctx
+            .output_buffer
+            .capacity()
+            < initial_cap
+        {
+            self.stats.buffers_allocated += 1;
+            server_ctx
+                .output_buffer
+                .reserve(initial_cap);
+        } else {
+            self.stats.buffers_reused += 1;
+        }
+
+        server_ctx.fill_from(ctx, |s| self.cstring(s));
+        server_ctx
+    }
+
+    /// Hands the output buffer over as the response body, without copying.
+    /// The context gets a new buffer when it's next acquired.
+    pub(crate) fn take_body(server_ctx: &mut ServerContext) -> Vec<u8> {
+        std::mem::take(&mut server_ctx.output_buffer)
+    }
+
+    /// Resets `server_ctx` and keeps it (and its strings' storage) for reuse.
+    pub(crate) fn release(&mut self, mut server_ctx: Box<ServerContext>) {
+        if let Some(vars) = server_ctx.vars.as_mut() {
+            for (key, value) in vars.vars.drain(..) {
+                self.recycle(key);
+                self.recycle(value);
+            }
+
+            for cstring in [
+                vars.content_type.take(),
+                vars.query_string.take(),
+                vars.cookie.take(),
+                vars.request_method.take(),
+            ]
+            .into_iter()
+            .flatten()
+            {
+                self.recycle(cstring);
+            }
+        }
+
+        for (key, value) in server_ctx
+            .env_vars
+            .drain(..)
+            .chain(
+                server_ctx
+                    .ini_overrides
+                    .drain(..),
+            )
+        {
+            self.recycle(key);
+            self.recycle(value);
+        }
+
+        if self.contexts.len() >= MAX_POOLED_CONTEXTS {
+            return;
+        }
+
+        server_ctx.reset();
+
+        if server_ctx
+            .output_buffer
+            .capacity()
+            > MAX_RETAINED_BUFFER
+        {
+            server_ctx.output_buffer = Vec::new();
+        }
+
+        self.contexts.push(server_ctx);
+    }
+
+    fn cstring(&mut self, s: String) -> Option<CString> {
+        let Some(mut buf) = self.cstring_bufs.pop() else {
+            return CString::new(s).ok();
+        };
+
+        buf.extend_from_slice(s.as_bytes());
+
+        match CString::new(buf) {
+            Ok(cstring) => {
+                self.stats.cstrings_reused += 1;
+                Some(cstring)
+            }
+            Err(e) => {
+                self.recycle_bytes(e.into_vec());
+                None
+            }
+        }
+    }
+
+    fn recycle(&mut self, cstring: CString) {
+        self.recycle_bytes(cstring.into_bytes());
+    }
+
+    fn recycle_bytes(&mut self, mut buf: Vec<u8>) {
+        if self.cstring_bufs.len() < MAX_POOLED_CSTRINGS {
+            buf.clear();
+            self.cstring_bufs.push(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> ExecutionContext {
+        ExecutionContext::script("/index.php")
+            .var("REQUEST_METHOD", "GET")
+            .var("QUERY_STRING", "a=1")
+            .env("APP_ENV", "test")
+            .ini("display_errors", "0")
+    }
+
+    #[test]
+    fn test_contexts_are_reused() {
+        let mut pool = ContextPool::default();
+
+        let server_ctx = pool.acquire(context());
+        pool.release(server_ctx);
+
+        let server_ctx = pool.acquire(context());
+
+        assert_eq!(pool.stats().contexts_created, 1);
+        assert_eq!(pool.stats().contexts_reused, 1);
+        assert!(pool.stats().cstrings_reused >= 6);
+        assert_eq!(
+            server_ctx
+                .vars
+                .as_ref()
+                .and_then(|v| v.query_string.as_deref())
+                .map(|c| c.to_bytes()),
+            Some(&b"a=1"[..])
+        );
+        assert_eq!(server_ctx.env_vars.len(), 1);
+        assert_eq!(server_ctx.ini_overrides.len(), 1);
+    }
+
+    #[test]
+    fn test_released_context_is_reset() {
+        let mut pool = ContextPool::default();
+
+        let mut server_ctx = pool.acquire(context());
+        server_ctx.write_output(b"hello");
+        server_ctx.set_status(404);
+        server_ctx.add_header(crate::ResponseHeader::new("X-A", "1"));
+        pool.release(server_ctx);
+
+        let server_ctx = pool.acquire(ExecutionContext::script("/b.php"));
+
+        assert_eq!(server_ctx.status_code(), 200);
+        assert!(server_ctx
+            .output_buffer
+            .is_empty());
+        assert!(server_ctx
+            .response_headers
+            .is_empty());
+        assert!(server_ctx.env_vars.is_empty());
+    }
+
+    #[test]
+    fn test_buffer_allocations_are_counted() {
+        let mut pool = ContextPool::default();
+
+        // Body handed to the result: the next request needs a new buffer.
+        let mut server_ctx = pool.acquire(context());
+        server_ctx.write_output(b"small");
+        assert_eq!(ContextPool::take_body(&mut server_ctx), b"small");
+        pool.release(server_ctx);
+
+        let server_ctx = pool.acquire(context());
+        assert_eq!(pool.stats().buffers_allocated, 2);
+        assert_eq!(pool.stats().buffers_reused, 0);
+
+        // Body not taken (streamed, or dropped by a hook): the buffer stays.
+        pool.release(server_ctx);
+        let _server_ctx = pool.acquire(context());
+
+        assert_eq!(pool.stats().buffers_allocated, 2);
+        assert_eq!(pool.stats().buffers_reused, 1);
+    }
+
+    #[test]
+    fn test_interior_nul_is_dropped() {
+        let mut pool = ContextPool::default();
+        pool.recycle_bytes(Vec::with_capacity(8));
+
+        assert!(pool
+            .cstring("bad\0value".to_string())
+            .is_none());
+        assert_eq!(pool.cstring_bufs.len(), 1);
+    }
+}
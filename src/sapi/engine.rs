@@ -0,0 +1,699 @@
+//! Engine lifecycle: startup configuration and the running/shut down state machine.
+//!
+//! PHP's module startup (MINIT) and shutdown (MSHUTDOWN) operate on process-wide
+//! globals. The state here tracks which phase the engine is in so handles can
+//! refuse to touch a dead engine instead of running into undefined behavior.
+
+use std::cell::Cell;
+use std::ffi::CString;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard};
+use std::thread::{self, ThreadId};
+
+#[cfg(feature = "tracing")]
+use tracing::{error, info, trace};
+
+use super::body_parser::{self, BodyParser, BodyParserHandle};
+use super::http_mock::{self, HttpMock};
+use super::input_filter::{self, InputFilter, InputFilterHandle};
+use super::mail::{self, MailSink, MailSinkHandle};
+use super::policy;
+#[cfg(feature = "session")]
+use super::session::{self, SessionStore, SessionStoreHandle};
+use super::stream_wrapper::{self, StreamWrapper, StreamWrapperHandle};
+#[cfg(php_zts)]
+use super::tsrm;
+use super::vfs::{self, VirtualFs, VirtualFsHandle};
+use super::{callbacks, ffi, SapiError, SAPI_NAME, SAPI_PRETTY_NAME};
+use crate::execution::{BufferPolicy, SecurityPolicy};
+
+static DEFAULT_INI_ENTRIES: &[(&str, &str)] = &[
+    ("variables_order", "EGPCS"),
+    ("request_order", "GP"),
+    ("output_buffering", "4096"),
+    ("implicit_flush", "0"),
+    ("html_errors", "0"),
+    ("display_errors", "1"),
+    ("log_errors", "1"),
+];
+
+static STATE: AtomicU8 = AtomicU8::new(EngineState::Uninitialized as u8);
+
+static LIFECYCLE: Mutex<Lifecycle> = Mutex::new(Lifecycle {
+    config: None,
+    ini_block: None,
+    owner: None,
+});
+
+// Read on every request, so kept apart from the lifecycle lock.
+static DEFAULT_POLICIES: RwLock<DefaultPolicies> =
+    RwLock::new(DefaultPolicies {
+        buffer_policy: None,
+        security_policy: None,
+    });
+
+/// Policies of the running engine's configuration.
+struct DefaultPolicies {
+    buffer_policy: Option<BufferPolicy>,
+    security_policy: Option<Arc<SecurityPolicy>>,
+}
+
+thread_local! {
+    static OWNER_TOKEN: OwnerToken = const { OwnerToken(Cell::new(None)) };
+}
+
+/// Releases engine ownership when the owning thread exits, so a later thread
+/// can adopt a running engine whose owner is gone.
+struct OwnerToken(Cell<Option<ThreadId>>);
+
+impl Drop for OwnerToken {
+    fn drop(&mut self) {
+        if let Some(id) = self.0.take() {
+            let mut lifecycle = lock();
+
+            if lifecycle.owner == Some(id) {
+                lifecycle.owner = None;
+            }
+        }
+    }
+}
+
+/// Lifecycle phase of the embedded PHP engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EngineState {
+    /// Module startup has not run yet.
+    Uninitialized = 0,
+    /// Module startup succeeded; requests can be executed.
+    Running = 1,
+    /// Module shutdown is done and startup is being re-run.
+    Restarting = 2,
+    /// Module shutdown has run. Use `restart()` to bring the engine back.
+    ShutDown = 3,
+}
+
+impl EngineState {
+    fn from_raw(raw: u8) -> Self {
+        match raw {
+            1 => Self::Running,
+            2 => Self::Restarting,
+            3 => Self::ShutDown,
+            _ => Self::Uninitialized,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        matches!(self, Self::Running)
+    }
+}
+
+/// Startup configuration for the PHP engine.
+///
+/// Applied during module startup, so it covers settings PHP only reads once
+/// (e.g. `INI_SYSTEM` directives). Obtain one with [`RiphtSapi::builder()`].
+///
+/// [`RiphtSapi::builder()`]: super::RiphtSapi::builder
+#[derive(Debug, Clone, Default)]
+pub struct EngineBuilder {
+    ini_entries: Vec<(String, String)>,
+    buffer_policy: Option<BufferPolicy>,
+    security_policy: Option<SecurityPolicy>,
+    #[cfg(feature = "session")]
+    session_store: Option<SessionStoreHandle>,
+    virtual_fs: Option<VirtualFsHandle>,
+    mail_sink: Option<MailSinkHandle>,
+    http_mock: Option<HttpMock>,
+    stream_wrappers: Vec<(String, StreamWrapperHandle)>,
+    input_filter: Option<InputFilterHandle>,
+    body_parsers: Vec<(String, BodyParserHandle)>,
+}
+
+impl EngineBuilder {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a startup INI entry. Later entries override earlier ones and the defaults.
+    #[must_use]
+    pub fn with_ini(
+        mut self,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Self {
+        self.ini_entries
+            .push((key.into(), value.into()));
+        self
+    }
+
+    #[must_use]
+    pub fn with_ini_entries<I, K, V>(mut self, iter: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.ini_entries.extend(
+            iter.into_iter()
+                .map(|(k, v)| (k.into(), v.into())),
+        );
+        self
+    }
+
+    /// Output buffer policy for requests that don't set their own.
+    ///
+    /// Defaults to [`BufferPolicy::from_env()`].
+    #[must_use]
+    pub fn with_buffer_policy(mut self, policy: BufferPolicy) -> Self {
+        self.buffer_policy = Some(policy);
+        self
+    }
+
+    /// Security policy for requests that don't set their own.
+    #[must_use]
+    pub fn with_security_policy(mut self, policy: SecurityPolicy) -> Self {
+        self.security_policy = Some(policy);
+        self
+    }
+
+    /// Stores PHP sessions in `store` instead of PHP's file handler.
+    ///
+    /// Registers the `ripht` save handler and makes it the default
+    /// `session.save_handler`.
+    #[cfg(feature = "session")]
+    #[must_use]
+    pub fn with_session_store(
+        mut self,
+        store: impl SessionStore + 'static,
+    ) -> Self {
+        self.session_store =
+            Some(SessionStoreHandle(std::sync::Arc::new(store)));
+        self
+    }
+
+    /// Serves scripts and includes from `fs` before the real filesystem.
+    ///
+    /// Request builders and the executor accept script paths that only exist
+    /// in `fs` while the engine runs with it.
+    #[must_use]
+    pub fn with_virtual_fs(mut self, fs: impl VirtualFs + 'static) -> Self {
+        self.virtual_fs = Some(VirtualFsHandle(std::sync::Arc::new(fs)));
+        self
+    }
+
+    /// Delivers `mail()` calls to `sink` instead of `sendmail_path`.
+    ///
+    /// Messages are also listed in `ExecutionResult::mail()`. Use a
+    /// [`MailCollector`](super::MailCollector) to assert on them in tests.
+    #[must_use]
+    pub fn with_mail_sink(mut self, sink: impl MailSink + 'static) -> Self {
+        self.mail_sink = Some(MailSinkHandle(std::sync::Arc::new(sink)));
+        self
+    }
+
+    /// Answers `http://` and `https://` stream requests from `mock`.
+    ///
+    /// Meant for tests: scripts' `file_get_contents()` and `fopen()` calls
+    /// get canned responses and never reach the network.
+    #[must_use]
+    pub fn with_http_mock(mut self, mock: HttpMock) -> Self {
+        self.http_mock = Some(mock);
+        self
+    }
+
+    /// Serves `scheme://` URLs from `wrapper` in every request.
+    ///
+    /// Replaces PHP's own wrapper for `scheme`, if any, and a wrapper added
+    /// earlier for the same scheme. An [`HttpMock`] still takes precedence
+    /// for `http` and `https`.
+    #[must_use]
+    pub fn with_stream_wrapper(
+        mut self,
+        scheme: impl Into<String>,
+        wrapper: impl StreamWrapper + 'static,
+    ) -> Self {
+        let scheme = scheme.into();
+        self.stream_wrappers
+            .retain(|(existing, _)| !existing.eq_ignore_ascii_case(&scheme));
+        self.stream_wrappers
+            .push((scheme, StreamWrapperHandle(std::sync::Arc::new(wrapper))));
+        self
+    }
+
+    /// Runs every GET, POST, cookie and `$_SERVER` value through `filter`
+    /// before PHP registers it.
+    #[must_use]
+    pub fn with_input_filter(
+        mut self,
+        filter: impl InputFilter + 'static,
+    ) -> Self {
+        self.input_filter =
+            Some(InputFilterHandle(std::sync::Arc::new(filter)));
+        self
+    }
+
+    /// Fills `$_POST` from `POST` bodies of `content_type` with `parser`.
+    ///
+    /// `content_type` is matched case-insensitively, ignoring parameters
+    /// such as `charset`. Registering a type PHP already parses (e.g.
+    /// `application/x-www-form-urlencoded`) replaces PHP's parser.
+    #[must_use]
+    pub fn with_body_parser(
+        mut self,
+        content_type: impl Into<String>,
+        parser: impl BodyParser + 'static,
+    ) -> Self {
+        self.body_parsers.push((
+            content_type.into(),
+            BodyParserHandle(std::sync::Arc::new(parser)),
+        ));
+        self
+    }
+
+    /// Starts the engine with this configuration.
+    ///
+    /// Fails with [`SapiError::AlreadyInitialized`] if the engine is already
+    /// running; use [`RiphtSapi::restart_with()`] to swap configuration.
+    ///
+    /// [`RiphtSapi::restart_with()`]: super::RiphtSapi::restart_with
+    pub fn build(self) -> Result<super::RiphtSapi, SapiError> {
+        let mut lifecycle = lock();
+
+        if state().is_running() {
+            return Err(SapiError::AlreadyInitialized);
+        }
+
+        lifecycle.start(self)?;
+
+        Ok(super::RiphtSapi::handle())
+    }
+
+    /// Renders the INI block handed to `sapi_module.ini_entries`.
+    /// Fails if the security policy names a function it can't disable.
+    fn check_security_policy(&self) -> Result<(), SapiError> {
+        match self
+            .security_policy
+            .as_ref()
+            .and_then(SecurityPolicy::uninterceptable_function)
+        {
+            Some(name) => Err(SapiError::UnenforceablePolicy(format!(
+                "{name}() can't be disabled"
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    fn ini_block(&self) -> Result<CString, SapiError> {
+        let mut block = String::new();
+
+        #[cfg(feature = "session")]
+        let session_entry = self
+            .session_store
+            .as_ref()
+            .map(|_| ("session.save_handler", session::SAVE_HANDLER));
+        #[cfg(not(feature = "session"))]
+        let session_entry = None;
+
+        let entries = DEFAULT_INI_ENTRIES
+            .iter()
+            .map(|(k, v)| (*k, *v))
+            .chain(session_entry)
+            .chain(
+                self.ini_entries
+                    .iter()
+                    .map(|(k, v)| (k.as_str(), v.as_str())),
+            );
+
+        for (key, value) in entries {
+            if key.is_empty() || key.contains(['\0', '\n', '\r', '=']) {
+                return Err(SapiError::InvalidIniKey);
+            }
+            if value.contains(['\0', '\n', '\r']) {
+                return Err(SapiError::InvalidIniValue);
+            }
+
+            block.push_str(key);
+            block.push('=');
+            block.push_str(value);
+            block.push('\n');
+        }
+
+        CString::new(block).map_err(|_| SapiError::InvalidIniValue)
+    }
+}
+
+/// Process-wide engine bookkeeping, guarded by [`LIFECYCLE`].
+pub(crate) struct Lifecycle {
+    config: Option<EngineBuilder>,
+    // `sapi_module.ini_entries` points into this block while the engine runs.
+    ini_block: Option<CString>,
+    // NTS globals may only be touched from the thread that ran startup.
+    owner: Option<ThreadId>,
+}
+
+impl Lifecycle {
+    /// Configuration the engine is (or was last) running with.
+    pub(crate) fn config(&self) -> EngineBuilder {
+        self.config
+            .clone()
+            .unwrap_or_default()
+    }
+
+    /// Fails if the engine is owned by a thread other than the caller.
+    pub(crate) fn check_thread(&self) -> Result<(), SapiError> {
+        let current = thread::current().id();
+
+        match self.owner {
+            Some(owner) if owner != current => {
+                Err(SapiError::WrongThread { owner, current })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Makes the calling thread the engine owner.
+    pub(crate) fn claim(&mut self) {
+        let current = thread::current().id();
+
+        self.owner = Some(current);
+        let _ = OWNER_TOKEN.try_with(|token| token.0.set(Some(current)));
+    }
+
+    /// Runs SAPI and module startup. Caller must ensure the engine isn't running.
+    pub(crate) fn start(
+        &mut self,
+        config: EngineBuilder,
+    ) -> Result<(), SapiError> {
+        #[cfg(feature = "tracing")]
+        info!("Initializing RiphtSapi");
+
+        config.check_security_policy()?;
+        let ini_block = config.ini_block()?;
+        let previous = state();
+
+        // SAFETY: The engine is not running (checked by callers while holding
+        // the lifecycle lock). All pointers/callbacks are 'static or owned by
+        // `self` for as long as the engine runs.
+        let result = unsafe {
+            #[cfg(php_zts)]
+            tsrm::startup();
+
+            register_callbacks();
+            ffi::sapi_module.ini_entries = ini_block.as_ptr();
+
+            #[cfg(feature = "tracing")]
+            trace!("Starting SAPI");
+
+            ffi::sapi_startup(&mut ffi::sapi_module);
+
+            #[cfg(feature = "tracing")]
+            trace!("Initializing SAPI module");
+
+            ffi::php_module_startup(&mut ffi::sapi_module, std::ptr::null_mut())
+        };
+
+        if result == ffi::FAILURE {
+            #[cfg(feature = "tracing")]
+            error!("SAPI module startup failed");
+
+            // SAFETY: sapi_startup succeeded above; undo it.
+            unsafe {
+                ffi::sapi_shutdown();
+                ffi::sapi_module.ini_entries = std::ptr::null();
+
+                #[cfg(php_zts)]
+                tsrm::shutdown();
+            }
+
+            set_state(match previous {
+                EngineState::Uninitialized => EngineState::Uninitialized,
+                _ => EngineState::ShutDown,
+            });
+
+            return Err(SapiError::InitializationFailed(
+                "SAPI module initialization failed".to_string(),
+            ));
+        }
+
+        #[cfg(feature = "tracing")]
+        info!("SAPI module initialized");
+
+        // SAFETY: Module startup succeeded, so the session extension and
+        // Zend's stream handlers are set up.
+        unsafe {
+            #[cfg(feature = "session")]
+            session::install(config.session_store.as_ref());
+
+            vfs::install(config.virtual_fs.as_ref());
+            mail::install(config.mail_sink.as_ref());
+            stream_wrapper::install(&config.stream_wrappers);
+            http_mock::install(config.http_mock.as_ref());
+            input_filter::install(config.input_filter.as_ref());
+            body_parser::install(&config.body_parsers);
+            policy::install();
+        }
+
+        set_default_policies(DefaultPolicies {
+            buffer_policy: config.buffer_policy,
+            security_policy: config
+                .security_policy
+                .clone()
+                .map(Arc::new),
+        });
+
+        self.config = Some(config);
+        self.ini_block = Some(ini_block);
+        self.claim();
+        set_state(EngineState::Running);
+
+        Ok(())
+    }
+
+    /// Runs module and SAPI shutdown. No-op unless the engine is running.
+    pub(crate) fn stop(&mut self) {
+        if !state().is_running() {
+            return;
+        }
+
+        #[cfg(feature = "tracing")]
+        info!("Shutting down RiphtSapi");
+
+        // SAFETY: The engine is running and no request is in flight
+        // (checked by callers).
+        unsafe {
+            ffi::php_module_shutdown();
+            ffi::sapi_shutdown();
+            ffi::sapi_module.ini_entries = std::ptr::null();
+
+            #[cfg(php_zts)]
+            tsrm::shutdown();
+        }
+
+        self.ini_block = None;
+        self.owner = None;
+        set_state(EngineState::ShutDown);
+    }
+
+    /// Shuts the engine down (if running) and starts it with `config`.
+    pub(crate) fn restart(
+        &mut self,
+        config: EngineBuilder,
+    ) -> Result<(), SapiError> {
+        // Refused before stopping, so the running engine stays up.
+        config.check_security_policy()?;
+
+        self.stop();
+        set_state(EngineState::Restarting);
+        self.start(config)
+    }
+}
+
+pub(crate) fn lock() -> MutexGuard<'static, Lifecycle> {
+    LIFECYCLE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Fails if another live thread owns the engine.
+///
+/// A running engine whose owner has exited is adopted by the caller.
+#[cfg(not(php_zts))]
+pub(crate) fn check_thread() -> Result<(), SapiError> {
+    let mut lifecycle = lock();
+
+    if lifecycle.owner.is_none() && state().is_running() {
+        lifecycle.claim();
+    }
+
+    lifecycle.check_thread()
+}
+
+/// Prepares the calling thread to execute requests.
+///
+/// ZTS builds give every thread its own globals, so any thread may execute
+/// once it has TSRM storage.
+#[cfg(php_zts)]
+pub(crate) fn check_thread() -> Result<(), SapiError> {
+    let lifecycle = lock();
+
+    if state().is_running() && lifecycle.owner != Some(thread::current().id()) {
+        tsrm::attach();
+    }
+
+    Ok(())
+}
+
+fn set_default_policies(policies: DefaultPolicies) {
+    *DEFAULT_POLICIES
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = policies;
+}
+
+fn default_policies() -> RwLockReadGuard<'static, DefaultPolicies> {
+    DEFAULT_POLICIES
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Buffer policy of the running engine's configuration.
+pub(crate) fn default_buffer_policy() -> BufferPolicy {
+    default_policies()
+        .buffer_policy
+        .unwrap_or_else(BufferPolicy::env_default)
+}
+
+/// Security policy of the running engine's configuration.
+pub(crate) fn default_security_policy() -> Option<Arc<SecurityPolicy>> {
+    default_policies()
+        .security_policy
+        .clone()
+}
+
+pub(crate) fn state() -> EngineState {
+    EngineState::from_raw(STATE.load(Ordering::Acquire))
+}
+
+fn set_state(state: EngineState) {
+    STATE.store(state as u8, Ordering::Release);
+}
+
+/// Points `sapi_module` at the Ripht callbacks.
+unsafe fn register_callbacks() {
+    ffi::sapi_module.name = SAPI_NAME.as_ptr() as *mut _;
+    ffi::sapi_module.pretty_name = SAPI_PRETTY_NAME.as_ptr() as *mut _;
+
+    ffi::sapi_module.startup = Some(callbacks::ripht_sapi_startup);
+    ffi::sapi_module.shutdown = Some(callbacks::ripht_sapi_shutdown);
+    ffi::sapi_module.activate = Some(callbacks::ripht_sapi_activate);
+    ffi::sapi_module.deactivate = Some(callbacks::ripht_sapi_deactivate);
+
+    ffi::sapi_module.ub_write = Some(callbacks::ripht_sapi_ub_write);
+    ffi::sapi_module.flush = Some(callbacks::ripht_sapi_flush);
+
+    ffi::sapi_module.send_headers = Some(callbacks::ripht_sapi_send_headers);
+    ffi::sapi_module.send_header = Some(callbacks::ripht_sapi_send_header);
+
+    ffi::sapi_module.read_post = Some(callbacks::ripht_sapi_read_post);
+    ffi::sapi_module.read_cookies = Some(callbacks::ripht_sapi_read_cookies);
+
+    ffi::sapi_module.register_server_variables =
+        Some(callbacks::ripht_sapi_register_server_variables);
+
+    ffi::sapi_module.log_message = Some(callbacks::ripht_sapi_log_message);
+    ffi::sapi_module.get_request_time =
+        Some(callbacks::ripht_sapi_get_request_time);
+    ffi::sapi_module.getenv = Some(callbacks::ripht_sapi_getenv);
+
+    ffi::sapi_module.php_ini_ignore = 0;
+    ffi::sapi_module.php_ini_ignore_cwd = 1;
+
+    ffi::sapi_module.input_filter = Some(callbacks::ripht_sapi_input_filter);
+    ffi::sapi_module.default_post_reader =
+        Some(callbacks::ripht_sapi_default_post_reader);
+    ffi::sapi_module.treat_data = Some(callbacks::ripht_sapi_treat_data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ini_block_includes_defaults_then_overrides() {
+        let block = EngineBuilder::new()
+            .with_ini("memory_limit", "256M")
+            .ini_block()
+            .unwrap();
+
+        let text = block.to_str().unwrap();
+        assert!(text.starts_with("variables_order=EGPCS\n"));
+        assert!(text.ends_with("memory_limit=256M\n"));
+    }
+
+    #[cfg(feature = "session")]
+    #[test]
+    fn test_session_store_selects_save_handler() {
+        let block = EngineBuilder::new()
+            .with_session_store(session::InMemorySessionStore::new())
+            .with_ini("session.gc_probability", "0")
+            .ini_block()
+            .unwrap();
+
+        assert!(block
+            .to_str()
+            .unwrap()
+            .contains(
+                "session.save_handler=ripht\nsession.gc_probability=0\n"
+            ));
+    }
+
+    #[test]
+    fn test_ini_block_rejects_newlines() {
+        let err = EngineBuilder::new()
+            .with_ini("memory_limit", "1M\nmax_execution_time=0")
+            .ini_block()
+            .unwrap_err();
+        assert!(matches!(err, SapiError::InvalidIniValue));
+
+        let err = EngineBuilder::new()
+            .with_ini("bad=key", "1")
+            .ini_block()
+            .unwrap_err();
+        assert!(matches!(err, SapiError::InvalidIniKey));
+    }
+
+    #[test]
+    fn test_check_thread_rejects_foreign_threads() {
+        let lifecycle = Lifecycle {
+            config: None,
+            ini_block: None,
+            owner: Some(thread::current().id()),
+        };
+        assert!(lifecycle
+            .check_thread()
+            .is_ok());
+
+        let foreign = thread::spawn(|| thread::current().id())
+            .join()
+            .unwrap();
+        let lifecycle = Lifecycle {
+            owner: Some(foreign),
+            ..lifecycle
+        };
+        assert!(matches!(
+            lifecycle.check_thread(),
+            Err(SapiError::WrongThread { owner, .. }) if owner == foreign
+        ));
+    }
+
+    #[test]
+    fn test_engine_state_round_trip() {
+        for state in [
+            EngineState::Uninitialized,
+            EngineState::Running,
+            EngineState::Restarting,
+            EngineState::ShutDown,
+        ] {
+            assert_eq!(EngineState::from_raw(state as u8), state);
+        }
+    }
+}
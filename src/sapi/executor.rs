@@ -3,19 +3,28 @@
 //! Manages the PHP request lifecycle: startup, script execution, and shutdown.
 
 use std::any::TypeId;
+use std::cell::RefCell;
 use std::ffi::CString;
+use std::time::Instant;
 
 use thiserror::Error;
 
 #[cfg(feature = "tracing")]
 use tracing::{debug, error, trace};
 
+use super::body_parser;
+use super::context_pool::{ContextPool, PoolStats};
 use super::ffi;
-use super::server_context::ServerContext;
+use super::http_mock;
+use super::server_context::{
+    memory_limit_from_message, OutputCallback, ServerContext,
+};
+use super::stream_wrapper;
 use super::SapiError;
 use crate::execution::{
-    ExecutionContext, ExecutionHooks, ExecutionResult, NoOpHooks, OutputAction,
-    ResponseHeader,
+    ExecutionContext, ExecutionHooks, ExecutionMetrics, ExecutionResult,
+    MemoryUsage, NoOpHooks, OutputAction, RequestAction, ResponseHeader,
+    SecurityPolicy,
 };
 
 /// Errors that can occur during PHP script execution.
@@ -31,24 +40,73 @@ pub enum ExecutionError {
     #[error("PHP engine not initialized")]
     NotInitialized,
 
+    #[error(
+        "PHP engine is owned by thread {owner:?}, called from {current:?}"
+    )]
+    WrongThread {
+        owner: std::thread::ThreadId,
+        current: std::thread::ThreadId,
+    },
+
     #[error("Request startup failed")]
     StartupFailed,
+
+    #[error("Worker process {pid} crashed (signal {signal:?})")]
+    WorkerCrashed { pid: u32, signal: Option<i32> },
+
+    #[error("Worker communication failed: {0}")]
+    WorkerIo(String),
+
+    #[error("Worker sent or received a malformed message: {0}")]
+    WorkerProtocol(String),
+
+    #[error("Output exceeded the {limit} byte limit")]
+    OutputLimitExceeded { limit: usize },
+
+    #[error("Memory limit of {limit} bytes exhausted")]
+    MemoryLimitExceeded { limit: usize, peak_usage: usize },
+
+    #[error("Security policy can't be enforced: {0}")]
+    UnenforceablePolicy(String),
+}
+
+impl From<SapiError> for ExecutionError {
+    fn from(err: SapiError) -> Self {
+        match err {
+            SapiError::WrongThread { owner, current } => {
+                Self::WrongThread { owner, current }
+            }
+            _ => Self::NotInitialized,
+        }
+    }
 }
 
 /// Executes PHP scripts within an initialized SAPI.
+///
+/// An executor recycles per-request allocations (server context, output
+/// buffer, header vectors, `CString` storage) between the requests it runs.
+/// Keep one around for hot loops instead of calling
+/// [`RiphtSapi::execute`](super::RiphtSapi::execute) each time.
 pub struct Executor<'sapi> {
     sapi: &'sapi super::RiphtSapi,
+    pool: RefCell<ContextPool>,
 }
 
 impl<'sapi> Executor<'sapi> {
     pub(super) fn new(
         sapi: &'sapi super::RiphtSapi,
     ) -> Result<Self, SapiError> {
-        if !sapi.is_initialized() {
-            return Err(SapiError::NotInitialized);
-        }
+        sapi.ensure_usable()?;
 
-        Ok(Self { sapi })
+        Ok(Self {
+            sapi,
+            pool: RefCell::default(),
+        })
+    }
+
+    /// Allocation statistics for this executor's context pool.
+    pub fn pool_stats(&self) -> PoolStats {
+        self.pool.borrow().stats()
     }
 
     pub fn execute(
@@ -66,204 +124,296 @@ impl<'sapi> Executor<'sapi> {
     where
         F: FnMut(&[u8]) + 'static,
     {
-        #[cfg(feature = "tracing")]
-        debug!(
-            script_path = %ctx.script_path.display(),
-            "Executing PHP (streaming)"
-        );
-
-        if !self.sapi.is_initialized() {
-            return Err(ExecutionError::NotInitialized);
-        }
-
-        if !ctx.script_path.exists() {
-            return Err(ExecutionError::ScriptNotFound(
-                ctx.script_path.clone(),
-            ));
-        }
-
-        let script_cstr = ctx.path_as_cstring()?;
-
-        let mut server_ctx = Box::<ServerContext>::from(ctx);
-        server_ctx.set_output_callback(on_output);
-
-        // SAFETY: Ownership transfer for request execution. ServerContext is boxed,
-        // stored in sapi_globals.server_context, then reclaimed after php_request_shutdown.
-        // All error paths clean up properly.
-        unsafe {
-            let ctx_ptr = Box::into_raw(server_ctx);
-            ffi::sapi_globals.server_context = ctx_ptr as *mut std::ffi::c_void;
-            Self::setup_globals(&*ctx_ptr);
-
-            if ffi::php_request_startup() == ffi::FAILURE {
-                let _ = Box::from_raw(ctx_ptr);
-                ffi::php_request_shutdown(std::ptr::null_mut());
-                ffi::sapi_globals.server_context = std::ptr::null_mut();
-                return Err(ExecutionError::StartupFailed);
-            }
-
-            Self::apply_ini_overrides(&*ctx_ptr);
-            Self::run_script(&script_cstr);
-
-            ffi::sapi_globals.post_read = 1;
-            ffi::php_request_shutdown(std::ptr::null_mut());
-            ffi::sapi_globals.server_context = std::ptr::null_mut();
-
-            let server_ctx = Box::from_raw(ctx_ptr);
-            Self::cleanup_globals();
+        self.execute_streaming_with_hooks(ctx, on_output, NoOpHooks)
+    }
 
-            Ok((*server_ctx).into_result(Vec::new()))
-        }
+    /// Streams output to `on_output` while driving `hooks`.
+    ///
+    /// [`ExecutionHooks::on_response_head`] runs before the first chunk, so
+    /// streamed responses carry the rewritten status and headers.
+    /// [`ExecutionHooks::on_output`] isn't called; the body goes to `on_output`.
+    pub fn execute_streaming_with_hooks<F, H>(
+        &self,
+        ctx: ExecutionContext,
+        on_output: F,
+        hooks: H,
+    ) -> Result<ExecutionResult, ExecutionError>
+    where
+        F: FnMut(&[u8]) + 'static,
+        H: ExecutionHooks + 'static,
+    {
+        self.run(ctx, hooks, Some(Box::new(on_output)))
     }
 
     pub fn execute_with_hooks<H: ExecutionHooks + 'static>(
         &self,
         ctx: ExecutionContext,
+        hooks: H,
+    ) -> Result<ExecutionResult, ExecutionError> {
+        self.run(ctx, hooks, None)
+    }
+
+    fn run<H: ExecutionHooks + 'static>(
+        &self,
+        mut ctx: ExecutionContext,
         mut hooks: H,
+        on_output: Option<OutputCallback>,
     ) -> Result<ExecutionResult, ExecutionError> {
+        let received = Instant::now();
+        let streaming = on_output.is_some();
+
         #[cfg(feature = "tracing")]
         debug!(
             script_path = %ctx.script_path.display(),
+            streaming,
             "Executing PHP"
         );
 
-        if !self.sapi.is_initialized() {
+        if let Err(e) = self.sapi.ensure_usable() {
+            #[cfg(feature = "tracing")]
+            error!(error = %e, "Execute on unusable engine");
+            return Err(e.into());
+        }
+
+        if let RequestAction::Respond(result) =
+            hooks.on_request_received(&mut ctx)
+        {
             #[cfg(feature = "tracing")]
-            error!("Execute before init");
-            return Err(ExecutionError::NotInitialized);
+            debug!(status = result.status_code(), "Request answered by hook");
+
+            hooks.on_request_finished(&result);
+            return Ok(result);
         }
 
-        if !ctx.script_path.exists() {
+        if !super::script_exists(&ctx.script_path) {
             return Err(ExecutionError::ScriptNotFound(
                 ctx.script_path.clone(),
             ));
         }
 
+        if let Some(name) = ctx
+            .security_policy
+            .as_ref()
+            .and_then(SecurityPolicy::uninterceptable_function)
+        {
+            return Err(ExecutionError::UnenforceablePolicy(format!(
+                "{name}() can't be disabled"
+            )));
+        }
+
         let script_cstr = ctx.path_as_cstring()?;
         let script_path = ctx.script_path.clone();
 
         hooks.on_context_created();
 
-        let server_ctx = Box::<ServerContext>::from(ctx);
+        let converting = Instant::now();
+        let mut server_ctx = self
+            .pool
+            .borrow_mut()
+            .acquire(ctx);
+        server_ctx.output_callback = on_output;
+
+        // Until the request is shut down, `hooks` is only reached through
+        // this pointer, which `send_headers` also uses via the context.
+        let hooks_ptr: *mut H = &mut hooks;
+        if TypeId::of::<H>() != TypeId::of::<NoOpHooks>() {
+            server_ctx.response_hooks =
+                Some(hooks_ptr as *mut dyn ExecutionHooks);
+        }
 
-        // SAFETY: Same ownership transfer pattern as execute_streaming.
-        unsafe {
+        // SAFETY: Ownership transfer for request execution. ServerContext is boxed,
+        // stored in sapi_globals.server_context, then reclaimed after php_request_shutdown.
+        // All error paths clean up properly. `hooks_ptr` outlives the request.
+        let (mut server_ctx, _success) = unsafe {
             let ctx_ptr = Box::into_raw(server_ctx);
-            ffi::sapi_globals.server_context = ctx_ptr as *mut std::ffi::c_void;
+            ffi::sg().server_context = ctx_ptr as *mut std::ffi::c_void;
             Self::setup_globals(&*ctx_ptr);
+            (*ctx_ptr).metrics.context = converting.elapsed();
 
-            hooks.on_request_starting();
+            (*hooks_ptr).on_request_starting();
 
             #[cfg(feature = "tracing")]
             trace!("Starting PHP request");
 
+            body_parser::activate();
+
+            let phase = Instant::now();
             let startup_result = ffi::php_request_startup();
 
             if startup_result == ffi::FAILURE {
                 #[cfg(feature = "tracing")]
                 error!("Request startup failed");
-                let _ = Box::from_raw(ctx_ptr);
+                // Shutdown still calls back into the context and reads the
+                // request globals pointing into it; recycle it only after.
                 ffi::php_request_shutdown(std::ptr::null_mut());
-                ffi::sapi_globals.server_context = std::ptr::null_mut();
+                ffi::sg().server_context = std::ptr::null_mut();
+                Self::cleanup_globals();
+                self.pool
+                    .borrow_mut()
+                    .release(Box::from_raw(ctx_ptr));
                 return Err(ExecutionError::StartupFailed);
             }
 
             Self::apply_ini_overrides(&*ctx_ptr);
+            Self::apply_memory_limit(&*ctx_ptr);
+            stream_wrapper::activate();
+            http_mock::activate();
+            if let Some(seed) = (*ctx_ptr).random_seed {
+                ffi::php_mt_srand(seed);
+            }
+            (*ctx_ptr).metrics.startup = phase.elapsed();
 
-            hooks.on_request_started();
-            hooks.on_script_executing(&script_path);
+            (*hooks_ptr).on_request_started();
+            (*hooks_ptr).on_script_executing(&script_path);
 
             #[cfg(feature = "tracing")]
             trace!("Executing script");
 
+            let phase = Instant::now();
             let exec_result = Self::run_script(&script_cstr);
+            (*ctx_ptr).metrics.script = phase.elapsed();
             let success = exec_result != ffi::FAILURE;
-            hooks.on_script_executed(success);
+            (*hooks_ptr).on_script_executed(success);
+            (*ctx_ptr).memory_limit_exceeded = Self::memory_limit_error();
+
+            (*hooks_ptr).on_request_finishing();
 
-            hooks.on_request_finishing();
+            // Shutdown resets the memory manager's counters.
+            (*ctx_ptr).memory_usage = MemoryUsage::new(
+                ffi::zend_memory_usage(false),
+                ffi::zend_memory_peak_usage(false),
+            );
 
             #[cfg(feature = "tracing")]
             trace!("Shutting down request");
 
-            ffi::sapi_globals.post_read = 1;
+            ffi::sg().post_read = 1;
+            let phase = Instant::now();
             ffi::php_request_shutdown(std::ptr::null_mut());
-            ffi::sapi_globals.server_context = std::ptr::null_mut();
-
-            let mut server_ctx = Box::from_raw(ctx_ptr);
+            (*ctx_ptr).metrics.shutdown = phase.elapsed();
+            ffi::sg().server_context = std::ptr::null_mut();
 
             // SAFETY: Defensive cleanup of request-related pointers.
             Self::cleanup_globals();
 
-            let headers: Vec<ResponseHeader> =
-                if TypeId::of::<H>() == TypeId::of::<NoOpHooks>() {
-                    std::mem::take(&mut server_ctx.response_headers)
-                } else {
-                    server_ctx
-                        .response_headers
-                        .iter()
-                        .filter(|h| hooks.on_header(h.name(), h.value()))
-                        .cloned()
-                        .collect()
-                };
-
-            let status = server_ctx.status_code();
-            hooks.on_status(status);
-
-            for message in &server_ctx.messages {
-                hooks.on_php_message(message);
-            }
+            (Box::from_raw(ctx_ptr), success)
+        };
 
-            let body = match hooks.on_output(&server_ctx.output_buffer) {
-                OutputAction::Continue => server_ctx.output_buffer,
-                OutputAction::Done => Vec::new(),
-            };
+        server_ctx.response_hooks = None;
 
+        if let Err(e) = Self::check_limits(&server_ctx) {
             #[cfg(feature = "tracing")]
-            debug!(
-                status = status,
-                body_len = body.len(),
-                headers_count = headers.len(),
-                messages_count = server_ctx.messages.len(),
-                "{}",
-                if success {
-                    "Execution succeeded"
-                } else {
-                    "Execution failed"
-                }
-            );
+            error!(error = %e, "Request aborted");
+            self.pool
+                .borrow_mut()
+                .release(server_ctx);
+            return Err(e);
+        }
 
-            let result = ExecutionResult::new(
-                status,
-                body,
-                headers,
-                server_ctx.messages,
-            );
+        let headers: Vec<ResponseHeader> =
+            if TypeId::of::<H>() == TypeId::of::<NoOpHooks>() {
+                server_ctx
+                    .response_headers
+                    .drain(..)
+                    .collect()
+            } else {
+                server_ctx
+                    .response_headers
+                    .drain(..)
+                    .filter(|h| hooks.on_header(h.name(), h.value()))
+                    .collect()
+            };
 
-            hooks.on_request_finished(&result);
+        let status = server_ctx.status_code();
+        hooks.on_status(status);
 
-            Ok(result)
+        for message in &server_ctx.messages {
+            hooks.on_php_message(message);
         }
+
+        let body = if streaming {
+            Vec::new()
+        } else {
+            match hooks.on_output(&server_ctx.output_buffer) {
+                OutputAction::Continue => {
+                    ContextPool::take_body(&mut server_ctx)
+                }
+                OutputAction::Done => Vec::new(),
+            }
+        };
+
+        #[cfg(feature = "tracing")]
+        debug!(
+            status = status,
+            body_len = body.len(),
+            headers_count = headers.len(),
+            messages_count = server_ctx.messages.len(),
+            "{}",
+            if _success {
+                "Execution succeeded"
+            } else {
+                "Execution failed"
+            }
+        );
+
+        let metrics = ExecutionMetrics {
+            total: received.elapsed(),
+            output_bytes: server_ctx.output_len,
+            body_bytes: body.len(),
+            header_bytes: headers
+                .iter()
+                .map(|h| h.name().len() + h.value().len())
+                .sum(),
+            ..server_ctx.metrics
+        };
+
+        let result = ExecutionResult::new(
+            status,
+            body,
+            headers,
+            server_ctx
+                .messages
+                .drain(..)
+                .collect(),
+        )
+        .with_truncated(server_ctx.truncated)
+        .with_sent_status_line(server_ctx.status_line.take())
+        .with_informational(std::mem::take(&mut server_ctx.informational))
+        .with_security_violations(std::mem::take(
+            &mut server_ctx.security_violations,
+        ))
+        .with_memory_usage(server_ctx.memory_usage)
+        .with_metrics(metrics)
+        .with_mail(std::mem::take(&mut server_ctx.mail));
+
+        self.pool
+            .borrow_mut()
+            .release(server_ctx);
+
+        hooks.on_request_finished(&result);
+
+        Ok(result)
     }
 
     /// Populates `sapi_globals.request_info` from the server context.
     unsafe fn setup_globals(ctx: &ServerContext) {
-        ffi::sapi_globals
+        ffi::sg()
             .request_info
             .request_method = ctx.request_method_ptr();
 
-        ffi::sapi_globals
+        ffi::sg()
             .request_info
             .content_type = ctx.content_type_ptr();
 
-        ffi::sapi_globals
+        ffi::sg()
             .request_info
             .content_length = ctx.post_data.len() as i64;
 
-        ffi::sapi_globals
+        ffi::sg()
             .request_info
             .query_string = ctx.query_string_ptr();
 
-        ffi::sapi_globals
+        ffi::sg()
             .sapi_headers
             .http_response_code = 200;
     }
@@ -279,23 +429,87 @@ impl<'sapi> Executor<'sapi> {
         exec_result
     }
 
+    /// Limit from the script's last error, if that was PHP's fatal
+    /// memory-exhaustion error.
+    ///
+    /// Read before request shutdown, which clears the last error.
+    unsafe fn memory_limit_error() -> Option<usize> {
+        let pg = ffi::pg();
+        if pg.last_error_type != ffi::E_ERROR
+            || pg
+                .last_error_message
+                .is_null()
+        {
+            return None;
+        }
+
+        let message = ffi::zstr_bytes(pg.last_error_message);
+
+        memory_limit_from_message(std::str::from_utf8(message).ok()?)
+    }
+
+    /// Fails if the request was aborted for exceeding its memory or output
+    /// limit.
+    fn check_limits(server_ctx: &ServerContext) -> Result<(), ExecutionError> {
+        if let Some(limit) = server_ctx.memory_limit_exceeded {
+            return Err(ExecutionError::MemoryLimitExceeded {
+                limit,
+                peak_usage: server_ctx.memory_usage.peak,
+            });
+        }
+
+        match server_ctx
+            .buffer_policy
+            .max_size()
+        {
+            Some(limit) if server_ctx.output_limit_exceeded => {
+                Err(ExecutionError::OutputLimitExceeded { limit })
+            }
+            _ => Ok(()),
+        }
+    }
+
     /// Clears request-related pointers to prevent stale access between requests.
     unsafe fn cleanup_globals() {
-        ffi::sapi_globals.server_context = std::ptr::null_mut();
+        ffi::sg().server_context = std::ptr::null_mut();
 
-        ffi::sapi_globals
+        ffi::sg()
             .request_info
             .content_type = std::ptr::null();
 
-        ffi::sapi_globals
+        ffi::sg()
             .request_info
             .query_string = std::ptr::null_mut();
 
-        ffi::sapi_globals
+        ffi::sg()
             .request_info
             .cookie_data = std::ptr::null_mut();
     }
 
+    /// Applies the context's typed `memory_limit`, after INI overrides so it
+    /// wins over them.
+    unsafe fn apply_memory_limit(ctx: &ServerContext) {
+        let Some(bytes) = ctx.memory_limit else {
+            return;
+        };
+        let init = ffi::zend_string_init_interned.expect("PHP not initialized");
+
+        let key = "memory_limit";
+        let value = bytes.to_string();
+        let name = init(key.as_ptr() as *const _, key.len(), true);
+        if name.is_null() {
+            return;
+        }
+
+        ffi::zend_alter_ini_entry_chars(
+            name,
+            value.as_ptr() as *const _,
+            value.len(),
+            ffi::ZEND_INI_USER | ffi::ZEND_INI_SYSTEM,
+            ffi::ZEND_INI_STAGE_RUNTIME,
+        );
+    }
+
     /// Applies per-request INI overrides from the server context.
     unsafe fn apply_ini_overrides(ctx: &ServerContext) {
         if ctx.ini_overrides.is_empty() {
@@ -305,6 +519,15 @@ impl<'sapi> Executor<'sapi> {
         let init = ffi::zend_string_init_interned.expect("PHP not initialized");
 
         for (key, value) in &ctx.ini_overrides {
+            // Directives set by the security policy can't be loosened.
+            if ctx
+                .security_policy
+                .as_ref()
+                .is_some_and(|policy| policy.governs(&key.to_string_lossy()))
+            {
+                continue;
+            }
+
             // SAFETY: Create an interned zend_string for the INI key.
             // CString::as_ptr() returns a valid null-terminated string.
             let name = init(key.as_ptr(), key.as_bytes().len(), true);
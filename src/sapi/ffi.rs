@@ -13,9 +13,39 @@ pub const FAILURE: c_int = -1;
 pub const SAPI_HEADER_SENT_SUCCESSFULLY: c_int = 1;
 pub const SAPI_HEADER_SEND_FAILED: c_int = 0;
 pub const ZEND_HANDLE_FILENAME: u8 = 0;
+pub const ZEND_HANDLE_STREAM: u8 = 2;
+pub const ZEND_INTERNAL_CLASS: c_char = 1;
+pub const ZEND_FETCH_CLASS_NO_AUTOLOAD: u32 = 0x80;
 pub const ZEND_INI_USER: c_int = 1;
 pub const ZEND_INI_SYSTEM: c_int = 4;
+pub const ZEND_INI_STAGE_ACTIVATE: c_int = 4;
 pub const ZEND_INI_STAGE_RUNTIME: c_int = 16;
+pub const IS_NULL: u8 = 1;
+pub const IS_FALSE: u8 = 2;
+pub const IS_TRUE: u8 = 3;
+pub const IS_LONG: u8 = 4;
+pub const IS_DOUBLE: u8 = 5;
+pub const IS_STRING: u8 = 6;
+pub const IS_ARRAY: u8 = 7;
+pub const IS_REFERENCE: u8 = 10;
+pub const HASH_KEY_IS_STRING: c_int = 1;
+pub const PARSE_POST: c_int = 0;
+pub const PARSE_GET: c_int = 1;
+pub const PARSE_COOKIE: c_int = 2;
+pub const PARSE_SERVER: c_int = 5;
+pub const TEMP_STREAM_DEFAULT: c_int = 0;
+pub const SEEK_SET: c_int = 0;
+pub const SEEK_CUR: c_int = 1;
+pub const SEEK_END: c_int = 2;
+pub const E_ERROR: c_int = 1;
+pub const E_WARNING: c_int = 2;
+pub const PHP_STREAM_OPTION_CHECK_LIVENESS: c_int = 12;
+pub const PHP_STREAM_OPTION_RETURN_OK: c_int = 0;
+pub const PHP_STREAM_OPTION_RETURN_ERR: c_int = -1;
+pub const PHP_STREAM_OPTION_RETURN_NOTIMPL: c_int = -2;
+
+pub type HashPosition = u32;
+pub type zend_long = i64;
 
 #[cfg(unix)]
 pub type uid_t = libc::uid_t;
@@ -97,6 +127,77 @@ impl Default for zend_llist {
     }
 }
 
+pub type zend_create_object_t =
+    Option<unsafe extern "C" fn(*mut zend_class_entry) -> *mut zend_object>;
+
+/// Leading fields of `zend_class_entry`, up to `create_object`.
+#[repr(C)]
+pub struct zend_class_entry {
+    pub type_: c_char,
+    pub name: *mut zend_string,
+    /// `parent` once the class is linked (`parent_name` before).
+    pub parent: *mut zend_class_entry,
+    pub refcount: c_int,
+    pub ce_flags: u32,
+    pub default_properties_count: c_int,
+    pub default_static_members_count: c_int,
+    pub default_properties_table: *mut zval,
+    pub default_static_members_table: *mut zval,
+    pub static_members_table: *mut c_void,
+    pub function_table: HashTable,
+    pub properties_info: HashTable,
+    pub constants_table: HashTable,
+    pub mutable_data: *mut c_void,
+    pub inheritance_cache: *mut c_void,
+    pub properties_info_table: *mut c_void,
+    pub constructor: *mut zend_function,
+    pub destructor: *mut zend_function,
+    pub clone: *mut zend_function,
+    pub __get: *mut zend_function,
+    pub __set: *mut zend_function,
+    pub __unset: *mut zend_function,
+    pub __isset: *mut zend_function,
+    pub __call: *mut zend_function,
+    pub __callstatic: *mut zend_function,
+    pub __tostring: *mut zend_function,
+    pub __debugInfo: *mut zend_function,
+    pub __serialize: *mut zend_function,
+    pub __unserialize: *mut zend_function,
+    pub default_object_handlers: *const c_void,
+    pub iterator_funcs_ptr: *mut c_void,
+    pub arrayaccess_funcs_ptr: *mut c_void,
+    /// Also `interface_gets_implemented` for interfaces.
+    pub create_object: zend_create_object_t,
+}
+
+#[repr(C)]
+pub struct zend_object {
+    _private: [u8; 0],
+}
+
+/// Leading fields shared by every `zend_function` variant (`common`).
+#[repr(C)]
+pub struct zend_function {
+    pub type_: u8,
+    pub arg_flags: [u8; 3],
+    pub fn_flags: u32,
+    pub function_name: *mut zend_string,
+    pub scope: *mut zend_class_entry,
+}
+
+/// Leading fields of `zend_execute_data`.
+#[repr(C)]
+pub struct zend_execute_data {
+    pub opline: *const c_void,
+    pub call: *mut zend_execute_data,
+    pub return_value: *mut zval,
+    pub func: *mut zend_function,
+    pub This: zval,
+}
+
+pub type zend_execute_internal_t =
+    Option<unsafe extern "C" fn(*mut zend_execute_data, *mut zval)>;
+
 #[repr(C)]
 pub struct zend_function_entry {
     _private: [u8; 0],
@@ -143,6 +244,19 @@ impl Default for sapi_headers_struct {
     }
 }
 
+/// Handler for one request body content type (`sapi_post_entry`).
+#[repr(C)]
+pub struct sapi_post_entry {
+    pub content_type: *mut c_char,
+    pub content_type_len: u32,
+    pub post_reader: Option<unsafe extern "C" fn()>,
+    pub post_handler: Option<unsafe extern "C" fn(*mut c_char, *mut c_void)>,
+}
+
+// Only ever read by PHP after registration.
+unsafe impl Send for sapi_post_entry {}
+unsafe impl Sync for sapi_post_entry {}
+
 #[repr(C)]
 pub struct sapi_request_info {
     pub request_method: *const c_char,
@@ -191,6 +305,76 @@ pub struct sapi_globals_struct {
     pub request_parse_body_context: sapi_request_parse_body_context,
 }
 
+#[repr(C)]
+pub struct arg_separators {
+    pub output: *mut c_char,
+    pub input: *mut c_char,
+}
+
+/// Leading fields of `php_core_globals`, up to the last error.
+#[repr(C)]
+pub struct php_core_globals {
+    pub output_buffering: zend_long,
+    pub implicit_flush: bool,
+    pub enable_dl: bool,
+    pub display_errors: u8,
+    pub display_startup_errors: bool,
+    pub log_errors: bool,
+    pub ignore_repeated_errors: bool,
+    pub ignore_repeated_source: bool,
+    pub report_memleaks: bool,
+    pub output_handler: *mut c_char,
+    pub unserialize_callback_func: *mut c_char,
+    pub serialize_precision: zend_long,
+    pub memory_limit: zend_long,
+    pub max_input_time: zend_long,
+    pub error_log: *mut c_char,
+    pub doc_root: *mut c_char,
+    pub user_dir: *mut c_char,
+    pub include_path: *mut c_char,
+    pub open_basedir: *mut c_char,
+    pub open_basedir_modified: bool,
+    pub extension_dir: *mut c_char,
+    pub php_binary: *mut c_char,
+    pub sys_temp_dir: *mut c_char,
+    pub upload_tmp_dir: *mut c_char,
+    pub upload_max_filesize: zend_long,
+    pub error_append_string: *mut c_char,
+    pub error_prepend_string: *mut c_char,
+    pub auto_prepend_file: *mut c_char,
+    pub auto_append_file: *mut c_char,
+    pub input_encoding: *mut c_char,
+    pub internal_encoding: *mut c_char,
+    pub output_encoding: *mut c_char,
+    pub arg_separator: arg_separators,
+    pub variables_order: *mut c_char,
+    pub rfc1867_protected_variables: HashTable,
+    pub connection_status: i16,
+    pub ignore_user_abort: bool,
+    pub header_is_being_sent: u8,
+    pub tick_functions: zend_llist,
+    pub http_globals: [zval; 6],
+    pub expose_php: bool,
+    pub register_argc_argv: bool,
+    pub auto_globals_jit: bool,
+    pub html_errors: bool,
+    pub xmlrpc_errors: bool,
+    pub docref_root: *mut c_char,
+    pub docref_ext: *mut c_char,
+    pub xmlrpc_error_number: zend_long,
+    pub activated_auto_globals: [bool; 8],
+    pub modules_activated: bool,
+    pub file_uploads: bool,
+    pub during_request_startup: bool,
+    pub allow_url_fopen: bool,
+    pub enable_post_data_reading: bool,
+    pub report_zend_debug: bool,
+    pub last_error_type: c_int,
+    pub last_error_lineno: c_int,
+    pub last_error_message: *mut zend_string,
+    pub last_error_file: *mut zend_string,
+}
+
 #[repr(C)]
 pub struct sapi_module_struct {
     pub name: *mut c_char,
@@ -344,9 +528,332 @@ impl Default for zend_file_handle {
 
 #[repr(C)]
 pub struct zend_string {
+    pub gc: [u32; 2],
+    pub h: u64,
+    pub len: usize,
+    pub val: [c_char; 1],
+}
+
+/// Contents of a `zend_string`, i.e. `ZSTR_VAL` / `ZSTR_LEN`.
+#[inline]
+pub unsafe fn zstr_bytes<'a>(s: *const zend_string) -> &'a [u8] {
+    std::slice::from_raw_parts(
+        std::ptr::addr_of!((*s).val) as *const u8,
+        (*s).len,
+    )
+}
+
+/// Type of `zv` (`Z_TYPE`).
+#[inline]
+pub unsafe fn zval_type(zv: *const zval) -> u8 {
+    *(zv as *const u8).add(8)
+}
+
+/// Pointer payload of `zv` (`Z_STR`, `Z_ARR`, `Z_REF`, ...).
+#[inline]
+pub unsafe fn zval_ptr(zv: *const zval) -> *mut c_void {
+    *(zv as *const *mut c_void)
+}
+
+/// Follows a reference to the value it holds (`ZVAL_DEREF`).
+#[inline]
+pub unsafe fn zval_deref(zv: *const zval) -> *const zval {
+    if zval_type(zv) == IS_REFERENCE {
+        // `zend_reference`: an 8-byte GC header, then the value.
+        (zval_ptr(zv) as *const u8).add(8) as *const zval
+    } else {
+        zv
+    }
+}
+
+/// `ZVAL_BOOL`.
+#[inline]
+pub unsafe fn zval_set_bool(zv: *mut zval, value: bool) {
+    let type_info = if value { IS_TRUE } else { IS_FALSE };
+    *((zv as *mut u8).add(8) as *mut u32) = type_info as u32;
+}
+
+/// Arguments passed to the call (`ZEND_CALL_NUM_ARGS`).
+#[inline]
+pub unsafe fn zend_call_num_args(call: *const zend_execute_data) -> u32 {
+    // `This.u2.num_args`
+    *((std::ptr::addr_of!((*call).This) as *const u8).add(12) as *const u32)
+}
+
+/// `ZVAL_LONG`'s payload; only meaningful when the type is `IS_LONG`.
+#[inline]
+pub unsafe fn zval_long(zv: *const zval) -> i64 {
+    *(zv as *const i64)
+}
+
+/// `ZVAL_ARR`.
+#[inline]
+pub unsafe fn zval_set_array(zv: *mut zval, ht: *mut HashTable) {
+    // `IS_ARRAY_EX`: refcounted and collectable.
+    const IS_ARRAY_EX: u32 = IS_ARRAY as u32 | (1 << 8) | (1 << 9);
+
+    *(zv as *mut *mut HashTable) = ht;
+    *((zv as *mut u8).add(8) as *mut u32) = IS_ARRAY_EX;
+}
+
+/// `ZVAL_NULL`.
+#[inline]
+pub unsafe fn zval_set_null(zv: *mut zval) {
+    *((zv as *mut u8).add(8) as *mut u32) = IS_NULL as u32;
+}
+
+/// `ZVAL_LONG`.
+#[inline]
+pub unsafe fn zval_set_long(zv: *mut zval, value: i64) {
+    *(zv as *mut i64) = value;
+    *((zv as *mut u8).add(8) as *mut u32) = IS_LONG as u32;
+}
+
+/// `ZVAL_DOUBLE`.
+#[inline]
+pub unsafe fn zval_set_double(zv: *mut zval, value: f64) {
+    *(zv as *mut f64) = value;
+    *((zv as *mut u8).add(8) as *mut u32) = IS_DOUBLE as u32;
+}
+
+/// `ZVAL_STR` with a new, non-interned string.
+#[inline]
+pub unsafe fn zval_set_str(zv: *mut zval, s: *mut zend_string) {
+    // `IS_STRING_EX`: refcounted.
+    const IS_STRING_EX: u32 = IS_STRING as u32 | (1 << 8);
+
+    *(zv as *mut *mut zend_string) = s;
+    *((zv as *mut u8).add(8) as *mut u32) = IS_STRING_EX;
+}
+
+/// Copies the string held by `zv`, which must be `IS_STRING`.
+pub unsafe fn zval_string(zv: *const zval) -> String {
+    String::from_utf8_lossy(zstr_bytes(zval_ptr(zv) as *const _)).into_owned()
+}
+
+/// Visits each entry of `table` with its string key, if any.
+pub unsafe fn for_each_entry(
+    table: *mut HashTable,
+    mut visit: impl FnMut(Option<String>, *const zval),
+) {
+    let mut pos: HashPosition = 0;
+    zend_hash_internal_pointer_reset_ex(table, &mut pos);
+
+    loop {
+        let value = zend_hash_get_current_data_ex(table, &mut pos);
+        if value.is_null() {
+            break;
+        }
+
+        let mut key: *mut zend_string = std::ptr::null_mut();
+        let mut index = 0u64;
+        let kind =
+            zend_hash_get_current_key_ex(table, &mut key, &mut index, &pos);
+        let key = (kind == HASH_KEY_IS_STRING && !key.is_null())
+            .then(|| String::from_utf8_lossy(zstr_bytes(key)).into_owned());
+
+        visit(key, zval_deref(value));
+
+        if zend_hash_move_forward_ex(table, &mut pos) != SUCCESS {
+            break;
+        }
+    }
+}
+
+/// Leading fields of `php_stream`.
+#[repr(C)]
+pub struct php_stream {
+    pub ops: *const php_stream_ops,
+    pub abstract_: *mut c_void,
+}
+
+#[repr(C)]
+pub struct php_stream_ops {
+    pub write: Option<
+        unsafe extern "C" fn(*mut php_stream, *const c_char, usize) -> isize,
+    >,
+    pub read: Option<
+        unsafe extern "C" fn(*mut php_stream, *mut c_char, usize) -> isize,
+    >,
+    pub close: Option<unsafe extern "C" fn(*mut php_stream, c_int) -> c_int>,
+    pub flush: Option<unsafe extern "C" fn(*mut php_stream) -> c_int>,
+    pub label: *const c_char,
+    pub seek: Option<
+        unsafe extern "C" fn(*mut php_stream, i64, c_int, *mut i64) -> c_int,
+    >,
+    pub cast: Option<
+        unsafe extern "C" fn(*mut php_stream, c_int, *mut *mut c_void) -> c_int,
+    >,
+    pub stat: Option<
+        unsafe extern "C" fn(*mut php_stream, *mut php_stream_statbuf) -> c_int,
+    >,
+    pub set_option: Option<
+        unsafe extern "C" fn(
+            *mut php_stream,
+            c_int,
+            c_int,
+            *mut c_void,
+        ) -> c_int,
+    >,
+}
+
+#[repr(C)]
+pub struct php_stream_context {
     _private: [u8; 0],
 }
 
+#[repr(C)]
+pub struct php_stream_statbuf {
+    pub sb: zend_stat_t,
+}
+
+/// `stream_opener` / `dir_opener` (release builds, no `STREAMS_DC`).
+pub type php_stream_opener_t = Option<
+    unsafe extern "C" fn(
+        wrapper: *mut php_stream_wrapper,
+        filename: *const c_char,
+        mode: *const c_char,
+        options: c_int,
+        opened_path: *mut *mut zend_string,
+        context: *mut php_stream_context,
+    ) -> *mut php_stream,
+>;
+
+pub type php_stream_url_op_t = Option<
+    unsafe extern "C" fn(
+        wrapper: *mut php_stream_wrapper,
+        url: *const c_char,
+        options: c_int,
+        context: *mut php_stream_context,
+    ) -> c_int,
+>;
+
+#[repr(C)]
+pub struct php_stream_wrapper_ops {
+    pub stream_opener: php_stream_opener_t,
+    pub stream_closer: Option<
+        unsafe extern "C" fn(*mut php_stream_wrapper, *mut php_stream) -> c_int,
+    >,
+    pub stream_stat: Option<
+        unsafe extern "C" fn(
+            *mut php_stream_wrapper,
+            *mut php_stream,
+            *mut php_stream_statbuf,
+        ) -> c_int,
+    >,
+    pub url_stat: Option<
+        unsafe extern "C" fn(
+            *mut php_stream_wrapper,
+            *const c_char,
+            c_int,
+            *mut php_stream_statbuf,
+            *mut php_stream_context,
+        ) -> c_int,
+    >,
+    pub dir_opener: php_stream_opener_t,
+    pub label: *const c_char,
+    pub unlink: php_stream_url_op_t,
+    pub rename: Option<
+        unsafe extern "C" fn(
+            *mut php_stream_wrapper,
+            *const c_char,
+            *const c_char,
+            c_int,
+            *mut php_stream_context,
+        ) -> c_int,
+    >,
+    pub stream_mkdir: Option<
+        unsafe extern "C" fn(
+            *mut php_stream_wrapper,
+            *const c_char,
+            c_int,
+            c_int,
+            *mut php_stream_context,
+        ) -> c_int,
+    >,
+    pub stream_rmdir: php_stream_url_op_t,
+    pub stream_metadata: Option<
+        unsafe extern "C" fn(
+            *mut php_stream_wrapper,
+            *const c_char,
+            c_int,
+            *mut c_void,
+            *mut php_stream_context,
+        ) -> c_int,
+    >,
+}
+
+#[repr(C)]
+pub struct php_stream_wrapper {
+    pub wops: *const php_stream_wrapper_ops,
+    pub abstract_: *mut c_void,
+    pub is_url: c_int,
+}
+
+// Only ever read by PHP after registration.
+unsafe impl Sync for php_stream_ops {}
+unsafe impl Sync for php_stream_wrapper_ops {}
+unsafe impl Sync for php_stream_wrapper {}
+unsafe impl Send for php_stream_wrapper {}
+
+/// Session save handler table (`ps_module` in `ext/session/php_session.h`).
+#[cfg(feature = "session")]
+#[repr(C)]
+pub struct ps_module {
+    pub s_name: *const c_char,
+    pub s_open: Option<
+        unsafe extern "C" fn(
+            *mut *mut c_void,
+            *const c_char,
+            *const c_char,
+        ) -> c_int,
+    >,
+    pub s_close: Option<unsafe extern "C" fn(*mut *mut c_void) -> c_int>,
+    pub s_read: Option<
+        unsafe extern "C" fn(
+            *mut *mut c_void,
+            *mut zend_string,
+            *mut *mut zend_string,
+            zend_long,
+        ) -> c_int,
+    >,
+    pub s_write: Option<
+        unsafe extern "C" fn(
+            *mut *mut c_void,
+            *mut zend_string,
+            *mut zend_string,
+            zend_long,
+        ) -> c_int,
+    >,
+    pub s_destroy: Option<
+        unsafe extern "C" fn(*mut *mut c_void, *mut zend_string) -> c_int,
+    >,
+    pub s_gc: Option<
+        unsafe extern "C" fn(
+            *mut *mut c_void,
+            zend_long,
+            *mut zend_long,
+        ) -> zend_long,
+    >,
+    pub s_create_sid:
+        Option<unsafe extern "C" fn(*mut *mut c_void) -> *mut zend_string>,
+    pub s_validate_sid: Option<
+        unsafe extern "C" fn(*mut *mut c_void, *mut zend_string) -> c_int,
+    >,
+    pub s_update_timestamp: Option<
+        unsafe extern "C" fn(
+            *mut *mut c_void,
+            *mut zend_string,
+            *mut zend_string,
+            zend_long,
+        ) -> c_int,
+    >,
+}
+
+// Only ever read by PHP after registration.
+#[cfg(feature = "session")]
+unsafe impl Sync for ps_module {}
+
 // Function pointer exported by PHP for creating interned zend_string values.
 pub type zend_string_init_interned_func_t = Option<
     unsafe extern "C" fn(
@@ -384,6 +891,8 @@ extern "C" {
         modify_type: c_int,
         stage: c_int,
     ) -> c_int;
+    pub fn zend_memory_usage(real_usage: bool) -> usize;
+    pub fn zend_memory_peak_usage(real_usage: bool) -> usize;
     pub fn zend_ini_string(
         name: *const c_char,
         name_length: usize,
@@ -405,11 +914,215 @@ extern "C" {
 
     // idempotent
     pub fn sapi_send_headers() -> c_int;
+    // bails out of the request unless `ignore_user_abort` is set
+    pub fn php_handle_aborted_connection();
+    pub fn php_mt_srand(seed: u32);
+
+    // non-persistent copy of both parts
+    pub fn zend_string_concat2(
+        str1: *const c_char,
+        str1_len: usize,
+        str2: *const c_char,
+        str2_len: usize,
+    ) -> *mut zend_string;
+
+    pub fn zend_get_executed_filename_ex() -> *mut zend_string;
+    pub fn zend_lookup_class_ex(
+        name: *mut zend_string,
+        key: *mut zend_string,
+        flags: u32,
+    ) -> *mut zend_class_entry;
+    pub fn zend_objects_new(ce: *mut zend_class_entry) -> *mut zend_object;
+    pub fn object_properties_init(
+        object: *mut zend_object,
+        class_type: *mut zend_class_entry,
+    );
+    pub fn execute_internal(
+        execute_data: *mut zend_execute_data,
+        return_value: *mut zval,
+    );
+    pub fn zend_throw_error(
+        exception_ce: *mut zend_class_entry,
+        format: *const c_char,
+        ...
+    );
+    pub fn _emalloc(size: usize) -> *mut c_void;
+    pub fn _efree(ptr: *mut c_void);
+    pub fn zend_parse_parameters(
+        num_args: u32,
+        type_spec: *const c_char,
+        ...
+    ) -> c_int;
+    pub fn zend_hash_internal_pointer_reset_ex(
+        ht: *mut HashTable,
+        pos: *mut HashPosition,
+    );
+    pub fn zend_hash_move_forward_ex(
+        ht: *mut HashTable,
+        pos: *mut HashPosition,
+    ) -> c_int;
+    pub fn zend_hash_get_current_key_ex(
+        ht: *const HashTable,
+        str_index: *mut *mut zend_string,
+        num_index: *mut u64,
+        pos: *const HashPosition,
+    ) -> c_int;
+    pub fn zend_hash_get_current_data_ex(
+        ht: *mut HashTable,
+        pos: *mut HashPosition,
+    ) -> *mut zval;
+
+    pub fn _zend_new_array_0() -> *mut HashTable;
+    pub fn zend_hash_str_find(
+        ht: *const HashTable,
+        key: *const c_char,
+        len: usize,
+    ) -> *mut zval;
+    pub fn zend_hash_next_index_insert(
+        ht: *mut HashTable,
+        data: *mut zval,
+    ) -> *mut zval;
+    // Takes ownership of `value`; numeric string keys become integers.
+    pub fn add_assoc_zval_ex(
+        arg: *mut zval,
+        key: *const c_char,
+        key_len: usize,
+        value: *mut zval,
+    );
+
+    // Per-thread: adds to the calling thread's `known_post_content_types`.
+    pub fn sapi_register_post_entry(
+        post_entry: *const sapi_post_entry,
+    ) -> c_int;
+    pub fn sapi_unregister_post_entry(post_entry: *const sapi_post_entry);
+    pub fn add_next_index_stringl(
+        arg: *mut zval,
+        str: *const c_char,
+        length: usize,
+    ) -> c_int;
+    // Takes ownership of `value`; `force` creates the variable if needed.
+    pub fn zend_set_local_var_str(
+        name: *const c_char,
+        len: usize,
+        value: *mut zval,
+        force: bool,
+    ) -> c_int;
+
+    // Per-request changes to the wrapper table, like
+    // `stream_wrapper_register()`.
+    pub fn php_register_url_stream_wrapper_volatile(
+        protocol: *mut zend_string,
+        wrapper: *mut php_stream_wrapper,
+    ) -> c_int;
+    pub fn php_unregister_url_stream_wrapper_volatile(
+        protocol: *mut zend_string,
+    ) -> c_int;
+    pub fn php_stream_wrapper_log_error(
+        wrapper: *const php_stream_wrapper,
+        options: c_int,
+        fmt: *const c_char,
+        ...
+    );
+    pub fn php_stream_context_get_option(
+        context: *mut php_stream_context,
+        wrappername: *const c_char,
+        optionname: *const c_char,
+    ) -> *mut zval;
+    pub fn _php_stream_memory_create(mode: c_int) -> *mut php_stream;
+    pub fn _php_stream_alloc(
+        ops: *const php_stream_ops,
+        abstract_: *mut c_void,
+        persistent_id: *const c_char,
+        mode: *const c_char,
+    ) -> *mut php_stream;
+    pub fn php_error_docref(
+        docref: *const c_char,
+        type_: c_int,
+        format: *const c_char,
+        ...
+    );
+    pub fn _php_stream_write(
+        stream: *mut php_stream,
+        buf: *const c_char,
+        count: usize,
+    ) -> isize;
+    pub fn _php_stream_seek(
+        stream: *mut php_stream,
+        offset: i64,
+        whence: c_int,
+    ) -> c_int;
 
     pub static mut zend_string_init_interned: zend_string_init_interned_func_t;
+    pub static mut zend_execute_internal: zend_execute_internal_t;
+    pub static mut zend_standard_class_def: *mut zend_class_entry;
+    pub static mut zend_stream_open_function:
+        Option<unsafe extern "C" fn(handle: *mut zend_file_handle) -> c_int>;
+    pub static mut zend_resolve_path: Option<
+        unsafe extern "C" fn(filename: *mut zend_string) -> *mut zend_string,
+    >;
 
     pub static mut sapi_module: sapi_module_struct;
+    #[cfg(not(php_zts))]
     pub static mut sapi_globals: sapi_globals_struct;
+    #[cfg(not(php_zts))]
+    pub static mut core_globals: php_core_globals;
+}
+
+#[cfg(feature = "session")]
+extern "C" {
+    pub fn php_session_register_module(module: *const ps_module) -> c_int;
+    pub fn php_session_create_id(
+        mod_data: *mut *mut c_void,
+    ) -> *mut zend_string;
+}
+
+// TSRM: per-thread resource management in ZTS builds.
+#[cfg(php_zts)]
+extern "C" {
+    pub static sapi_globals_offset: usize;
+    pub static core_globals_offset: usize;
+
+    pub fn php_tsrm_startup() -> bool;
+    pub fn tsrm_shutdown();
+    pub fn tsrm_get_ls_cache() -> *mut c_void;
+    pub fn ts_resource_ex(id: c_int, th_id: *mut c_void) -> *mut c_void;
+    pub fn ts_free_thread();
+}
+
+/// SAPI globals for the calling thread, i.e. PHP's `SG()` macro.
+#[cfg(not(php_zts))]
+#[inline]
+pub unsafe fn sg() -> &'static mut sapi_globals_struct {
+    &mut *std::ptr::addr_of_mut!(sapi_globals)
+}
+
+/// SAPI globals for the calling thread, i.e. PHP's `SG()` macro.
+///
+/// The calling thread must have TSRM resources (see `tsrm::attach`).
+#[cfg(php_zts)]
+#[inline]
+pub unsafe fn sg() -> &'static mut sapi_globals_struct {
+    let base = tsrm_get_ls_cache() as *mut u8;
+
+    &mut *(base.add(sapi_globals_offset) as *mut sapi_globals_struct)
+}
+
+/// Core globals for the calling thread, i.e. PHP's `PG()` macro.
+#[cfg(not(php_zts))]
+#[inline]
+pub unsafe fn pg() -> &'static mut php_core_globals {
+    &mut *std::ptr::addr_of_mut!(core_globals)
+}
+
+/// Core globals for the calling thread, i.e. PHP's `PG()` macro.
+///
+/// The calling thread must have TSRM resources (see `tsrm::attach`).
+#[cfg(php_zts)]
+#[inline]
+pub unsafe fn pg() -> &'static mut php_core_globals {
+    let base = tsrm_get_ls_cache() as *mut u8;
+
+    &mut *(base.add(core_globals_offset) as *mut php_core_globals)
 }
 
 #[cfg(all(test, bindgen_available))]
@@ -481,6 +1194,20 @@ mod bindgen_tests {
         startup
     );
 
+    bindgen_offset_test!(
+        test_core_globals_last_error_type,
+        php_core_globals,
+        bindgen_validation::_php_core_globals,
+        last_error_type
+    );
+
+    bindgen_offset_test!(
+        test_core_globals_last_error_message,
+        php_core_globals,
+        bindgen_validation::_php_core_globals,
+        last_error_message
+    );
+
     bindgen_offset_test!(
         test_request_info_request_method,
         sapi_request_info,
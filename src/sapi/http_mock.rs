@@ -0,0 +1,528 @@
+//! Offline stand-in for PHP's `http://` and `https://` stream wrappers.
+//!
+//! With an [`HttpMock`] set through [`EngineBuilder::with_http_mock()`],
+//! each request swaps PHP's HTTP wrappers for one answered from Rust, so
+//! `file_get_contents()`, `fopen()` and friends never touch the network.
+//! cURL and raw sockets are not covered.
+//!
+//! [`EngineBuilder::with_http_mock()`]: super::EngineBuilder::with_http_mock
+
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+
+#[cfg(feature = "tracing")]
+use tracing::debug;
+
+use super::ffi;
+use super::stream_wrapper;
+
+/// Canned reply for requests matched by an [`HttpMock`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl MockResponse {
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// `200 OK` with `body`.
+    pub fn ok(body: impl Into<Vec<u8>>) -> Self {
+        Self::new(200).with_body(body)
+    }
+
+    #[must_use]
+    pub fn with_header(
+        mut self,
+        name: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Self {
+        self.headers
+            .push((name.into(), value.into()));
+        self
+    }
+
+    #[must_use]
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    fn status_line(&self) -> String {
+        let reason = reason_phrase(self.status);
+        if reason.is_empty() {
+            format!("HTTP/1.1 {}", self.status)
+        } else {
+            format!("HTTP/1.1 {} {}", self.status, reason)
+        }
+    }
+
+    /// What PHP puts in `$http_response_header`.
+    fn header_lines(&self) -> Vec<String> {
+        std::iter::once(self.status_line())
+            .chain(
+                self.headers
+                    .iter()
+                    .map(|(name, value)| format!("{name}: {value}")),
+            )
+            .collect()
+    }
+}
+
+/// A request a script made through the mocked wrappers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpCall {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpCall {
+    /// Upper-cased; `GET` unless the stream context set `http.method`.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Headers from the stream context's `http.header` option.
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    /// First value of header `name`, matched case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The stream context's `http.content` option.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    routes: Vec<Route>,
+    calls: Vec<HttpCall>,
+}
+
+#[derive(Debug)]
+struct Route {
+    method: String,
+    url: String,
+    response: MockResponse,
+}
+
+/// Answers `http://` and `https://` stream requests from a route table.
+///
+/// Routes match on method (case-insensitively) and the exact URL, first
+/// added first. Every request is recorded, matched or not; unmatched ones
+/// fail to open as if the host were unreachable. Clones share routes and
+/// calls, so one clone can go to the engine while another asserts.
+///
+/// ```
+/// use ripht_php_sapi::{HttpMock, MockResponse};
+///
+/// let mock = HttpMock::new().with_response(
+///     "GET",
+///     "https://api.example.com/users/1",
+///     MockResponse::ok(r#"{"id":1}"#).with_header("Content-Type", "application/json"),
+/// );
+///
+/// assert!(mock.calls().is_empty());
+/// ```
+///
+/// Calls made by [`PreforkExecutor`](super::PreforkExecutor) workers are
+/// recorded in the worker process and not visible here.
+#[derive(Debug, Clone, Default)]
+pub struct HttpMock {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl HttpMock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn with_response(
+        self,
+        method: &str,
+        url: impl Into<String>,
+        response: MockResponse,
+    ) -> Self {
+        self.respond(method, url, response);
+        self
+    }
+
+    /// Adds a route; also works while the engine is running.
+    pub fn respond(
+        &self,
+        method: &str,
+        url: impl Into<String>,
+        response: MockResponse,
+    ) {
+        self.lock()
+            .routes
+            .push(Route {
+                method: method.to_ascii_uppercase(),
+                url: url.into(),
+                response,
+            });
+    }
+
+    pub fn calls(&self) -> Vec<HttpCall> {
+        self.lock().calls.clone()
+    }
+
+    /// Removes and returns the recorded calls.
+    pub fn take_calls(&self) -> Vec<HttpCall> {
+        std::mem::take(&mut self.lock().calls)
+    }
+
+    /// Records `call` and returns the response of the first matching route.
+    fn answer(&self, call: HttpCall) -> Option<MockResponse> {
+        let mut state = self.lock();
+
+        let response = state
+            .routes
+            .iter()
+            .find(|route| route.method == call.method && route.url == call.url)
+            .map(|route| route.response.clone());
+
+        state.calls.push(call);
+        response
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MockState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+static MOCK: RwLock<Option<HttpMock>> = RwLock::new(None);
+
+// Checked at every request startup, so kept apart from the lock.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+static WRAPPER_OPS: ffi::php_stream_wrapper_ops = ffi::php_stream_wrapper_ops {
+    stream_opener: Some(ripht_http_mock_open),
+    stream_closer: None,
+    stream_stat: None,
+    url_stat: None,
+    dir_opener: None,
+    label: c"http".as_ptr(),
+    unlink: None,
+    rename: None,
+    stream_mkdir: None,
+    stream_rmdir: None,
+    stream_metadata: None,
+};
+
+static WRAPPER: ffi::php_stream_wrapper = ffi::php_stream_wrapper {
+    wops: &WRAPPER_OPS,
+    abstract_: std::ptr::null_mut(),
+    is_url: 1,
+};
+
+/// Makes requests use `mock` for HTTP; `None` restores PHP's wrappers.
+pub(crate) fn install(mock: Option<&HttpMock>) {
+    *MOCK
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = mock.cloned();
+
+    ACTIVE.store(mock.is_some(), Ordering::Release);
+}
+
+fn mock() -> Option<HttpMock> {
+    MOCK.read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+}
+
+/// Swaps the mock in for this request's `http`/`https` wrappers.
+///
+/// Call after `php_request_startup`; PHP drops the change at shutdown.
+pub(crate) unsafe fn activate() {
+    if !ACTIVE.load(Ordering::Acquire) {
+        return;
+    }
+
+    for scheme in ["http", "https"] {
+        stream_wrapper::register(scheme, &WRAPPER);
+    }
+}
+
+unsafe extern "C" fn ripht_http_mock_open(
+    wrapper: *mut ffi::php_stream_wrapper,
+    filename: *const c_char,
+    _mode: *const c_char,
+    options: c_int,
+    _opened_path: *mut *mut ffi::zend_string,
+    context: *mut ffi::php_stream_context,
+) -> *mut ffi::php_stream {
+    let call = read_call(filename, context);
+
+    #[cfg(feature = "tracing")]
+    debug!(
+        method = call.method(),
+        url = call.url(),
+        "Mocked HTTP request"
+    );
+
+    let response =
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            mock().and_then(|mock| mock.answer(call))
+        }))
+        .ok()
+        .flatten();
+
+    let Some(response) = response else {
+        ffi::php_stream_wrapper_log_error(
+            wrapper,
+            options,
+            c"No mock response for %s".as_ptr(),
+            filename,
+        );
+        return std::ptr::null_mut();
+    };
+
+    set_response_header(&response);
+
+    // Like PHP's wrapper, error statuses fail unless `http.ignore_errors`.
+    if response.status >= 400 && !ignore_errors(context) {
+        let line = CString::new(response.status_line()).unwrap_or_default();
+        ffi::php_stream_wrapper_log_error(
+            wrapper,
+            options,
+            c"HTTP request failed! %s".as_ptr(),
+            line.as_ptr(),
+        );
+        return std::ptr::null_mut();
+    }
+
+    let stream = ffi::_php_stream_memory_create(ffi::TEMP_STREAM_DEFAULT);
+    if stream.is_null() {
+        return stream;
+    }
+
+    if !response.body.is_empty() {
+        ffi::_php_stream_write(
+            stream,
+            response.body.as_ptr() as *const c_char,
+            response.body.len(),
+        );
+    }
+    ffi::_php_stream_seek(stream, 0, ffi::SEEK_SET);
+
+    stream
+}
+
+/// Builds the call from the URL and the context's `http` options.
+unsafe fn read_call(
+    filename: *const c_char,
+    context: *mut ffi::php_stream_context,
+) -> HttpCall {
+    let mut call = HttpCall {
+        method: "GET".to_string(),
+        url: CStr::from_ptr(filename)
+            .to_string_lossy()
+            .into_owned(),
+        headers: Vec::new(),
+        body: Vec::new(),
+    };
+
+    if let Some(method) = context_option(context, c"method") {
+        if ffi::zval_type(method) == ffi::IS_STRING {
+            call.method = ffi::zval_string(method).to_ascii_uppercase();
+        }
+    }
+
+    if let Some(content) = context_option(context, c"content") {
+        if ffi::zval_type(content) == ffi::IS_STRING {
+            call.body =
+                ffi::zstr_bytes(ffi::zval_ptr(content) as *const _).to_vec();
+        }
+    }
+
+    if let Some(header) = context_option(context, c"header") {
+        match ffi::zval_type(header) {
+            ffi::IS_STRING => {
+                add_header_lines(&mut call, &ffi::zval_string(header));
+            }
+            ffi::IS_ARRAY => {
+                let table = ffi::zval_ptr(header) as *mut ffi::HashTable;
+
+                ffi::for_each_entry(table, |_, line| {
+                    if ffi::zval_type(line) == ffi::IS_STRING {
+                        add_header_lines(&mut call, &ffi::zval_string(line));
+                    }
+                });
+            }
+            _ => {}
+        }
+    }
+
+    call
+}
+
+fn add_header_lines(call: &mut HttpCall, raw: &str) {
+    for line in raw.lines() {
+        if let Some((name, value)) = line.split_once(':') {
+            call.headers
+                .push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+}
+
+unsafe fn context_option(
+    context: *mut ffi::php_stream_context,
+    name: &CStr,
+) -> Option<*const ffi::zval> {
+    if context.is_null() {
+        return None;
+    }
+
+    let value = ffi::php_stream_context_get_option(
+        context,
+        c"http".as_ptr(),
+        name.as_ptr(),
+    );
+
+    (!value.is_null()).then(|| ffi::zval_deref(value))
+}
+
+unsafe fn ignore_errors(context: *mut ffi::php_stream_context) -> bool {
+    context_option(context, c"ignore_errors").is_some_and(|value| {
+        match ffi::zval_type(value) {
+            ffi::IS_TRUE => true,
+            ffi::IS_LONG => ffi::zval_long(value) != 0,
+            ffi::IS_STRING => {
+                !matches!(ffi::zval_string(value).as_str(), "" | "0")
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Sets `$http_response_header` in the calling scope.
+unsafe fn set_response_header(response: &MockResponse) {
+    let mut headers: ffi::zval = std::mem::zeroed();
+    ffi::zval_set_array(&mut headers, ffi::_zend_new_array_0());
+
+    for line in response.header_lines() {
+        ffi::add_next_index_stringl(
+            &mut headers,
+            line.as_ptr() as *const c_char,
+            line.len(),
+        );
+    }
+
+    let name = "http_response_header";
+    ffi::zend_set_local_var_str(
+        name.as_ptr() as *const c_char,
+        name.len(),
+        &mut headers,
+        true,
+    );
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        409 => "Conflict",
+        422 => "Unprocessable Entity",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(method: &str, url: &str) -> HttpCall {
+        HttpCall {
+            method: method.to_string(),
+            url: url.to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_routes_match_method_and_url_and_record_calls() {
+        let mock = HttpMock::new()
+            .with_response("get", "http://a.test/", MockResponse::ok("a"))
+            .with_response("POST", "http://a.test/", MockResponse::new(201));
+
+        let shared = mock.clone();
+        assert_eq!(
+            shared
+                .answer(call("GET", "http://a.test/"))
+                .map(|r| r.body),
+            Some(b"a".to_vec())
+        );
+        assert_eq!(
+            shared
+                .answer(call("POST", "http://a.test/"))
+                .map(|r| r.status),
+            Some(201)
+        );
+        assert_eq!(shared.answer(call("GET", "http://b.test/")), None);
+
+        let calls = mock.take_calls();
+        assert_eq!(calls.len(), 3);
+        assert_eq!(calls[2].url(), "http://b.test/");
+        assert!(mock.calls().is_empty());
+    }
+
+    #[test]
+    fn test_response_header_lines() {
+        let response = MockResponse::new(404).with_header("X-Id", "7");
+
+        assert_eq!(
+            response.header_lines(),
+            ["HTTP/1.1 404 Not Found", "X-Id: 7"]
+        );
+        assert_eq!(MockResponse::new(299).status_line(), "HTTP/1.1 299");
+    }
+}
@@ -0,0 +1,272 @@
+//! Rust-side filtering of incoming request variables.
+//!
+//! An [`InputFilter`] set through [`EngineBuilder::with_input_filter()`]
+//! sees every GET, POST (urlencoded and multipart fields), cookie and
+//! `$_SERVER` value before PHP registers it, and can accept, drop or
+//! rewrite it. It runs ahead of PHP's own filter (ext/filter's
+//! `filter.default`, when loaded), so `filter_input()` sees the result.
+//!
+//! [`EngineBuilder::with_input_filter()`]: super::EngineBuilder::with_input_filter
+
+use std::ffi::{c_char, c_int, c_uint, CStr};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+#[cfg(feature = "tracing")]
+use tracing::trace;
+
+use super::ffi;
+
+/// Which superglobal a variable is headed for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum InputSource {
+    Get,
+    Post,
+    Cookie,
+    Server,
+}
+
+impl InputSource {
+    fn from_arg(arg: c_int) -> Option<Self> {
+        match arg {
+            ffi::PARSE_GET => Some(Self::Get),
+            ffi::PARSE_POST => Some(Self::Post),
+            ffi::PARSE_COOKIE => Some(Self::Cookie),
+            ffi::PARSE_SERVER => Some(Self::Server),
+            _ => None,
+        }
+    }
+}
+
+/// What to do with an incoming variable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum InputAction {
+    Accept,
+    /// Leave the variable out of its superglobal.
+    Reject,
+    /// Register this value instead.
+    Replace(Vec<u8>),
+}
+
+/// Decides on each incoming variable.
+///
+/// `name` is the variable name as sent, e.g. `tags[]`; `value` is
+/// URL-decoded but otherwise raw. Called on the thread running the request.
+pub trait InputFilter: Send + Sync {
+    fn filter(
+        &self,
+        source: InputSource,
+        name: &str,
+        value: &[u8],
+    ) -> InputAction;
+}
+
+impl<F> InputFilter for F
+where
+    F: Fn(InputSource, &str, &[u8]) -> InputAction + Send + Sync,
+{
+    fn filter(
+        &self,
+        source: InputSource,
+        name: &str,
+        value: &[u8],
+    ) -> InputAction {
+        self(source, name, value)
+    }
+}
+
+/// Shared filter handle kept in the engine configuration.
+#[derive(Clone)]
+pub(crate) struct InputFilterHandle(pub(crate) Arc<dyn InputFilter>);
+
+impl fmt::Debug for InputFilterHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("InputFilterHandle(..)")
+    }
+}
+
+type InputFilterFn = unsafe extern "C" fn(
+    c_int,
+    *const c_char,
+    *mut *mut c_char,
+    usize,
+    *mut usize,
+) -> c_uint;
+
+static FILTER: RwLock<Option<Arc<dyn InputFilter>>> = RwLock::new(None);
+
+// Checked for every `$_SERVER` entry, so kept apart from the lock.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+// The filter in place at install: ours or one an extension registered.
+// Only written during module startup, under the engine lifecycle lock.
+static mut PREVIOUS: Option<InputFilterFn> = None;
+
+/// Puts `filter` in front of the SAPI's input filter.
+///
+/// Must run after module startup, where extensions may replace it.
+pub(crate) unsafe fn install(filter: Option<&InputFilterHandle>) {
+    *FILTER
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) =
+        filter.map(|handle| Arc::clone(&handle.0));
+
+    ACTIVE.store(filter.is_some(), Ordering::Release);
+
+    if filter.is_none() {
+        return;
+    }
+
+    PREVIOUS = ffi::sapi_module.input_filter;
+    ffi::sapi_module.input_filter = Some(ripht_input_filter);
+}
+
+fn filter() -> Option<Arc<dyn InputFilter>> {
+    FILTER
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+}
+
+/// Registers a `$_SERVER` entry, through the input filter if one is set.
+pub(crate) unsafe fn register_server_variable(
+    name: &CStr,
+    value: &[u8],
+    track_vars_array: *mut ffi::zval,
+) {
+    let Some(input_filter) = ffi::sapi_module
+        .input_filter
+        .filter(|_| ACTIVE.load(Ordering::Acquire))
+    else {
+        ffi::php_register_variable_safe(
+            name.as_ptr(),
+            value.as_ptr() as *const c_char,
+            value.len(),
+            track_vars_array,
+        );
+        return;
+    };
+
+    // Filters may free and replace the value, so it must be emalloc'd.
+    let mut val = emalloc_copy(value);
+    let mut new_len = 0usize;
+
+    if input_filter(
+        ffi::PARSE_SERVER,
+        name.as_ptr(),
+        &mut val,
+        value.len(),
+        &mut new_len,
+    ) != 0
+    {
+        ffi::php_register_variable_safe(
+            name.as_ptr(),
+            val,
+            new_len,
+            track_vars_array,
+        );
+    }
+
+    ffi::_efree(val as *mut _);
+}
+
+/// NUL-terminated copy of `bytes` in the request arena.
+unsafe fn emalloc_copy(bytes: &[u8]) -> *mut c_char {
+    let buf = ffi::_emalloc(bytes.len() + 1) as *mut u8;
+    std::ptr::copy_nonoverlapping(bytes.as_ptr(), buf, bytes.len());
+    *buf.add(bytes.len()) = 0;
+    buf as *mut c_char
+}
+
+unsafe extern "C" fn ripht_input_filter(
+    arg: c_int,
+    var: *const c_char,
+    val: *mut *mut c_char,
+    val_len: usize,
+    new_val_len: *mut usize,
+) -> c_uint {
+    let mut val_len = val_len;
+
+    if let (Some(source), false) = (InputSource::from_arg(arg), var.is_null()) {
+        let action =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                let filter = filter()?;
+                let name = CStr::from_ptr(var).to_string_lossy();
+                let value = if (*val).is_null() {
+                    &[][..]
+                } else {
+                    std::slice::from_raw_parts(*val as *const u8, val_len)
+                };
+
+                Some(filter.filter(source, &name, value))
+            }))
+            // A panicking filter fails closed.
+            .unwrap_or(Some(InputAction::Reject));
+
+        match action {
+            None | Some(InputAction::Accept) => {}
+            Some(InputAction::Reject) => {
+                #[cfg(feature = "tracing")]
+                trace!(?source, "Input variable rejected");
+
+                if !new_val_len.is_null() {
+                    *new_val_len = 0;
+                }
+                return 0;
+            }
+            Some(InputAction::Replace(value)) => {
+                if !(*val).is_null() {
+                    ffi::_efree(*val as *mut _);
+                }
+                *val = emalloc_copy(&value);
+                val_len = value.len();
+            }
+        }
+    }
+
+    match PREVIOUS {
+        Some(previous) => previous(arg, var, val, val_len, new_val_len),
+        None => {
+            if !new_val_len.is_null() {
+                *new_val_len = val_len;
+            }
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_closure_filter_and_sources() {
+        let filter = |source: InputSource, name: &str, value: &[u8]| {
+            if source == InputSource::Cookie {
+                InputAction::Reject
+            } else if name == "q" {
+                InputAction::Replace(value.trim_ascii().to_vec())
+            } else {
+                InputAction::Accept
+            }
+        };
+        let filter: &dyn InputFilter = &filter;
+
+        assert_eq!(
+            filter.filter(InputSource::Get, "q", b"  hi "),
+            InputAction::Replace(b"hi".to_vec())
+        );
+        assert_eq!(
+            filter.filter(InputSource::Cookie, "q", b"x"),
+            InputAction::Reject
+        );
+        assert_eq!(
+            InputSource::from_arg(ffi::PARSE_SERVER),
+            Some(InputSource::Server)
+        );
+        assert_eq!(InputSource::from_arg(3), None);
+    }
+}
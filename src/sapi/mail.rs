@@ -0,0 +1,300 @@
+//! `mail()` delivery to Rust.
+//!
+//! With a [`MailSink`] set through [`EngineBuilder::with_mail_sink()`], calls
+//! to PHP's `mail()` are answered by the SAPI instead of piping the message
+//! to `sendmail_path`. Each message is handed to the sink and listed in
+//! `ExecutionResult::mail()`.
+//!
+//! [`EngineBuilder::with_mail_sink()`]: super::EngineBuilder::with_mail_sink
+
+use std::ffi::c_char;
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+
+#[cfg(feature = "tracing")]
+use tracing::{debug, warn};
+
+use super::callbacks::get_context;
+use super::ffi;
+use crate::execution::MailMessage;
+
+/// Receives messages scripts send with `mail()`.
+///
+/// Called on the thread running the request; an error makes `mail()`
+/// return `false`.
+pub trait MailSink: Send + Sync {
+    fn send(&self, message: &MailMessage) -> io::Result<()>;
+}
+
+/// [`MailSink`] that keeps every message in memory.
+///
+/// Clones share the same messages, so one clone can go to the engine while
+/// another inspects what was sent.
+#[derive(Debug, Clone, Default)]
+pub struct MailCollector {
+    messages: Arc<Mutex<Vec<MailMessage>>>,
+}
+
+impl MailCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn messages(&self) -> Vec<MailMessage> {
+        self.lock().clone()
+    }
+
+    /// Removes and returns the collected messages.
+    pub fn take(&self) -> Vec<MailMessage> {
+        std::mem::take(&mut *self.lock())
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<MailMessage>> {
+        self.messages
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl MailSink for MailCollector {
+    fn send(&self, message: &MailMessage) -> io::Result<()> {
+        self.lock()
+            .push(message.clone());
+        Ok(())
+    }
+}
+
+impl<F> MailSink for F
+where
+    F: Fn(&MailMessage) -> io::Result<()> + Send + Sync,
+{
+    fn send(&self, message: &MailMessage) -> io::Result<()> {
+        self(message)
+    }
+}
+
+/// Shared sink handle kept in the engine configuration.
+#[derive(Clone)]
+pub(crate) struct MailSinkHandle(pub(crate) Arc<dyn MailSink>);
+
+impl fmt::Debug for MailSinkHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MailSinkHandle(..)")
+    }
+}
+
+static SINK: RwLock<Option<Arc<dyn MailSink>>> = RwLock::new(None);
+
+// Checked on every internal call, so kept apart from the lock.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Makes `sink` receive `mail()` calls; `None` restores PHP's own `mail()`.
+pub(crate) fn install(sink: Option<&MailSinkHandle>) {
+    *SINK
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) =
+        sink.map(|handle| Arc::clone(&handle.0));
+
+    ACTIVE.store(sink.is_some(), Ordering::Release);
+}
+
+fn sink() -> Option<Arc<dyn MailSink>> {
+    SINK.read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+}
+
+/// Handles the call if it is `mail()` and a sink is installed.
+///
+/// Returns whether the call was handled; otherwise the caller runs it.
+pub(crate) unsafe fn intercept(
+    execute_data: *mut ffi::zend_execute_data,
+    return_value: *mut ffi::zval,
+) -> bool {
+    if !ACTIVE.load(Ordering::Acquire) || !is_mail((*execute_data).func) {
+        return false;
+    }
+
+    // On bad arguments ZPP has thrown a TypeError already.
+    let Some(message) = parse_args(execute_data) else {
+        return true;
+    };
+
+    let sent = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let Some(sink) = sink() else {
+            return false;
+        };
+
+        match sink.send(&message) {
+            Ok(()) => true,
+            Err(_err) => {
+                #[cfg(feature = "tracing")]
+                warn!(error = %_err, "Mail sink failed");
+                false
+            }
+        }
+    }))
+    .unwrap_or(false);
+
+    #[cfg(feature = "tracing")]
+    debug!(to = ?message.to(), sent, "Captured mail()");
+
+    if sent {
+        if let Some(ctx_ptr) = get_context() {
+            (*ctx_ptr).mail.push(message);
+        }
+    }
+
+    if !return_value.is_null() {
+        ffi::zval_set_bool(return_value, sent);
+    }
+
+    true
+}
+
+unsafe fn is_mail(func: *const ffi::zend_function) -> bool {
+    !func.is_null()
+        && (*func).scope.is_null()
+        && !(*func)
+            .function_name
+            .is_null()
+        && ffi::zstr_bytes((*func).function_name).eq_ignore_ascii_case(b"mail")
+}
+
+/// Reads `mail(string $to, string $subject, string $message,
+/// array|string $additional_headers = [], string $additional_params = "")`.
+unsafe fn parse_args(
+    execute_data: *const ffi::zend_execute_data,
+) -> Option<MailMessage> {
+    let mut to: *const c_char = std::ptr::null();
+    let mut to_len = 0usize;
+    let mut subject: *const c_char = std::ptr::null();
+    let mut subject_len = 0usize;
+    let mut body: *const c_char = std::ptr::null();
+    let mut body_len = 0usize;
+    let mut headers: *mut ffi::zval = std::ptr::null_mut();
+    let mut params: *const c_char = std::ptr::null();
+    let mut params_len = 0usize;
+
+    let parsed = ffi::zend_parse_parameters(
+        ffi::zend_call_num_args(execute_data),
+        c"sss|zs".as_ptr(),
+        &mut to,
+        &mut to_len,
+        &mut subject,
+        &mut subject_len,
+        &mut body,
+        &mut body_len,
+        &mut headers,
+        &mut params,
+        &mut params_len,
+    );
+    if parsed != ffi::SUCCESS {
+        return None;
+    }
+
+    let text = |ptr: *const c_char, len: usize| {
+        if ptr.is_null() {
+            String::new()
+        } else {
+            String::from_utf8_lossy(std::slice::from_raw_parts(
+                ptr as *const u8,
+                len,
+            ))
+            .into_owned()
+        }
+    };
+
+    let mut message = MailMessage::new(
+        &text(to, to_len),
+        text(subject, subject_len),
+        text(body, body_len),
+    );
+
+    if !headers.is_null() {
+        message = add_headers(message, headers);
+    }
+    if params_len > 0 {
+        message = message.with_additional_params(text(params, params_len));
+    }
+
+    Some(message)
+}
+
+/// Adds `mail()`'s headers argument: a raw block or a name => value(s) array.
+unsafe fn add_headers(
+    mut message: MailMessage,
+    headers: *const ffi::zval,
+) -> MailMessage {
+    let headers = ffi::zval_deref(headers);
+
+    match ffi::zval_type(headers) {
+        ffi::IS_STRING => {
+            let raw = ffi::zstr_bytes(ffi::zval_ptr(headers) as *const _);
+            message.with_raw_headers(&String::from_utf8_lossy(raw))
+        }
+        ffi::IS_ARRAY => {
+            let table = ffi::zval_ptr(headers) as *mut ffi::HashTable;
+
+            ffi::for_each_entry(table, |name, value| {
+                let Some(name) = name else {
+                    return;
+                };
+
+                match ffi::zval_type(value) {
+                    ffi::IS_STRING => {
+                        message = std::mem::take(&mut message)
+                            .with_header(name, ffi::zval_string(value));
+                    }
+                    // Several values for one header name.
+                    ffi::IS_ARRAY => {
+                        let values =
+                            ffi::zval_ptr(value) as *mut ffi::HashTable;
+
+                        ffi::for_each_entry(values, |_, value| {
+                            if ffi::zval_type(value) == ffi::IS_STRING {
+                                message = std::mem::take(&mut message)
+                                    .with_header(
+                                        name.clone(),
+                                        ffi::zval_string(value),
+                                    );
+                            }
+                        });
+                    }
+                    _ => {}
+                }
+            });
+
+            message
+        }
+        _ => message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collector_clones_share_messages() {
+        let collector = MailCollector::new();
+        let sink: &dyn MailSink = &collector.clone();
+
+        sink.send(&MailMessage::new("a@example.com", "Hi", "Hello"))
+            .unwrap();
+
+        assert_eq!(collector.len(), 1);
+        assert_eq!(collector.take()[0].subject(), "Hi");
+        assert!(collector.is_empty());
+    }
+}
@@ -15,39 +15,80 @@
 //! - Section 4.1.4: `GATEWAY_INTERFACE` set to `CGI/1.1`
 
 use std::ffi::CString;
-use std::sync::OnceLock;
 
 use thiserror::Error;
 
 #[cfg(feature = "tracing")]
-use tracing::{error, info, trace};
+use tracing::trace;
 
+mod body_parser;
 pub(crate) mod callbacks;
+mod context_pool;
+mod engine;
 mod executor;
 pub(crate) mod ffi;
+mod http_mock;
+mod input_filter;
+mod mail;
+mod policy;
+#[cfg(php_zts)]
+mod pool;
+#[cfg(unix)]
+mod prefork;
 pub(crate) mod server_context;
 pub(crate) mod server_vars;
-
+#[cfg(feature = "session")]
+mod session;
+mod stream_wrapper;
+mod thread;
+#[cfg(php_zts)]
+mod tsrm;
+mod vfs;
+
+#[cfg(feature = "serde")]
+pub use body_parser::JsonBodyParser;
+pub use body_parser::{BodyParser, PostValue};
+pub use context_pool::PoolStats;
+pub(crate) use engine::{default_buffer_policy, default_security_policy};
+pub use engine::{EngineBuilder, EngineState};
 pub use executor::{ExecutionError, Executor};
+pub use http_mock::{HttpCall, HttpMock, MockResponse};
+pub use input_filter::{InputAction, InputFilter, InputSource};
+pub use mail::{MailCollector, MailSink};
+#[cfg(php_zts)]
+pub use pool::ExecutorPool;
+#[cfg(unix)]
+pub use prefork::PreforkExecutor;
 pub(crate) use server_vars::{ServerVars, ServerVarsCString};
+#[cfg(feature = "session")]
+pub use session::{InMemorySessionStore, SessionStore};
+pub use stream_wrapper::{StreamStat, StreamWrapper, WrapperStream};
+pub use thread::PhpThread;
+pub use vfs::{MemoryFs, VirtualFs};
 
 use crate::execution::{ExecutionContext, ExecutionHooks, ExecutionResult};
 
-static PHP_INIT_RESULT: OnceLock<Result<(), SapiError>> = OnceLock::new();
+/// Whether `path` exists on disk or in the engine's virtual filesystem.
+///
+/// For `phar://` paths only the archive is checked; the entry is resolved by
+/// PHP's phar extension when the script runs.
+pub(crate) fn script_exists(path: &std::path::Path) -> bool {
+    let phar_inner = path
+        .to_str()
+        .and_then(|p| p.strip_prefix("phar://"));
+
+    match phar_inner {
+        Some(inner) => std::path::Path::new(inner)
+            .ancestors()
+            .any(|archive| archive.is_file() || vfs::contains(archive)),
+        None => path.exists() || vfs::contains(path),
+    }
+}
 
 pub(crate) static SAPI_NAME: &[u8] = b"ripht\0";
 pub(crate) static SAPI_PRETTY_NAME: &[u8] = b"Ripht PHP SAPI\0";
 pub(crate) static SERVER_SOFTWARE: &str =
     concat!("Ripht/", env!("CARGO_PKG_VERSION"));
-static INI_ENTRIES: &[u8] = b"\
-variables_order=EGPCS\n\
-request_order=GP\n\
-output_buffering=4096\n\
-implicit_flush=0\n\
-html_errors=0\n\
-display_errors=1\n\
-log_errors=1\n\
-\0";
 
 /// Errors from SAPI initialization and configuration.
 #[derive(Debug, Clone, Error)]
@@ -56,9 +97,29 @@ pub enum SapiError {
     #[error("PHP engine not initialized")]
     NotInitialized,
 
+    #[error("PHP engine already initialized")]
+    AlreadyInitialized,
+
     #[error("PHP initialization failed: {0}")]
     InitializationFailed(String),
 
+    #[error("Engine lifecycle change attempted while a request is executing")]
+    RequestInProgress,
+
+    #[error(
+        "PHP engine is owned by thread {owner:?}, called from {current:?}"
+    )]
+    WrongThread {
+        owner: std::thread::ThreadId,
+        current: std::thread::ThreadId,
+    },
+
+    #[error("Security policy can't be enforced: {0}")]
+    UnenforceablePolicy(String),
+
+    #[error("Cannot fork workers: the process has {threads} threads")]
+    MultipleThreads { threads: usize },
+
     #[error("INI key contains null byte")]
     InvalidIniKey,
 
@@ -75,6 +136,19 @@ pub enum SapiError {
 }
 
 /// PHP SAPI instance. Initialize once, execute scripts repeatedly.
+///
+/// The engine moves through the states in [`EngineState`]. Handles stay valid
+/// across [`shutdown()`](Self::shutdown) and [`restart()`](Self::restart), but
+/// every operation returns `NotInitialized` while the engine isn't running.
+///
+/// The NTS engine belongs to the thread that started it; calls from any other
+/// thread fail with `WrongThread` while that thread is alive. Once the owner
+/// exits, the next thread to use the engine takes it over. Use [`PhpThread`]
+/// to submit work from multiple threads.
+///
+/// ZTS builds lift the restriction for request execution: every thread gets
+/// its own PHP globals, and `ExecutorPool` runs requests in parallel. Startup,
+/// shutdown and restart still belong to the thread that started the engine.
 pub struct RiphtSapi {
     _marker: std::marker::PhantomData<*mut ()>,
 }
@@ -86,107 +160,104 @@ impl RiphtSapi {
         Self::init().expect("SAPI initialization failure")
     }
 
+    /// Returns a builder for starting the engine with custom configuration.
+    #[must_use]
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder::new()
+    }
+
     fn init() -> Result<Self, SapiError> {
-        let init_result = PHP_INIT_RESULT.get_or_init(|| {
-            #[cfg(feature = "tracing")]
-            info!("Initializing RiphtSapi");
-
-            // SAFETY: One-time PHP engine initialization via OnceLock.
-            // All pointers/callbacks are static or 'static and remain valid.
-            unsafe {
-                ffi::sapi_module.name = SAPI_NAME.as_ptr() as *mut _;
-                ffi::sapi_module.pretty_name =
-                    SAPI_PRETTY_NAME.as_ptr() as *mut _;
-
-                // Register callbacks
-                ffi::sapi_module.startup = Some(callbacks::ripht_sapi_startup);
-                ffi::sapi_module.shutdown =
-                    Some(callbacks::ripht_sapi_shutdown);
-                ffi::sapi_module.activate =
-                    Some(callbacks::ripht_sapi_activate);
-                ffi::sapi_module.deactivate =
-                    Some(callbacks::ripht_sapi_deactivate);
-
-                ffi::sapi_module.ub_write =
-                    Some(callbacks::ripht_sapi_ub_write);
-                ffi::sapi_module.flush = Some(callbacks::ripht_sapi_flush);
-
-                ffi::sapi_module.send_headers =
-                    Some(callbacks::ripht_sapi_send_headers);
-                ffi::sapi_module.send_header =
-                    Some(callbacks::ripht_sapi_send_header);
-
-                ffi::sapi_module.read_post =
-                    Some(callbacks::ripht_sapi_read_post);
-                ffi::sapi_module.read_cookies =
-                    Some(callbacks::ripht_sapi_read_cookies);
-
-                ffi::sapi_module.register_server_variables =
-                    Some(callbacks::ripht_sapi_register_server_variables);
-
-                ffi::sapi_module.log_message =
-                    Some(callbacks::ripht_sapi_log_message);
-                ffi::sapi_module.get_request_time =
-                    Some(callbacks::ripht_sapi_get_request_time);
-                ffi::sapi_module.getenv = Some(callbacks::ripht_sapi_getenv);
-
-                ffi::sapi_module.php_ini_ignore = 0;
-                ffi::sapi_module.php_ini_ignore_cwd = 1;
-
-                ffi::sapi_module.input_filter =
-                    Some(callbacks::ripht_sapi_input_filter);
-                ffi::sapi_module.default_post_reader =
-                    Some(callbacks::ripht_sapi_default_post_reader);
-                ffi::sapi_module.treat_data =
-                    Some(callbacks::ripht_sapi_treat_data);
-
-                ffi::sapi_module.ini_entries = INI_ENTRIES.as_ptr() as *const _;
-
-                #[cfg(feature = "tracing")]
-                trace!("Starting SAPI");
-
-                ffi::sapi_startup(&mut ffi::sapi_module);
-
-                #[cfg(feature = "tracing")]
-                trace!("Initializing SAPI module");
-
-                let result = ffi::php_module_startup(
-                    &mut ffi::sapi_module,
-                    std::ptr::null_mut(),
-                );
-
-                if result == ffi::FAILURE {
-                    #[cfg(feature = "tracing")]
-                    error!("SAPI module startup failed");
-
-                    ffi::sapi_shutdown();
-
-                    Err(SapiError::InitializationFailed(
-                        "SAPI module initialization failed".to_string(),
-                    ))
-                } else {
-                    #[cfg(feature = "tracing")]
-                    info!("SAPI module initialized");
-                    Ok(())
-                }
-            }
-        });
-
-        match init_result {
-            Ok(()) => Ok(Self {
-                _marker: std::marker::PhantomData,
-            }),
-            // Clone the original error instead of wrapping it redundantly.
-            // The error already contains descriptive context.
-            Err(e) => Err(e.clone()),
+        let mut lifecycle = engine::lock();
+
+        // Only a fresh process starts the engine implicitly. After an explicit
+        // shutdown the handle is inert until `restart()` is called.
+        if engine::state() == EngineState::Uninitialized {
+            lifecycle.start(EngineBuilder::default())?;
+        }
+
+        Ok(Self::handle())
+    }
+
+    pub(crate) fn handle() -> Self {
+        Self {
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    /// Shuts down the PHP engine (MSHUTDOWN).
+    ///
+    /// Idempotent. Afterwards, executions return `NotInitialized` until
+    /// [`restart()`](Self::restart) is called.
+    ///
+    /// Fails with `WrongThread` when called from a thread that doesn't own
+    /// the engine and with `RequestInProgress` while a request is executing;
+    /// the engine keeps running in both cases.
+    pub fn shutdown() -> Result<(), SapiError> {
+        let mut lifecycle = engine::lock();
+
+        lifecycle.check_thread()?;
+
+        if Self::request_in_progress() {
+            return Err(SapiError::RequestInProgress);
+        }
+
+        lifecycle.stop();
+
+        Ok(())
+    }
+
+    /// Restarts the engine with its current configuration.
+    ///
+    /// Re-runs module startup, discarding any persistent state PHP
+    /// accumulated (e.g. leaked persistent resources).
+    pub fn restart(&self) -> Result<(), SapiError> {
+        let mut lifecycle = engine::lock();
+        let config = lifecycle.config();
+
+        Self::restart_locked(&mut lifecycle, config)
+    }
+
+    /// Restarts the engine with new startup configuration.
+    pub fn restart_with(&self, config: EngineBuilder) -> Result<(), SapiError> {
+        let mut lifecycle = engine::lock();
+
+        Self::restart_locked(&mut lifecycle, config)
+    }
+
+    fn restart_locked(
+        lifecycle: &mut engine::Lifecycle,
+        config: EngineBuilder,
+    ) -> Result<(), SapiError> {
+        lifecycle.check_thread()?;
+
+        if Self::request_in_progress() {
+            return Err(SapiError::RequestInProgress);
         }
+
+        lifecycle.restart(config)
+    }
+
+    /// Current lifecycle state of the engine.
+    pub fn state(&self) -> EngineState {
+        engine::state()
     }
 
-    /// Shuts down the PHP engine. Calling `execute()` after this is undefined behavior.
-    pub fn shutdown() {
+    /// Fails unless the engine is running and owned by the calling thread.
+    pub(crate) fn ensure_usable(&self) -> Result<(), SapiError> {
+        if !self.is_initialized() {
+            return Err(SapiError::NotInitialized);
+        }
+
+        engine::check_thread()
+    }
+
+    fn request_in_progress() -> bool {
+        // SAFETY: Reading a pointer-sized global; non-null only while a
+        // request owns a ServerContext.
         unsafe {
-            ffi::php_module_shutdown();
-            ffi::sapi_shutdown();
+            !ffi::sg()
+                .server_context
+                .is_null()
         }
     }
 
@@ -195,6 +266,8 @@ impl RiphtSapi {
         key: impl Into<Vec<u8>>,
         value: impl Into<Vec<u8>>,
     ) -> Result<(), SapiError> {
+        self.ensure_usable()?;
+
         let k_str = key.into();
         let v_str = value.into();
 
@@ -237,6 +310,8 @@ impl RiphtSapi {
         #[cfg(feature = "tracing")]
         trace!(ini_key = key, "Getting INI value");
 
+        self.ensure_usable().ok()?;
+
         let key_cstr = CString::new(key).ok()?;
 
         unsafe {
@@ -261,9 +336,7 @@ impl RiphtSapi {
         &self,
         ctx: ExecutionContext,
     ) -> Result<ExecutionResult, ExecutionError> {
-        self.executor()
-            .map_err(|_| ExecutionError::NotInitialized)?
-            .execute(ctx)
+        self.executor()?.execute(ctx)
     }
 
     pub fn execute_streaming<F>(
@@ -274,25 +347,35 @@ impl RiphtSapi {
     where
         F: FnMut(&[u8]) + 'static,
     {
-        self.executor()
-            .map_err(|_| ExecutionError::NotInitialized)?
+        self.executor()?
             .execute_streaming(ctx, on_output)
     }
 
+    /// See [`Executor::execute_streaming_with_hooks`].
+    pub fn execute_streaming_with_hooks<F, H>(
+        &self,
+        ctx: ExecutionContext,
+        on_output: F,
+        hooks: H,
+    ) -> Result<ExecutionResult, ExecutionError>
+    where
+        F: FnMut(&[u8]) + 'static,
+        H: ExecutionHooks + 'static,
+    {
+        self.executor()?
+            .execute_streaming_with_hooks(ctx, on_output, hooks)
+    }
+
     pub fn execute_with_hooks<H: ExecutionHooks + 'static>(
         &self,
         ctx: ExecutionContext,
         hooks: H,
     ) -> Result<ExecutionResult, ExecutionError> {
-        self.executor()
-            .map_err(|_| ExecutionError::NotInitialized)?
+        self.executor()?
             .execute_with_hooks(ctx, hooks)
     }
 
     pub fn is_initialized(&self) -> bool {
-        PHP_INIT_RESULT
-            .get()
-            .map(|result| result.is_ok())
-            .unwrap_or(false)
+        engine::state().is_running()
     }
 }
@@ -0,0 +1,278 @@
+//! Enforcement of [`SecurityPolicy`] inside the engine.
+//!
+//! INI-backed restrictions are applied with system privileges while the
+//! request activates, like a web server's per-vhost admin values, so they
+//! also cover `INI_SYSTEM` directives and are restored when the request ends.
+//! Disabled functions and classes are checked on every internal call through
+//! `zend_execute_internal`, since PHP only honors `disable_functions` at startup.
+//! The same hook hands `mail()` to the mail sink, after the policy check.
+//! Disabled classes also can't be instantiated: like PHP's old
+//! `disable_classes`, their `create_object` handler is replaced, here by one
+//! that consults the running request's policy.
+//!
+//! The hook is installed at every module startup, before any script
+//! compiles, so every script (including ones opcache keeps across requests)
+//! calls internal functions through it and any request's policy applies.
+//! Functions the compiler inlines and PHP 8.4's frameless functions never
+//! reach it; policies disabling those are rejected up front (see
+//! `SecurityPolicy::uninterceptable_function`).
+
+use std::ffi::CString;
+use std::sync::RwLock;
+
+#[cfg(feature = "tracing")]
+use tracing::{debug, warn};
+
+use super::callbacks::get_context;
+use super::ffi;
+use super::mail;
+use crate::execution::{SecurityPolicy, SecurityViolation};
+
+// Handler installed before ours, if any. Only written during module startup,
+// under the engine lifecycle lock.
+static mut PREVIOUS_EXECUTE_INTERNAL: ffi::zend_execute_internal_t = None;
+
+// Internal classes whose `create_object` we replaced, with the handler each
+// had before. The replacement stays for the module's lifetime, so classes
+// that inherited it keep working.
+static GUARDED_CLASSES: RwLock<Vec<(usize, ffi::zend_create_object_t)>> =
+    RwLock::new(Vec::new());
+
+/// Routes internal function calls through the policy check.
+///
+/// Must run once after each module startup, which resets
+/// `zend_execute_internal` (extensions may have set their own since) and
+/// recreates the internal classes.
+pub(crate) unsafe fn install() {
+    PREVIOUS_EXECUTE_INTERNAL = ffi::zend_execute_internal;
+    ffi::zend_execute_internal = Some(ripht_execute_internal);
+
+    GUARDED_CLASSES
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clear();
+}
+
+/// Routes instantiation of the policy's disabled classes through the policy
+/// check. Call from SAPI activation, before the script runs.
+pub(crate) unsafe fn guard_classes(policy: &SecurityPolicy) {
+    let Some(init) = ffi::zend_string_init_interned else {
+        return;
+    };
+
+    let mut guarded = GUARDED_CLASSES
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    for name in policy.disabled_classes() {
+        let name = name.trim_start_matches('\\');
+        let name = init(name.as_ptr() as *const _, name.len(), true);
+        if name.is_null() {
+            continue;
+        }
+
+        let ce = ffi::zend_lookup_class_ex(
+            name,
+            std::ptr::null_mut(),
+            ffi::ZEND_FETCH_CLASS_NO_AUTOLOAD,
+        );
+        if ce.is_null()
+            || (*ce).type_ != ffi::ZEND_INTERNAL_CLASS
+            || guarded
+                .iter()
+                .any(|&(class, _)| class == ce as usize)
+        {
+            continue;
+        }
+
+        // Internal classes are shared by every thread in ZTS builds; the
+        // handler is swapped once, as a single pointer store.
+        guarded.push((ce as usize, (*ce).create_object));
+        (*ce).create_object = Some(ripht_create_object);
+    }
+}
+
+/// Applies the policy's INI directives. Call from SAPI activation.
+pub(crate) unsafe fn apply_ini(policy: &SecurityPolicy) {
+    let Some(init) = ffi::zend_string_init_interned else {
+        return;
+    };
+
+    for (key, value) in policy.ini_entries() {
+        let Ok(value) = CString::new(value) else {
+            continue;
+        };
+
+        let name = init(key.as_ptr() as *const _, key.len(), true);
+        if name.is_null() {
+            continue;
+        }
+
+        let _result = ffi::zend_alter_ini_entry_chars(
+            name,
+            value.as_ptr(),
+            value.as_bytes().len(),
+            ffi::ZEND_INI_SYSTEM,
+            ffi::ZEND_INI_STAGE_ACTIVATE,
+        );
+
+        #[cfg(feature = "tracing")]
+        if _result == ffi::FAILURE {
+            warn!(directive = key, "Security policy directive not applied");
+        }
+    }
+}
+
+/// Violation for calling `func` under `policy`, if it's blocked.
+unsafe fn check_call(
+    policy: &SecurityPolicy,
+    func: *const ffi::zend_function,
+) -> Option<SecurityViolation> {
+    if func.is_null()
+        || (*func)
+            .function_name
+            .is_null()
+    {
+        return None;
+    }
+
+    let scope = (*func).scope;
+    if !scope.is_null() && !(*scope).name.is_null() {
+        let class = String::from_utf8_lossy(ffi::zstr_bytes((*scope).name));
+
+        return policy
+            .disables_class(&class)
+            .then(|| SecurityViolation::DisabledClass {
+                name: class.into_owned(),
+            });
+    }
+
+    let name = String::from_utf8_lossy(ffi::zstr_bytes((*func).function_name));
+
+    policy
+        .disables_function(&name)
+        .then(|| SecurityViolation::DisabledFunction {
+            name: name.into_owned(),
+        })
+}
+
+/// Disabled class `ce` is, or extends, under the current request's policy.
+unsafe fn disabled_ancestor(
+    mut ce: *const ffi::zend_class_entry,
+) -> Option<SecurityViolation> {
+    let ctx_ptr = get_context()?;
+    let policy = (*ctx_ptr)
+        .security_policy
+        .as_ref()
+        .filter(|policy| policy.disables_calls())?;
+
+    while !ce.is_null() {
+        let class = String::from_utf8_lossy(ffi::zstr_bytes((*ce).name));
+        if policy.disables_class(&class) {
+            let violation = SecurityViolation::DisabledClass {
+                name: class.into_owned(),
+            };
+            (*ctx_ptr)
+                .security_violations
+                .push(violation.clone());
+
+            return Some(violation);
+        }
+
+        ce = (*ce).parent;
+    }
+
+    None
+}
+
+/// `create_object` of guarded classes and everything inheriting it.
+unsafe extern "C" fn ripht_create_object(
+    class_type: *mut ffi::zend_class_entry,
+) -> *mut ffi::zend_object {
+    if let Some(violation) = disabled_ancestor(class_type) {
+        #[cfg(feature = "tracing")]
+        debug!(%violation, "Blocked instantiation");
+
+        throw(&violation);
+
+        // A constructor-less placeholder: `new` sees no constructor and
+        // unwinds with the exception before the object is ever used.
+        return ffi::zend_objects_new(ffi::zend_standard_class_def);
+    }
+
+    let original = {
+        let guarded = GUARDED_CLASSES
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut ce = class_type;
+
+        loop {
+            if ce.is_null() {
+                break None;
+            }
+            if let Some(&(_, original)) = guarded
+                .iter()
+                .find(|&&(class, _)| class == ce as usize)
+            {
+                break original;
+            }
+            ce = (*ce).parent;
+        }
+    };
+
+    match original {
+        Some(create_object) => create_object(class_type),
+        None => {
+            let object = ffi::zend_objects_new(class_type);
+            ffi::object_properties_init(object, class_type);
+            object
+        }
+    }
+}
+
+unsafe fn throw(violation: &SecurityViolation) {
+    if let Ok(message) = CString::new(violation.to_string()) {
+        ffi::zend_throw_error(
+            std::ptr::null_mut(),
+            c"%s".as_ptr(),
+            message.as_ptr(),
+        );
+    }
+}
+
+unsafe extern "C" fn ripht_execute_internal(
+    execute_data: *mut ffi::zend_execute_data,
+    return_value: *mut ffi::zval,
+) {
+    let violation = get_context().and_then(|ctx_ptr| {
+        let policy = (*ctx_ptr)
+            .security_policy
+            .as_ref()
+            .filter(|policy| policy.disables_calls())?;
+
+        let violation = check_call(policy, (*execute_data).func)?;
+        (*ctx_ptr)
+            .security_violations
+            .push(violation.clone());
+
+        Some(violation)
+    });
+
+    if let Some(violation) = violation {
+        #[cfg(feature = "tracing")]
+        debug!(%violation, "Blocked call");
+
+        // The VM nulls `return_value` before the call; throwing is enough.
+        throw(&violation);
+        return;
+    }
+
+    if mail::intercept(execute_data, return_value) {
+        return;
+    }
+
+    match PREVIOUS_EXECUTE_INTERNAL {
+        Some(previous) => previous(execute_data, return_value),
+        None => ffi::execute_internal(execute_data, return_value),
+    }
+}
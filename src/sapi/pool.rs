@@ -0,0 +1,150 @@
+//! Multi-threaded execution for ZTS builds.
+//!
+//! Each worker thread gets its own TSRM storage, so requests on different
+//! workers run in parallel without sharing PHP globals.
+
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+#[cfg(feature = "tracing")]
+use tracing::debug;
+
+use super::thread::{run_job, submit, Job};
+use super::{ExecutionError, RiphtSapi, SapiError};
+use crate::execution::{ExecutionContext, ExecutionHooks, ExecutionResult};
+
+/// Runs up to N requests concurrently on N worker threads.
+///
+/// Only available when linked against a ZTS `libphp.a`. The engine is
+/// started with default configuration if it isn't running yet; use
+/// [`RiphtSapi::builder()`] beforehand for custom startup INI.
+///
+/// Dropping the pool finishes queued jobs and joins the workers. Drop it
+/// before calling [`RiphtSapi::shutdown()`].
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use ripht_php_sapi::{ExecutorPool, WebRequest};
+///
+/// let pool = Arc::new(ExecutorPool::new(4).expect("pool startup"));
+///
+/// let handles: Vec<_> = (0..8)
+///     .map(|_| {
+///         let pool = Arc::clone(&pool);
+///         std::thread::spawn(move || {
+///             let ctx = WebRequest::get()
+///                 .build("/var/www/index.php")
+///                 .expect("build");
+///             pool.execute(ctx)
+///         })
+///     })
+///     .collect();
+///
+/// for handle in handles {
+///     let result = handle.join().unwrap().expect("execute");
+///     println!("{}", result.status_code());
+/// }
+/// ```
+pub struct ExecutorPool {
+    sender: Option<mpsc::Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ExecutorPool {
+    /// Starts `threads` workers (at least one).
+    pub fn new(threads: usize) -> Result<Self, SapiError> {
+        if !RiphtSapi::init()?.is_initialized() {
+            return Err(SapiError::NotInitialized);
+        }
+
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let mut workers = Vec::with_capacity(threads.max(1));
+
+        for id in 0..threads.max(1) {
+            let receiver = Arc::clone(&receiver);
+
+            let worker = thread::Builder::new()
+                .name(format!("ripht-php-{id}"))
+                .spawn(move || Self::work(&receiver))
+                .map_err(|e| SapiError::InitializationFailed(e.to_string()))?;
+
+            workers.push(worker);
+        }
+
+        #[cfg(feature = "tracing")]
+        debug!(threads = workers.len(), "Executor pool started");
+
+        Ok(Self {
+            sender: Some(sender),
+            workers,
+        })
+    }
+
+    fn work(receiver: &Mutex<mpsc::Receiver<Job>>) {
+        let sapi = RiphtSapi::handle();
+
+        loop {
+            let job = receiver
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .recv();
+
+            match job {
+                Ok(job) => run_job(job, &sapi),
+                Err(_) => break,
+            }
+        }
+    }
+
+    /// Number of worker threads.
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Runs `f` on the next free worker and returns its result.
+    ///
+    /// A panic inside `f` is resumed on the calling thread.
+    pub fn run<F, R>(&self, f: F) -> Result<R, SapiError>
+    where
+        F: FnOnce(&RiphtSapi) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        submit(
+            self.sender
+                .as_ref()
+                .ok_or(SapiError::NotInitialized)?,
+            f,
+        )
+    }
+
+    pub fn execute(
+        &self,
+        ctx: ExecutionContext,
+    ) -> Result<ExecutionResult, ExecutionError> {
+        self.run(move |sapi| sapi.execute(ctx))?
+    }
+
+    pub fn execute_with_hooks<H>(
+        &self,
+        ctx: ExecutionContext,
+        hooks: H,
+    ) -> Result<ExecutionResult, ExecutionError>
+    where
+        H: ExecutionHooks + Send + 'static,
+    {
+        self.run(move |sapi| sapi.execute_with_hooks(ctx, hooks))?
+    }
+}
+
+impl Drop for ExecutorPool {
+    fn drop(&mut self) {
+        // Workers exit once the channel is closed and drained.
+        drop(self.sender.take());
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//! Fork-after-init executor for crash isolation.
+//!
+//! The parent starts the engine once, then forks a [`zygote`] that forks
+//! worker processes on demand. Workers inherit the initialized engine
+//! copy-on-write. Requests travel over a socketpair using the encoding in
+//! [`wire`]. A worker that dies mid-request (segfault, `abort()` in an
+//! extension, ...) only fails that request.
+
+mod wire;
+mod zygote;
+
+use std::io;
+use std::os::unix::net::UnixStream;
+use std::sync::{Condvar, Mutex, MutexGuard};
+
+#[cfg(feature = "tracing")]
+use tracing::{debug, warn};
+
+use super::{ExecutionError, RiphtSapi, SapiError};
+use crate::execution::{ExecutionContext, ExecutionResult};
+use zygote::{Exit, Zygote};
+
+/// Executes requests in forked worker processes.
+///
+/// Each worker handles one request at a time; up to N requests run in
+/// parallel, submitted from any thread. A worker that crashes is reported as
+/// [`ExecutionError::WorkerCrashed`] and replaced with a fresh fork.
+///
+/// The engine is started with default configuration if it isn't running
+/// yet; use [`RiphtSapi::builder()`] beforehand for custom startup INI.
+/// Workers are forked from a copy of the process taken by [`new()`], so they
+/// keep the engine configuration of that moment.
+///
+/// [`new()`] must run while the process has a single thread, e.g. early in
+/// `main` before any thread is spawned: a forked child of a multi-threaded
+/// process may only make async-signal-safe calls. Where the OS reports the
+/// thread count (Linux), it fails with [`SapiError::MultipleThreads`]
+/// otherwise.
+///
+/// [`new()`]: Self::new
+///
+/// ```no_run
+/// use ripht_php_sapi::{ExecutionError, PreforkExecutor, WebRequest};
+///
+/// let prefork = PreforkExecutor::new(4).expect("fork workers");
+///
+/// let ctx = WebRequest::get()
+///     .build("/var/www/index.php")
+///     .expect("build");
+///
+/// match prefork.execute(ctx) {
+///     Ok(result) => println!("{}", result.status_code()),
+///     Err(ExecutionError::WorkerCrashed { pid, signal }) => {
+///         eprintln!("worker {pid} crashed (signal {signal:?})");
+///     }
+///     Err(e) => eprintln!("{e}"),
+/// }
+/// ```
+pub struct PreforkExecutor {
+    state: Mutex<PoolState>,
+    available: Condvar,
+    size: usize,
+}
+
+struct PoolState {
+    idle: Vec<Worker>,
+    live: usize,
+    zygote: Zygote,
+}
+
+struct Worker {
+    pid: libc::pid_t,
+    stream: UnixStream,
+}
+
+/// Why a round trip to a worker failed.
+enum Failure {
+    /// The connection broke, usually because the worker died.
+    Io(io::Error),
+    /// A frame couldn't be encoded or decoded.
+    Protocol(io::Error),
+}
+
+impl PreforkExecutor {
+    /// Starts the engine if needed, forks the zygote and `workers` processes
+    /// (at least one).
+    pub fn new(workers: usize) -> Result<Self, SapiError> {
+        if !RiphtSapi::init()?.is_initialized() {
+            return Err(SapiError::NotInitialized);
+        }
+
+        let executor = Self {
+            state: Mutex::new(PoolState {
+                idle: Vec::new(),
+                live: 0,
+                zygote: Zygote::fork()?,
+            }),
+            available: Condvar::new(),
+            size: workers.max(1),
+        };
+
+        {
+            let mut state = executor.lock();
+
+            for _ in 0..executor.size {
+                state.spawn().map_err(|e| {
+                    SapiError::InitializationFailed(e.to_string())
+                })?;
+            }
+        }
+
+        #[cfg(feature = "tracing")]
+        debug!(workers = executor.size, "Prefork workers started");
+
+        Ok(executor)
+    }
+
+    /// Number of worker processes the executor maintains.
+    pub fn workers(&self) -> usize {
+        self.size
+    }
+
+    pub fn execute(
+        &self,
+        ctx: ExecutionContext,
+    ) -> Result<ExecutionResult, ExecutionError> {
+        let mut worker = self.checkout()?;
+
+        match worker.round_trip(&ctx) {
+            Ok(reply) => {
+                self.lock().idle.push(worker);
+                self.available.notify_one();
+
+                reply
+            }
+            Err(failure) => {
+                let mut state = self.lock();
+                let err = state.retire(worker, failure);
+
+                // Replace right away; if that fails, the next checkout retries.
+                if let Err(_e) = state.spawn() {
+                    #[cfg(feature = "tracing")]
+                    warn!(error = %_e, "Failed to replace prefork worker");
+                }
+
+                drop(state);
+                self.available.notify_one();
+
+                Err(err)
+            }
+        }
+    }
+
+    fn checkout(&self) -> Result<Worker, ExecutionError> {
+        let mut state = self.lock();
+
+        loop {
+            if let Some(worker) = state.idle.pop() {
+                return Ok(worker);
+            }
+
+            if state.live < self.size {
+                state
+                    .spawn()
+                    .map_err(|e| ExecutionError::WorkerIo(e.to_string()))?;
+                continue;
+            }
+
+            state = self
+                .available
+                .wait(state)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PoolState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl PoolState {
+    /// Forks a new worker into the idle list.
+    fn spawn(&mut self) -> io::Result<()> {
+        let (pid, stream) = self.zygote.spawn()?;
+
+        self.idle
+            .push(Worker { pid, stream });
+        self.live += 1;
+
+        Ok(())
+    }
+
+    /// Collects a worker whose round trip failed and describes why.
+    fn retire(&mut self, worker: Worker, failure: Failure) -> ExecutionError {
+        self.live -= 1;
+
+        // The worker may be mid-frame either way, so it can't be reused.
+        let status = self.zygote.reap(worker.pid);
+
+        let io_error = match failure {
+            Failure::Protocol(e) => {
+                #[cfg(feature = "tracing")]
+                warn!(pid = worker.pid, error = %e, "Prefork protocol error");
+
+                return ExecutionError::WorkerProtocol(e.to_string());
+            }
+            Failure::Io(e) => e,
+        };
+
+        // Only a worker that was already gone crashed; a live one just broke
+        // the connection (and has been killed now).
+        let Ok(Some(Exit::Died(status))) = status else {
+            return ExecutionError::WorkerIo(io_error.to_string());
+        };
+
+        let signal = libc::WIFSIGNALED(status).then(|| libc::WTERMSIG(status));
+
+        #[cfg(feature = "tracing")]
+        warn!(pid = worker.pid, signal = ?signal, "Prefork worker died");
+
+        ExecutionError::WorkerCrashed {
+            pid: worker.pid as u32,
+            signal,
+        }
+    }
+}
+
+impl Worker {
+    fn round_trip(
+        &mut self,
+        ctx: &ExecutionContext,
+    ) -> Result<Result<ExecutionResult, ExecutionError>, Failure> {
+        wire::write_frame(&mut self.stream, &wire::encode_context(ctx))
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::InvalidInput => Failure::Protocol(e),
+                _ => Failure::Io(e),
+            })?;
+        let reply = wire::read_frame(&mut self.stream).map_err(Failure::Io)?;
+
+        wire::decode_reply(&reply).map_err(Failure::Protocol)
+    }
+}
+
+/// Worker loop: decode a request, execute it, reply. Returns on EOF.
+fn serve(mut stream: UnixStream) {
+    let sapi = RiphtSapi::handle();
+
+    while let Ok(frame) = wire::read_frame(&mut stream) {
+        let reply = match wire::decode_context(&frame) {
+            Ok(ctx) => sapi.execute(ctx),
+            Err(e) => Err(ExecutionError::WorkerProtocol(e.to_string())),
+        };
+
+        if wire::write_frame(&mut stream, &wire::encode_reply(&reply)).is_err()
+        {
+            break;
+        }
+    }
+}
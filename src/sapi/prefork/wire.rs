@@ -0,0 +1,914 @@
+//! Length-prefixed wire encoding between the parent and prefork workers.
+//!
+//! Every frame is a little-endian `u32` length followed by the payload.
+//! Variable-length fields inside a payload use the same `u32` prefix.
+
+use std::ffi::OsStr;
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::execution::{
+    BufferGrowth, BufferPolicy, ExecutionContext, ExecutionMessage,
+    ExecutionMetrics, ExecutionResult, InformationalResponse, MailMessage,
+    MemoryUsage, OverflowAction, ResponseHeader, SecurityPolicy,
+    SecurityViolation, StatusLine, SyslogLevel,
+};
+use crate::sapi::{ExecutionError, ServerVars};
+
+const REPLY_OK: u8 = 0;
+const REPLY_INVALID_PATH: u8 = 1;
+const REPLY_SCRIPT_NOT_FOUND: u8 = 2;
+const REPLY_STARTUP_FAILED: u8 = 3;
+const REPLY_WORKER_IO: u8 = 4;
+const REPLY_OUTPUT_LIMIT: u8 = 5;
+const REPLY_MEMORY_LIMIT: u8 = 6;
+const REPLY_NOT_INITIALIZED: u8 = 7;
+const REPLY_WORKER_CRASHED: u8 = 8;
+const REPLY_WORKER_PROTOCOL: u8 = 9;
+const REPLY_UNENFORCEABLE_POLICY: u8 = 10;
+
+pub(super) fn write_frame<W: Write>(
+    w: &mut W,
+    payload: &[u8],
+) -> io::Result<()> {
+    let len = u32::try_from(payload.len()).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidInput, "frame too large")
+    })?;
+
+    w.write_all(&len.to_le_bytes())?;
+    w.write_all(payload)?;
+    w.flush()
+}
+
+pub(super) fn read_frame<R: Read>(r: &mut R) -> io::Result<Vec<u8>> {
+    let mut len_buf = [0u8; 4];
+    r.read_exact(&mut len_buf)?;
+
+    let mut payload = vec![0u8; u32::from_le_bytes(len_buf) as usize];
+    r.read_exact(&mut payload)?;
+
+    Ok(payload)
+}
+
+pub(super) fn encode_context(ctx: &ExecutionContext) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(256 + ctx.input.len());
+
+    put_bytes(
+        &mut buf,
+        ctx.script_path
+            .as_os_str()
+            .as_bytes(),
+    );
+    put_bytes(&mut buf, &ctx.input);
+    put_pairs(&mut buf, ctx.server_vars.len(), ctx.server_vars.iter());
+    put_pairs(&mut buf, ctx.env_vars.len(), &ctx.env_vars);
+    put_pairs(&mut buf, ctx.ini_overrides.len(), &ctx.ini_overrides);
+    buf.push(ctx.log_to_stderr as u8);
+    put_buffer_policy(&mut buf, ctx.buffer_policy.as_ref());
+    buf.extend_from_slice(
+        &ctx.max_response_size
+            .map_or(u64::MAX, |bytes| bytes as u64)
+            .to_le_bytes(),
+    );
+    put_security_policy(&mut buf, ctx.security_policy.as_ref());
+    buf.extend_from_slice(
+        &ctx.memory_limit
+            .map_or(u64::MAX, |bytes| bytes as u64)
+            .to_le_bytes(),
+    );
+    buf.extend_from_slice(
+        &ctx.request_time
+            .map_or(u64::MAX, |time| {
+                time.duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos() as u64
+            })
+            .to_le_bytes(),
+    );
+    buf.extend_from_slice(
+        &ctx.random_seed
+            .map_or(u64::MAX, u64::from)
+            .to_le_bytes(),
+    );
+
+    buf
+}
+
+pub(super) fn decode_context(payload: &[u8]) -> io::Result<ExecutionContext> {
+    let mut r = Reader(payload);
+
+    let script_path = PathBuf::from(OsStr::from_bytes(r.bytes()?));
+    let input = r.bytes()?.to_vec();
+
+    let mut server_vars = ServerVars::new();
+    server_vars.extend(r.pairs()?);
+
+    let mut ctx = ExecutionContext::script(script_path);
+    ctx.input = input;
+    ctx.server_vars = server_vars;
+    ctx.env_vars = r.pairs()?;
+    ctx.ini_overrides = r.pairs()?;
+    ctx.log_to_stderr = r.u8()? != 0;
+    ctx.buffer_policy = r.buffer_policy()?;
+    ctx.max_response_size = match r.u64()? {
+        u64::MAX => None,
+        bytes => Some(bytes as usize),
+    };
+    ctx.security_policy = r.security_policy()?;
+    ctx.memory_limit = match r.u64()? {
+        u64::MAX => None,
+        bytes => Some(bytes as usize),
+    };
+    ctx.request_time = match r.u64()? {
+        u64::MAX => None,
+        nanos => Some(UNIX_EPOCH + Duration::from_nanos(nanos)),
+    };
+    ctx.random_seed = match r.u64()? {
+        u64::MAX => None,
+        seed => Some(seed as u32),
+    };
+
+    Ok(ctx)
+}
+
+pub(super) fn encode_reply(
+    reply: &Result<ExecutionResult, ExecutionError>,
+) -> Vec<u8> {
+    let mut buf = Vec::new();
+
+    match reply {
+        Ok(result) => {
+            let body = result.body_bytes();
+
+            buf.reserve(64 + body.len());
+            buf.push(REPLY_OK);
+            buf.extend_from_slice(
+                &result
+                    .status_code()
+                    .to_le_bytes(),
+            );
+            buf.push(result.is_truncated() as u8);
+            put_bytes(
+                &mut buf,
+                result
+                    .status_line()
+                    .map(ToString::to_string)
+                    .unwrap_or_default()
+                    .as_bytes(),
+            );
+            put_bytes(&mut buf, body);
+
+            let headers: Vec<_> = result.all_headers().collect();
+            put_len(&mut buf, headers.len());
+            for header in headers {
+                put_bytes(&mut buf, header.name().as_bytes());
+                put_bytes(&mut buf, header.value().as_bytes());
+            }
+
+            let messages: Vec<_> = result
+                .all_messages()
+                .collect();
+            put_len(&mut buf, messages.len());
+            for message in messages {
+                buf.push(message.level as u8);
+                put_bytes(&mut buf, message.message.as_bytes());
+            }
+
+            put_len(&mut buf, result.informational().len());
+            for response in result.informational() {
+                buf.extend_from_slice(
+                    &response
+                        .status()
+                        .to_le_bytes(),
+                );
+                put_len(&mut buf, response.headers().len());
+                for header in response.headers() {
+                    put_bytes(&mut buf, header.name().as_bytes());
+                    put_bytes(&mut buf, header.value().as_bytes());
+                }
+            }
+
+            put_len(
+                &mut buf,
+                result
+                    .security_violations()
+                    .len(),
+            );
+            for violation in result.security_violations() {
+                put_violation(&mut buf, violation);
+            }
+
+            let usage = result.memory_usage();
+            buf.extend_from_slice(&(usage.current as u64).to_le_bytes());
+            buf.extend_from_slice(&(usage.peak as u64).to_le_bytes());
+
+            put_metrics(&mut buf, result.metrics());
+
+            put_len(&mut buf, result.mail().len());
+            for message in result.mail() {
+                put_mail(&mut buf, message);
+            }
+        }
+        Err(ExecutionError::InvalidPath(reason)) => {
+            buf.push(REPLY_INVALID_PATH);
+            put_bytes(&mut buf, reason.as_bytes());
+        }
+        Err(ExecutionError::ScriptNotFound(path)) => {
+            buf.push(REPLY_SCRIPT_NOT_FOUND);
+            put_bytes(&mut buf, path.as_os_str().as_bytes());
+        }
+        Err(ExecutionError::StartupFailed) => buf.push(REPLY_STARTUP_FAILED),
+        Err(ExecutionError::OutputLimitExceeded { limit }) => {
+            buf.push(REPLY_OUTPUT_LIMIT);
+            buf.extend_from_slice(&(*limit as u64).to_le_bytes());
+        }
+        Err(ExecutionError::MemoryLimitExceeded { limit, peak_usage }) => {
+            buf.push(REPLY_MEMORY_LIMIT);
+            buf.extend_from_slice(&(*limit as u64).to_le_bytes());
+            buf.extend_from_slice(&(*peak_usage as u64).to_le_bytes());
+        }
+        Err(ExecutionError::NotInitialized) => buf.push(REPLY_NOT_INITIALIZED),
+        Err(ExecutionError::WorkerCrashed { pid, signal }) => {
+            buf.push(REPLY_WORKER_CRASHED);
+            buf.extend_from_slice(&pid.to_le_bytes());
+            put_signal(&mut buf, *signal);
+        }
+        Err(ExecutionError::WorkerIo(reason)) => {
+            buf.push(REPLY_WORKER_IO);
+            put_bytes(&mut buf, reason.as_bytes());
+        }
+        Err(ExecutionError::WorkerProtocol(reason)) => {
+            buf.push(REPLY_WORKER_PROTOCOL);
+            put_bytes(&mut buf, reason.as_bytes());
+        }
+        Err(ExecutionError::UnenforceablePolicy(reason)) => {
+            buf.push(REPLY_UNENFORCEABLE_POLICY);
+            put_bytes(&mut buf, reason.as_bytes());
+        }
+        // Thread ids don't cross processes, and a worker's only thread owns
+        // its engine, so this can't come from a worker in practice.
+        Err(err @ ExecutionError::WrongThread { .. }) => {
+            buf.push(REPLY_WORKER_IO);
+            put_bytes(&mut buf, err.to_string().as_bytes());
+        }
+    }
+
+    buf
+}
+
+pub(super) fn decode_reply(
+    payload: &[u8],
+) -> io::Result<Result<ExecutionResult, ExecutionError>> {
+    let mut r = Reader(payload);
+
+    let reply = match r.u8()? {
+        REPLY_OK => {
+            let status = u16::from_le_bytes([r.u8()?, r.u8()?]);
+            let truncated = r.u8()? != 0;
+            let status_line = StatusLine::parse(&r.string()?);
+            let body = r.bytes()?.to_vec();
+
+            let headers = r.headers()?;
+
+            let message_count = r.len()?;
+            let mut messages = Vec::with_capacity(message_count.min(1024));
+            for _ in 0..message_count {
+                let level = SyslogLevel::from_raw(r.u8()? as i32);
+                messages.push(ExecutionMessage::new(level, r.string()?));
+            }
+
+            let informational_count = r.len()?;
+            let mut informational =
+                Vec::with_capacity(informational_count.min(16));
+            for _ in 0..informational_count {
+                let status = u16::from_le_bytes([r.u8()?, r.u8()?]);
+                informational
+                    .push(InformationalResponse::new(status, r.headers()?));
+            }
+
+            let violation_count = r.len()?;
+            let mut violations = Vec::with_capacity(violation_count.min(64));
+            for _ in 0..violation_count {
+                violations.push(r.violation()?);
+            }
+
+            let usage = MemoryUsage::new(r.u64()? as usize, r.u64()? as usize);
+            let metrics = r.metrics()?;
+
+            let mail_count = r.len()?;
+            let mut mail = Vec::with_capacity(mail_count.min(64));
+            for _ in 0..mail_count {
+                mail.push(r.mail()?);
+            }
+
+            Ok(ExecutionResult::new(status, body, headers, messages)
+                .with_truncated(truncated)
+                .with_sent_status_line(status_line)
+                .with_informational(informational)
+                .with_security_violations(violations)
+                .with_memory_usage(usage)
+                .with_metrics(metrics)
+                .with_mail(mail))
+        }
+        REPLY_INVALID_PATH => Err(ExecutionError::InvalidPath(r.string()?)),
+        REPLY_SCRIPT_NOT_FOUND => Err(ExecutionError::ScriptNotFound(
+            PathBuf::from(OsStr::from_bytes(r.bytes()?)),
+        )),
+        REPLY_STARTUP_FAILED => Err(ExecutionError::StartupFailed),
+        REPLY_WORKER_IO => Err(ExecutionError::WorkerIo(r.string()?)),
+        REPLY_OUTPUT_LIMIT => Err(ExecutionError::OutputLimitExceeded {
+            limit: r.u64()? as usize,
+        }),
+        REPLY_MEMORY_LIMIT => Err(ExecutionError::MemoryLimitExceeded {
+            limit: r.u64()? as usize,
+            peak_usage: r.u64()? as usize,
+        }),
+        REPLY_NOT_INITIALIZED => Err(ExecutionError::NotInitialized),
+        REPLY_WORKER_CRASHED => Err(ExecutionError::WorkerCrashed {
+            pid: r.u32()?,
+            signal: r.signal()?,
+        }),
+        REPLY_WORKER_PROTOCOL => {
+            Err(ExecutionError::WorkerProtocol(r.string()?))
+        }
+        REPLY_UNENFORCEABLE_POLICY => {
+            Err(ExecutionError::UnenforceablePolicy(r.string()?))
+        }
+        tag => return Err(invalid(format!("unknown reply tag {tag}"))),
+    };
+
+    Ok(reply)
+}
+
+fn put_len(buf: &mut Vec<u8>, len: usize) {
+    buf.extend_from_slice(&(len as u32).to_le_bytes());
+}
+
+fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    put_len(buf, bytes.len());
+    buf.extend_from_slice(bytes);
+}
+
+fn put_pairs<'a>(
+    buf: &mut Vec<u8>,
+    len: usize,
+    pairs: impl IntoIterator<Item = &'a (String, String)>,
+) {
+    put_len(buf, len);
+    for (key, value) in pairs {
+        put_bytes(buf, key.as_bytes());
+        put_bytes(buf, value.as_bytes());
+    }
+}
+
+fn put_signal(buf: &mut Vec<u8>, signal: Option<i32>) {
+    match signal {
+        Some(signal) => {
+            buf.push(1);
+            buf.extend_from_slice(&signal.to_le_bytes());
+        }
+        None => buf.push(0),
+    }
+}
+
+fn put_buffer_policy(buf: &mut Vec<u8>, policy: Option<&BufferPolicy>) {
+    let Some(policy) = policy else {
+        buf.push(0);
+        return;
+    };
+
+    let (growth, step) = match policy.growth() {
+        BufferGrowth::X4 => (0, 0),
+        BufferGrowth::X2 => (1, 0),
+        BufferGrowth::Fixed(step) => (2, step),
+    };
+
+    buf.push(1);
+    buf.extend_from_slice(&(policy.initial_capacity() as u64).to_le_bytes());
+    buf.push(growth);
+    buf.extend_from_slice(&(step as u64).to_le_bytes());
+    buf.extend_from_slice(
+        &(policy
+            .max_size()
+            .map_or(u64::MAX, |max| max as u64))
+        .to_le_bytes(),
+    );
+    buf.push(matches!(policy.on_overflow(), OverflowAction::Truncate) as u8);
+}
+
+fn put_strings<'a>(
+    buf: &mut Vec<u8>,
+    items: impl ExactSizeIterator<Item = &'a str>,
+) {
+    put_len(buf, items.len());
+    for item in items {
+        put_bytes(buf, item.as_bytes());
+    }
+}
+
+fn put_security_policy(buf: &mut Vec<u8>, policy: Option<&SecurityPolicy>) {
+    let Some(policy) = policy else {
+        buf.push(0);
+        return;
+    };
+
+    // 0 = unset, 1 = off, 2 = on
+    let flag = |allow: Option<bool>| allow.map_or(0, |on| 1 + on as u8);
+
+    buf.push(1);
+    put_len(buf, policy.open_basedir().len());
+    for root in policy.open_basedir() {
+        put_bytes(buf, root.as_os_str().as_bytes());
+    }
+    put_strings(
+        buf,
+        policy
+            .disabled_functions()
+            .iter()
+            .map(String::as_str),
+    );
+    put_strings(
+        buf,
+        policy
+            .disabled_classes()
+            .iter()
+            .map(String::as_str),
+    );
+    buf.push(flag(policy.allow_url_fopen()));
+    buf.push(flag(policy.allow_url_include()));
+    buf.extend_from_slice(
+        &policy
+            .max_upload_size()
+            .unwrap_or(u64::MAX)
+            .to_le_bytes(),
+    );
+}
+
+fn put_metrics(buf: &mut Vec<u8>, metrics: &ExecutionMetrics) {
+    let durations = [
+        metrics.context,
+        metrics.startup,
+        metrics.script,
+        metrics.send_headers,
+        metrics.shutdown,
+        metrics.total,
+    ];
+    for duration in durations {
+        buf.extend_from_slice(&(duration.as_nanos() as u64).to_le_bytes());
+    }
+
+    for count in [
+        metrics.output_bytes,
+        metrics.body_bytes,
+        metrics.header_bytes,
+    ] {
+        buf.extend_from_slice(&(count as u64).to_le_bytes());
+    }
+}
+
+fn put_mail(buf: &mut Vec<u8>, message: &MailMessage) {
+    put_bytes(
+        buf,
+        message
+            .to()
+            .join(", ")
+            .as_bytes(),
+    );
+    put_bytes(buf, message.subject().as_bytes());
+    put_pairs(buf, message.headers().len(), message.headers());
+    put_bytes(buf, message.body().as_bytes());
+    match message.additional_params() {
+        Some(params) => {
+            buf.push(1);
+            put_bytes(buf, params.as_bytes());
+        }
+        None => buf.push(0),
+    }
+}
+
+fn put_violation(buf: &mut Vec<u8>, violation: &SecurityViolation) {
+    match violation {
+        SecurityViolation::DisabledFunction { name } => {
+            buf.push(0);
+            put_bytes(buf, name.as_bytes());
+        }
+        SecurityViolation::DisabledClass { name } => {
+            buf.push(1);
+            put_bytes(buf, name.as_bytes());
+        }
+        SecurityViolation::OpenBasedir { path } => {
+            buf.push(2);
+            put_bytes(buf, path.as_bytes());
+        }
+        SecurityViolation::UrlAccess { wrapper, directive } => {
+            buf.push(3);
+            put_bytes(buf, wrapper.as_bytes());
+            put_bytes(buf, directive.as_bytes());
+        }
+    }
+}
+
+fn invalid(reason: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason.into())
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(invalid("truncated frame"));
+        }
+
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let raw = self.take(4)?;
+        Ok(u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]))
+    }
+
+    fn signal(&mut self) -> io::Result<Option<i32>> {
+        match self.u8()? {
+            0 => Ok(None),
+            _ => Ok(Some(self.u32()? as i32)),
+        }
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let raw = self.take(8)?;
+        Ok(u64::from_le_bytes(
+            raw.try_into()
+                .expect("8 bytes"),
+        ))
+    }
+
+    fn len(&mut self) -> io::Result<usize> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn bytes(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.len()?;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        String::from_utf8(self.bytes()?.to_vec())
+            .map_err(|_| invalid("invalid UTF-8"))
+    }
+
+    fn buffer_policy(&mut self) -> io::Result<Option<BufferPolicy>> {
+        if self.u8()? == 0 {
+            return Ok(None);
+        }
+
+        let initial_capacity = self.u64()? as usize;
+        let growth = match (self.u8()?, self.u64()? as usize) {
+            (0, _) => BufferGrowth::X4,
+            (1, _) => BufferGrowth::X2,
+            (2, step) => BufferGrowth::Fixed(step),
+            (tag, _) => return Err(invalid(format!("unknown growth {tag}"))),
+        };
+        let max_size = self.u64()?;
+        let on_overflow = match self.u8()? {
+            0 => OverflowAction::Abort,
+            _ => OverflowAction::Truncate,
+        };
+
+        let mut policy = BufferPolicy::new()
+            .with_initial_capacity(initial_capacity)
+            .with_growth(growth)
+            .with_overflow(on_overflow);
+
+        if max_size != u64::MAX {
+            policy = policy.with_max_size(max_size as usize);
+        }
+
+        Ok(Some(policy))
+    }
+
+    fn headers(&mut self) -> io::Result<Vec<ResponseHeader>> {
+        Ok(self
+            .pairs()?
+            .into_iter()
+            .map(|(name, value)| ResponseHeader::new(name, value))
+            .collect())
+    }
+
+    fn strings(&mut self) -> io::Result<Vec<String>> {
+        let count = self.len()?;
+        let mut items = Vec::with_capacity(count.min(1024));
+
+        for _ in 0..count {
+            items.push(self.string()?);
+        }
+
+        Ok(items)
+    }
+
+    fn security_policy(&mut self) -> io::Result<Option<SecurityPolicy>> {
+        if self.u8()? == 0 {
+            return Ok(None);
+        }
+
+        let mut policy = SecurityPolicy::new();
+
+        for _ in 0..self.len()? {
+            policy = policy.with_open_basedir(PathBuf::from(
+                OsStr::from_bytes(self.bytes()?),
+            ));
+        }
+        policy = policy.with_disabled_functions(self.strings()?);
+        for class in self.strings()? {
+            policy = policy.with_disabled_class(class);
+        }
+
+        match self.u8()? {
+            0 => {}
+            flag => policy = policy.with_allow_url_fopen(flag == 2),
+        }
+        match self.u8()? {
+            0 => {}
+            flag => policy = policy.with_allow_url_include(flag == 2),
+        }
+        match self.u64()? {
+            u64::MAX => {}
+            bytes => policy = policy.with_max_upload_size(bytes),
+        }
+
+        Ok(Some(policy))
+    }
+
+    fn metrics(&mut self) -> io::Result<ExecutionMetrics> {
+        let mut duration = || {
+            self.u64()
+                .map(Duration::from_nanos)
+        };
+
+        Ok(ExecutionMetrics {
+            context: duration()?,
+            startup: duration()?,
+            script: duration()?,
+            send_headers: duration()?,
+            shutdown: duration()?,
+            total: duration()?,
+            output_bytes: self.u64()? as usize,
+            body_bytes: self.u64()? as usize,
+            header_bytes: self.u64()? as usize,
+        })
+    }
+
+    fn mail(&mut self) -> io::Result<MailMessage> {
+        let to = self.string()?;
+        let subject = self.string()?;
+        let headers = self.pairs()?;
+        let mut message = MailMessage::new(&to, subject, self.string()?);
+
+        for (name, value) in headers {
+            message = message.with_header(name, value);
+        }
+        if self.u8()? != 0 {
+            message = message.with_additional_params(self.string()?);
+        }
+
+        Ok(message)
+    }
+
+    fn violation(&mut self) -> io::Result<SecurityViolation> {
+        Ok(match self.u8()? {
+            0 => SecurityViolation::DisabledFunction {
+                name: self.string()?,
+            },
+            1 => SecurityViolation::DisabledClass {
+                name: self.string()?,
+            },
+            2 => SecurityViolation::OpenBasedir {
+                path: self.string()?,
+            },
+            3 => SecurityViolation::UrlAccess {
+                wrapper: self.string()?,
+                directive: self.string()?,
+            },
+            tag => return Err(invalid(format!("unknown violation {tag}"))),
+        })
+    }
+
+    fn pairs(&mut self) -> io::Result<Vec<(String, String)>> {
+        let count = self.len()?;
+        let mut pairs = Vec::with_capacity(count.min(1024));
+
+        for _ in 0..count {
+            pairs.push((self.string()?, self.string()?));
+        }
+
+        Ok(pairs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context_round_trip() {
+        let ctx = ExecutionContext::script("/var/www/index.php")
+            .var("REQUEST_METHOD", "POST")
+            .var("QUERY_STRING", "a=1")
+            .input(b"payload\0with nul".to_vec())
+            .env("APP_ENV", "test")
+            .ini("memory_limit", "64M");
+
+        let decoded = decode_context(&encode_context(&ctx)).unwrap();
+
+        assert_eq!(decoded.script_path, ctx.script_path);
+        assert_eq!(decoded.input, ctx.input);
+        assert_eq!(
+            decoded
+                .server_vars
+                .get_query_string(),
+            Some("a=1")
+        );
+        assert_eq!(decoded.env_vars, ctx.env_vars);
+        assert_eq!(decoded.ini_overrides, ctx.ini_overrides);
+        assert_eq!(decoded.buffer_policy, None);
+        assert_eq!(decoded.max_response_size, None);
+    }
+
+    #[test]
+    fn test_buffer_policy_round_trip() {
+        let policy = BufferPolicy::new()
+            .with_initial_capacity(8192)
+            .with_growth(BufferGrowth::Fixed(1024))
+            .with_max_size(1 << 20)
+            .with_overflow(OverflowAction::Truncate);
+        let ctx = ExecutionContext::script("/a.php")
+            .buffer_policy(policy)
+            .max_response_size(4096);
+
+        let decoded = decode_context(&encode_context(&ctx)).unwrap();
+
+        assert_eq!(decoded.buffer_policy, Some(policy));
+        assert_eq!(decoded.max_response_size, Some(4096));
+    }
+
+    #[test]
+    fn test_security_policy_round_trip() {
+        let policy = SecurityPolicy::new()
+            .with_open_basedir("/srv/a")
+            .with_disabled_functions(["exec", "system"])
+            .with_disabled_class("ReflectionClass")
+            .with_allow_url_include(false)
+            .with_max_upload_size(1 << 20);
+        let ctx =
+            ExecutionContext::script("/a.php").security_policy(policy.clone());
+
+        let decoded = decode_context(&encode_context(&ctx)).unwrap();
+
+        assert_eq!(decoded.security_policy, Some(policy));
+    }
+
+    #[test]
+    fn test_request_time_and_seed_round_trip() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_250);
+        let ctx = ExecutionContext::script("/a.php")
+            .request_time(time)
+            .random_seed(42);
+
+        let decoded = decode_context(&encode_context(&ctx)).unwrap();
+
+        assert_eq!(decoded.request_time, Some(time));
+        assert_eq!(decoded.random_seed, Some(42));
+    }
+
+    #[test]
+    fn test_memory_limit_round_trip() {
+        let ctx = ExecutionContext::script("/a.php").memory_limit(8 << 20);
+
+        let decoded = decode_context(&encode_context(&ctx)).unwrap();
+
+        assert_eq!(decoded.memory_limit, Some(8 << 20));
+        assert_eq!(
+            decode_context(&encode_context(&ExecutionContext::script(
+                "/a.php"
+            )))
+            .unwrap()
+            .memory_limit,
+            None
+        );
+    }
+
+    fn result_mail() -> Vec<MailMessage> {
+        vec![
+            MailMessage::new("a@example.com, b@example.com", "Hi", "Body")
+                .with_header("From", "app@example.com")
+                .with_additional_params("-fapp@example.com"),
+        ]
+    }
+
+    #[test]
+    fn test_reply_round_trip() {
+        let result = ExecutionResult::new(
+            201,
+            b"created".to_vec(),
+            vec![ResponseHeader::new("Location", "/items/1")],
+            vec![ExecutionMessage::new(SyslogLevel::Warning, "careful")],
+        )
+        .with_status_line(StatusLine::new("HTTP/1.1", 201, "Made It"))
+        .with_informational(vec![InformationalResponse::new(
+            103,
+            vec![ResponseHeader::new("Link", "</a.css>; rel=preload")],
+        )])
+        .with_memory_usage(MemoryUsage::new(1024, 4096))
+        .with_metrics(ExecutionMetrics {
+            script: Duration::from_micros(250),
+            body_bytes: 7,
+            ..ExecutionMetrics::default()
+        })
+        .with_mail(result_mail());
+
+        let decoded = decode_reply(&encode_reply(&Ok(result)))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(decoded.status_code(), 201);
+        assert_eq!(decoded.body(), b"created");
+        assert_eq!(decoded.header_val("Location"), Some("/items/1"));
+        assert!(decoded.has_message_level(SyslogLevel::Warning));
+        assert_eq!(decoded.reason_phrase(), Some("Made It"));
+        assert_eq!(decoded.informational()[0].status(), 103);
+        assert_eq!(
+            decoded.informational()[0]
+                .header_values("link")
+                .next(),
+            Some("</a.css>; rel=preload")
+        );
+        assert_eq!(decoded.memory_usage().peak, 4096);
+        assert_eq!(decoded.metrics().script, Duration::from_micros(250));
+        assert_eq!(decoded.metrics().body_bytes, 7);
+        assert_eq!(decoded.mail(), result_mail().as_slice());
+    }
+
+    #[test]
+    fn test_error_reply_round_trip() {
+        let err = ExecutionError::ScriptNotFound("/missing.php".into());
+
+        let decoded = decode_reply(&encode_reply(&Err(err))).unwrap();
+
+        assert!(matches!(
+            decoded,
+            Err(ExecutionError::ScriptNotFound(path)) if path == std::path::Path::new("/missing.php")
+        ));
+    }
+
+    #[test]
+    fn test_typed_error_replies_round_trip() {
+        let errors = [
+            ExecutionError::MemoryLimitExceeded {
+                limit: 1 << 20,
+                peak_usage: 3 << 20,
+            },
+            ExecutionError::OutputLimitExceeded { limit: 64 },
+            ExecutionError::WorkerCrashed {
+                pid: 42,
+                signal: Some(11),
+            },
+            ExecutionError::NotInitialized,
+            ExecutionError::WorkerProtocol("unknown reply tag 99".into()),
+            ExecutionError::UnenforceablePolicy(
+                "strlen() can't be disabled".into(),
+            ),
+        ];
+
+        for err in errors {
+            let expected = format!("{err:?}");
+            let decoded = decode_reply(&encode_reply(&Err(err)))
+                .unwrap()
+                .unwrap_err();
+
+            assert_eq!(format!("{decoded:?}"), expected);
+        }
+    }
+
+    #[test]
+    fn test_truncated_frame_is_rejected() {
+        let payload = encode_context(&ExecutionContext::script("/a.php"));
+
+        assert!(decode_context(&payload[..payload.len() - 3]).is_err());
+    }
+
+    #[test]
+    fn test_frame_round_trip() {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, b"hello").unwrap();
+
+        assert_eq!(read_frame(&mut buf.as_slice()).unwrap(), b"hello");
+    }
+}
@@ -0,0 +1,449 @@
+//! Single-threaded fork server for prefork workers.
+//!
+//! After `fork()` in a process with other threads, the child may only make
+//! async-signal-safe calls until it execs, yet a worker runs the whole PHP
+//! engine. The zygote is forked once, while the process still has a single
+//! thread, and forks every worker from there. The parent can then use the
+//! executor from any number of threads.
+//!
+//! The parent sends commands over a socketpair: `SPAWN` carries the worker's
+//! end of a fresh socketpair (`SCM_RIGHTS`) and is answered with the pid, and
+//! `REAP` collects a worker, killing it if it's still alive, and answers
+//! with how it ended.
+//! Workers are the zygote's children, so only the zygote can wait for them.
+
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::sapi::{engine, RiphtSapi, SapiError};
+
+const SPAWN: u8 = 1;
+const REAP: u8 = 2;
+
+/// How long a reaped worker gets to become waitable on its own. A dying
+/// process closes its socket just before its exit status is available.
+const EXIT_GRACE: Duration = Duration::from_millis(50);
+
+/// How a reaped worker ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Exit {
+    /// It exited or was killed on its own, with this wait status.
+    Died(libc::c_int),
+    /// It was still running and the zygote killed it.
+    Killed,
+}
+
+pub(super) struct Zygote {
+    pid: libc::pid_t,
+    control: UnixStream,
+}
+
+impl Zygote {
+    /// Forks the zygote from the running engine.
+    ///
+    /// Fails with `MultipleThreads` if the process has other threads (only
+    /// detected on Linux).
+    pub(super) fn fork() -> Result<Self, SapiError> {
+        let (parent, child) = UnixStream::pair()
+            .map_err(|e| SapiError::InitializationFailed(e.to_string()))?;
+
+        // Holding the lifecycle lock keeps the engine from being shut down or
+        // restarted mid-fork, and leaves the zygote a consistent copy of it.
+        let mut lifecycle = engine::lock();
+
+        if !engine::state().is_running() {
+            return Err(SapiError::NotInitialized);
+        }
+
+        if RiphtSapi::request_in_progress() {
+            return Err(SapiError::RequestInProgress);
+        }
+
+        if let Some(threads) = thread_count().filter(|&threads| threads > 1) {
+            return Err(SapiError::MultipleThreads { threads });
+        }
+
+        // SAFETY: The process has a single thread (checked above where the
+        // OS reports it), so the child may keep running normal code.
+        match unsafe { libc::fork() } {
+            -1 => Err(SapiError::InitializationFailed(
+                io::Error::last_os_error().to_string(),
+            )),
+            0 => {
+                drop(parent);
+                lifecycle.claim();
+                drop(lifecycle);
+
+                let code =
+                    match panic::catch_unwind(AssertUnwindSafe(|| run(&child)))
+                    {
+                        Ok(()) => 0,
+                        Err(_) => 101,
+                    };
+
+                // SAFETY: Skips the parent's atexit handlers and destructors,
+                // which must not run twice.
+                unsafe { libc::_exit(code) }
+            }
+            pid => Ok(Self {
+                pid,
+                control: parent,
+            }),
+        }
+    }
+
+    /// Has the zygote fork a worker; returns its pid and socket.
+    pub(super) fn spawn(&mut self) -> io::Result<(libc::pid_t, UnixStream)> {
+        let (parent, child) = UnixStream::pair()?;
+
+        send_fd(&self.control, SPAWN, child.as_raw_fd())?;
+        drop(child);
+
+        match read_i32(&mut self.control)? {
+            pid if pid > 0 => Ok((pid, parent)),
+            errno => Err(io::Error::from_raw_os_error(-errno)),
+        }
+    }
+
+    /// Collects a worker, killing it if it's still running. `None` if the
+    /// zygote doesn't know the pid.
+    pub(super) fn reap(
+        &mut self,
+        pid: libc::pid_t,
+    ) -> io::Result<Option<Exit>> {
+        let mut command = [REAP, 0, 0, 0, 0];
+        command[1..].copy_from_slice(&pid.to_le_bytes());
+        self.control
+            .write_all(&command)?;
+
+        let mut reply = [0u8; 2];
+        self.control
+            .read_exact(&mut reply)?;
+        let status = read_i32(&mut self.control)?;
+
+        Ok(match reply {
+            [0, _] => None,
+            [_, 0] => Some(Exit::Killed),
+            _ => Some(Exit::Died(status)),
+        })
+    }
+}
+
+impl Drop for Zygote {
+    fn drop(&mut self) {
+        // EOF on the control socket makes the zygote kill its workers and exit.
+        let _ = self
+            .control
+            .shutdown(std::net::Shutdown::Both);
+
+        // SAFETY: `pid` is our child and hasn't been waited on yet.
+        unsafe {
+            libc::waitpid(self.pid, ptr::null_mut(), 0);
+        }
+    }
+}
+
+/// Zygote loop: fork or reap workers on command. Returns on EOF.
+fn run(control: &UnixStream) {
+    let mut workers: Vec<libc::pid_t> = Vec::new();
+
+    while let Ok((command, fd)) = recv_command(control) {
+        let written = match command {
+            SPAWN => {
+                let pid = match fd {
+                    Some(fd) => fork_worker(control, fd, &mut workers),
+                    None => -libc::EBADF,
+                };
+
+                (&*control).write_all(&pid.to_le_bytes())
+            }
+            REAP => {
+                let mut raw = [0u8; 4];
+                if (&*control)
+                    .read_exact(&mut raw)
+                    .is_err()
+                {
+                    break;
+                }
+
+                let pid = libc::pid_t::from_le_bytes(raw);
+                let exit = workers
+                    .iter()
+                    .position(|&worker| worker == pid)
+                    .map(|index| {
+                        workers.swap_remove(index);
+                        collect(pid)
+                    });
+
+                let (died, status) = match exit {
+                    Some(Exit::Died(status)) => (true, status),
+                    _ => (false, 0),
+                };
+                let mut reply = [0u8; 6];
+                reply[0] = exit.is_some() as u8;
+                reply[1] = died as u8;
+                reply[2..].copy_from_slice(&status.to_le_bytes());
+                (&*control).write_all(&reply)
+            }
+            _ => break,
+        };
+
+        if written.is_err() {
+            break;
+        }
+    }
+
+    for pid in workers {
+        kill_and_wait(pid);
+    }
+}
+
+/// Forks a worker serving `fd`; returns its pid or `-errno`.
+fn fork_worker(
+    control: &UnixStream,
+    fd: OwnedFd,
+    workers: &mut Vec<libc::pid_t>,
+) -> libc::pid_t {
+    // SAFETY: The zygote is single-threaded.
+    match unsafe { libc::fork() } {
+        -1 => -io::Error::last_os_error()
+            .raw_os_error()
+            .unwrap_or(libc::EIO),
+        0 => {
+            // SAFETY: The zygote's control socket; the worker never uses it
+            // and never returns to the code that owns it.
+            unsafe { libc::close(control.as_raw_fd()) };
+
+            let stream = UnixStream::from(fd);
+            let code = match panic::catch_unwind(AssertUnwindSafe(|| {
+                super::serve(stream)
+            })) {
+                Ok(()) => 0,
+                Err(_) => 101,
+            };
+
+            // SAFETY: See `Zygote::fork`.
+            unsafe { libc::_exit(code) }
+        }
+        pid => {
+            workers.push(pid);
+            pid
+        }
+    }
+}
+
+/// Waits for a worker that may be exiting on its own, and kills it if it
+/// doesn't within `EXIT_GRACE`.
+fn collect(pid: libc::pid_t) -> Exit {
+    let deadline = Instant::now() + EXIT_GRACE;
+
+    loop {
+        let mut status = 0;
+
+        // SAFETY: `pid` is a child of this process that hasn't been waited
+        // on; `WNOHANG` returns 0 while it's still running.
+        match unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) } {
+            0 if Instant::now() < deadline => {
+                thread::sleep(Duration::from_millis(5))
+            }
+            0 => {
+                kill_and_wait(pid);
+                return Exit::Killed;
+            }
+            -1 if io::Error::last_os_error().kind()
+                == io::ErrorKind::Interrupted => {}
+            // Not our child to wait for (can't happen for tracked workers);
+            // nothing is known about how it ended.
+            -1 => return Exit::Killed,
+            _ => return Exit::Died(status),
+        }
+    }
+}
+
+fn kill_and_wait(pid: libc::pid_t) -> libc::c_int {
+    let mut status = 0;
+
+    // SAFETY: `pid` is a child of this process that hasn't been waited on.
+    // Killing an already-dead child keeps its original status.
+    unsafe {
+        libc::kill(pid, libc::SIGKILL);
+        while libc::waitpid(pid, &mut status, 0) == -1
+            && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted
+        {
+        }
+    }
+
+    status
+}
+
+fn read_i32(stream: &mut UnixStream) -> io::Result<i32> {
+    let mut raw = [0u8; 4];
+    stream.read_exact(&mut raw)?;
+
+    Ok(i32::from_le_bytes(raw))
+}
+
+// Room for one `cmsghdr` carrying a single fd, suitably aligned.
+type ControlBuf = [u64; 4];
+
+/// Sends `command` with `fd` attached as `SCM_RIGHTS`.
+fn send_fd(stream: &UnixStream, command: u8, fd: RawFd) -> io::Result<()> {
+    let mut data = [command];
+    let mut control: ControlBuf = [0; 4];
+
+    // SAFETY: `msg` points at live, correctly sized buffers for the call;
+    // the cmsg macros stay within `control`, which fits one fd.
+    unsafe {
+        let space = libc::CMSG_SPACE(mem::size_of::<RawFd>() as u32) as usize;
+        debug_assert!(space <= mem::size_of::<ControlBuf>());
+
+        let mut iov = libc::iovec {
+            iov_base: data.as_mut_ptr().cast(),
+            iov_len: data.len(),
+        };
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = space as _;
+
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<RawFd>() as u32) as _;
+        ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<RawFd>(), fd);
+
+        loop {
+            match libc::sendmsg(stream.as_raw_fd(), &msg, 0) {
+                1 => return Ok(()),
+                -1 if io::Error::last_os_error().kind()
+                    == io::ErrorKind::Interrupted => {}
+                -1 => return Err(io::Error::last_os_error()),
+                _ => return Err(io::ErrorKind::WriteZero.into()),
+            }
+        }
+    }
+}
+
+/// Receives a command byte and the fd attached to it, if any.
+fn recv_command(stream: &UnixStream) -> io::Result<(u8, Option<OwnedFd>)> {
+    let mut data = [0u8; 1];
+    let mut control: ControlBuf = [0; 4];
+
+    // SAFETY: As in `send_fd`; the kernel fills `control` and reports its
+    // used length, which the cmsg macros respect.
+    unsafe {
+        let mut iov = libc::iovec {
+            iov_base: data.as_mut_ptr().cast(),
+            iov_len: data.len(),
+        };
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = mem::size_of::<ControlBuf>() as _;
+
+        loop {
+            match libc::recvmsg(stream.as_raw_fd(), &mut msg, 0) {
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                -1 if io::Error::last_os_error().kind()
+                    == io::ErrorKind::Interrupted => {}
+                -1 => return Err(io::Error::last_os_error()),
+                _ => break,
+            }
+        }
+
+        let mut fd = None;
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET
+                && (*cmsg).cmsg_type == libc::SCM_RIGHTS
+            {
+                let raw =
+                    ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast::<RawFd>());
+                fd = Some(OwnedFd::from_raw_fd(raw));
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+
+        Ok((data[0], fd))
+    }
+}
+
+/// Threads in this process, where the OS reports it.
+#[cfg(target_os = "linux")]
+fn thread_count() -> Option<usize> {
+    std::fs::read_dir("/proc/self/task")
+        .ok()
+        .map(|tasks| tasks.count())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn thread_count() -> Option<usize> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Forks a child that runs `body`, which must be async-signal-safe.
+    fn fork_child(body: fn()) -> libc::pid_t {
+        // SAFETY: The child only makes async-signal-safe calls.
+        match unsafe { libc::fork() } {
+            0 => {
+                body();
+                unsafe { libc::_exit(0) }
+            }
+            pid => {
+                assert!(pid > 0, "fork failed");
+                pid
+            }
+        }
+    }
+
+    #[test]
+    fn test_collect_tells_dead_from_live_workers() {
+        let crashed = fork_child(|| unsafe {
+            libc::raise(libc::SIGABRT);
+        });
+        let Exit::Died(status) = collect(crashed) else {
+            panic!("a crashed worker should be reported as dead");
+        };
+        assert!(libc::WIFSIGNALED(status));
+        assert_eq!(libc::WTERMSIG(status), libc::SIGABRT);
+
+        let alive = fork_child(|| unsafe {
+            libc::pause();
+        });
+        assert_eq!(collect(alive), Exit::Killed);
+    }
+
+    #[test]
+    fn test_fd_passing_round_trip() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let (mut kept, sent) = UnixStream::pair().unwrap();
+
+        send_fd(&a, SPAWN, sent.as_raw_fd()).unwrap();
+        drop(sent);
+
+        let (command, fd) = recv_command(&b).unwrap();
+        assert_eq!(command, SPAWN);
+
+        let mut received = UnixStream::from(fd.expect("fd attached"));
+        received
+            .write_all(b"ping")
+            .unwrap();
+
+        let mut buf = [0u8; 4];
+        kept.read_exact(&mut buf)
+            .unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+}
@@ -1,58 +1,20 @@
 use std::cell::Cell;
 use std::ffi::CString;
-use std::sync::OnceLock;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::execution::{
-    ExecutionContext, ExecutionMessage, ExecutionResult, ResponseHeader,
+    BufferPolicy, ExecutionContext, ExecutionHooks, ExecutionMessage,
+    ExecutionMetrics, ExecutionResult, InformationalResponse, MailMessage,
+    MemoryUsage, OverflowAction, ResponseHead, ResponseHeader, SecurityPolicy,
+    SecurityViolation, StatusLine,
+};
+use crate::sapi::{
+    default_buffer_policy, default_security_policy, ServerVarsCString,
 };
-use crate::sapi::ServerVarsCString;
-
-const MIN_BUFFER_SIZE: usize = 4096;
-const DEFAULT_BUFFER_SIZE: usize = 65536;
-
-/// Output buffer configuration. Set via `SAPI_INIT_BUF` and `SAPI_BUF_GROWTH` env vars.
-#[derive(Clone, Copy)]
-struct BufferPolicy {
-    initial_cap: usize,
-    strategy: Growth,
-}
-
-/// Buffer growth strategy when output exceeds capacity.
-#[derive(Clone, Copy)]
-enum Growth {
-    X4,
-    X2,
-    Fixed(usize),
-}
-
-static BUFFER_POLICY: OnceLock<BufferPolicy> = OnceLock::new();
-
-fn buffer_policy() -> &'static BufferPolicy {
-    BUFFER_POLICY.get_or_init(|| {
-        let initial_cap = std::env::var("SAPI_INIT_BUF")
-            .ok()
-            .and_then(|v| v.parse::<usize>().ok())
-            .filter(|&n| n >= MIN_BUFFER_SIZE)
-            .unwrap_or(DEFAULT_BUFFER_SIZE);
-
-        let strategy = match std::env::var("SAPI_BUF_GROWTH")
-            .ok()
-            .as_deref()
-        {
-            Some("x2") | Some("X2") => Growth::X2,
-            Some("fixed32k") => Growth::Fixed(32 * 1024),
-            _ => Growth::X4,
-        };
-
-        BufferPolicy {
-            initial_cap,
-            strategy,
-        }
-    })
-}
 
 type FlushCallback = Box<dyn FnMut()>;
-type OutputCallback = Box<dyn FnMut(&[u8])>;
+pub(crate) type OutputCallback = Box<dyn FnMut(&[u8])>;
 
 /// Per-request state for the SAPI.
 ///
@@ -74,6 +36,36 @@ pub struct ServerContext {
     pub output_callback: Option<OutputCallback>,
     pub flush_callback: Option<FlushCallback>,
     pub log_to_stderr: bool,
+    pub buffer_policy: BufferPolicy,
+    /// Output bytes accepted so far, buffered or streamed.
+    pub output_len: usize,
+    /// Output beyond `buffer_policy.max_size()` was discarded.
+    pub truncated: bool,
+    /// Output hit the limit under [`OverflowAction::Abort`].
+    pub output_limit_exceeded: bool,
+    /// PHP was told the connection aborted (once per request).
+    pub connection_aborted: bool,
+    /// Hooks of the running request, for callbacks that fire mid-request.
+    /// Set by the executor only while the hooks are alive.
+    pub response_hooks: Option<*mut dyn ExecutionHooks>,
+    /// Status line from `header("HTTP/...")`, kept while it matches the status.
+    pub status_line: Option<StatusLine>,
+    /// 1xx header blocks sent so far.
+    pub informational: Vec<InformationalResponse>,
+    pub security_policy: Option<Arc<SecurityPolicy>>,
+    pub security_violations: Vec<SecurityViolation>,
+    pub memory_limit: Option<usize>,
+    /// Limit from the script's "Allowed memory size" fatal error, if it hit
+    /// one.
+    pub memory_limit_exceeded: Option<usize>,
+    pub memory_usage: MemoryUsage,
+    /// Pinned request time in seconds since the epoch.
+    pub request_time: Option<f64>,
+    pub random_seed: Option<u32>,
+    /// Messages delivered to the mail sink during the request.
+    pub mail: Vec<MailMessage>,
+    /// Phase durations, filled in by the executor and `send_headers`.
+    pub metrics: ExecutionMetrics,
 }
 
 impl Default for ServerContext {
@@ -84,12 +76,14 @@ impl Default for ServerContext {
 
 impl ServerContext {
     pub fn new() -> Self {
-        let policy = buffer_policy();
+        Self::with_buffer_policy(BufferPolicy::env_default())
+    }
 
+    pub fn with_buffer_policy(buffer_policy: BufferPolicy) -> Self {
         Self {
             post_data: Vec::new(),
             post_position: Cell::new(0),
-            output_buffer: Vec::with_capacity(policy.initial_cap),
+            output_buffer: Vec::with_capacity(buffer_policy.initial_capacity()),
             status_code: Cell::new(200),
             messages: Vec::with_capacity(8),
             vars: None,
@@ -99,6 +93,101 @@ impl ServerContext {
             output_callback: None,
             flush_callback: None,
             log_to_stderr: false,
+            buffer_policy,
+            output_len: 0,
+            truncated: false,
+            output_limit_exceeded: false,
+            connection_aborted: false,
+            response_hooks: None,
+            status_line: None,
+            informational: Vec::new(),
+            security_policy: None,
+            security_violations: Vec::new(),
+            memory_limit: None,
+            memory_limit_exceeded: None,
+            memory_usage: MemoryUsage::default(),
+            request_time: None,
+            random_seed: None,
+            mail: Vec::new(),
+            metrics: ExecutionMetrics::default(),
+        }
+    }
+
+    /// Clears per-request state, keeping allocated capacity.
+    pub(crate) fn reset(&mut self) {
+        self.status_code.set(200);
+        self.post_data = Vec::new();
+        self.post_position.set(0);
+        self.output_buffer.clear();
+        self.messages.clear();
+        self.env_vars.clear();
+        self.ini_overrides.clear();
+        self.response_headers.clear();
+        self.output_callback = None;
+        self.flush_callback = None;
+        self.log_to_stderr = false;
+        self.output_len = 0;
+        self.truncated = false;
+        self.output_limit_exceeded = false;
+        self.connection_aborted = false;
+        self.response_hooks = None;
+        self.status_line = None;
+        self.informational.clear();
+        self.security_policy = None;
+        self.security_violations
+            .clear();
+        self.memory_limit = None;
+        self.memory_limit_exceeded = None;
+        self.memory_usage = MemoryUsage::default();
+        self.request_time = None;
+        self.random_seed = None;
+        self.mail.clear();
+        self.metrics = ExecutionMetrics::default();
+    }
+
+    /// Records a 1xx header block and hands it to the request's hooks.
+    ///
+    /// # Safety
+    /// Same as [`ServerContext::apply_response_head_hooks`].
+    pub(crate) unsafe fn add_informational(
+        &mut self,
+        response: InformationalResponse,
+    ) {
+        if let Some(hooks) = self.response_hooks {
+            (*hooks).on_informational(&response);
+        }
+
+        self.informational
+            .push(response);
+    }
+
+    /// Runs the request's `on_response_head` hooks over the collected head.
+    ///
+    /// # Safety
+    /// `response_hooks`, if set, must point to live hooks not borrowed elsewhere.
+    pub(crate) unsafe fn apply_response_head_hooks(&mut self) {
+        let Some(hooks) = self.response_hooks else {
+            return;
+        };
+
+        let mut head = ResponseHead::new(
+            self.status_code(),
+            std::mem::take(&mut self.response_headers),
+        );
+
+        (*hooks).on_response_head(&mut head);
+
+        let (status, headers) = head.into_parts();
+        self.set_status(status);
+        self.response_headers = headers;
+
+        // A hook-changed status makes the script's reason phrase stale.
+        if self
+            .status_line
+            .as_ref()
+            .is_some_and(|line| line.code() != status)
+        {
+            self.status_line = None;
         }
     }
 
@@ -163,41 +252,56 @@ impl ServerContext {
     }
 
     pub fn write_output(&mut self, data: &[u8]) -> usize {
+        let len = data.len();
+        let data = &data[..self.accept_output(len)];
+
         if let Some(ref mut callback) = self.output_callback {
-            callback(data);
+            if !data.is_empty() {
+                callback(data);
+            }
+        } else {
+            let capacity = self.output_buffer.capacity();
+            let required = self.output_buffer.len() + data.len();
+
+            if required > capacity {
+                let new_cap = self
+                    .buffer_policy
+                    .grow(capacity, required);
+
+                self.output_buffer
+                    .reserve(new_cap - self.output_buffer.len());
+            }
 
-            return data.len();
+            self.output_buffer
+                .extend_from_slice(data);
         }
 
-        let actual_buffer_length = self.output_buffer.capacity();
-        let required_buffer_length = self.output_buffer.len() + data.len();
-
-        if required_buffer_length > actual_buffer_length {
-            let policy = buffer_policy();
-
-            let new_cap = match policy.strategy {
-                Growth::X4 => actual_buffer_length
-                    .saturating_mul(4)
-                    .max(required_buffer_length + policy.initial_cap),
-                Growth::X2 => actual_buffer_length
-                    .saturating_mul(2)
-                    .max(required_buffer_length + policy.initial_cap),
-                Growth::Fixed(step) => {
-                    let mut cap = actual_buffer_length;
-                    while cap < required_buffer_length {
-                        cap = cap.saturating_add(step);
-                    }
-                    cap
-                }
-            };
+        // Report everything as written; discarded output isn't an I/O error.
+        len
+    }
 
-            self.output_buffer
-                .reserve(new_cap - self.output_buffer.len());
+    /// Applies the size cap, returning how many of `len` bytes to keep.
+    fn accept_output(&mut self, len: usize) -> usize {
+        let Some(max) = self.buffer_policy.max_size() else {
+            self.output_len += len;
+            return len;
+        };
+
+        let room = max.saturating_sub(self.output_len);
+
+        if len > room {
+            match self
+                .buffer_policy
+                .on_overflow()
+            {
+                OverflowAction::Truncate => self.truncated = true,
+                OverflowAction::Abort => self.output_limit_exceeded = true,
+            }
         }
 
-        self.output_buffer
-            .extend_from_slice(data);
-        data.len()
+        let accepted = len.min(room);
+        self.output_len += accepted;
+        accepted
     }
 
     pub fn add_header(&mut self, header: ResponseHeader) {
@@ -210,6 +314,13 @@ impl ServerContext {
     }
 
     pub fn add_message(&mut self, message: ExecutionMessage) {
+        if let Some(violation) =
+            SecurityViolation::from_php_message(&message.message)
+        {
+            self.security_violations
+                .push(violation);
+        }
+
         self.messages.push(message);
     }
 
@@ -244,37 +355,106 @@ impl ServerContext {
             self.response_headers,
             self.messages,
         )
+        .with_truncated(self.truncated)
+        .with_sent_status_line(self.status_line)
+        .with_informational(self.informational)
+        .with_security_violations(self.security_violations)
+        .with_memory_usage(self.memory_usage)
+        .with_metrics(self.metrics)
+        .with_mail(self.mail)
+    }
+
+    /// Takes the request's input, variables and policies from `ctx`; the
+    /// buffer policy is left to the caller. `make_cstring` builds each string
+    /// (entries it rejects are dropped), which lets the pool recycle storage.
+    pub(crate) fn fill_from(
+        &mut self,
+        ctx: ExecutionContext,
+        mut make_cstring: impl FnMut(String) -> Option<CString>,
+    ) {
+        self.post_data = ctx.input;
+        self.log_to_stderr = ctx.log_to_stderr;
+        self.security_policy = ctx
+            .security_policy
+            .map(Arc::new)
+            .or_else(default_security_policy);
+        self.memory_limit = ctx.memory_limit;
+        self.request_time = ctx
+            .request_time
+            .map(epoch_seconds);
+        self.random_seed = ctx.random_seed;
+
+        let mut vars = self
+            .vars
+            .take()
+            .unwrap_or_default();
+        ctx.server_vars
+            .into_cstring_pairs_with(&mut vars, &mut make_cstring);
+        self.vars = Some(vars);
+
+        for (key, value) in ctx.env_vars {
+            if let (Some(k), Some(v)) = (make_cstring(key), make_cstring(value))
+            {
+                self.env_vars.push((k, v));
+            }
+        }
+
+        for (key, value) in ctx.ini_overrides {
+            if let (Some(k), Some(v)) = (make_cstring(key), make_cstring(value))
+            {
+                self.ini_overrides
+                    .push((k, v));
+            }
+        }
     }
 }
 
 impl From<ExecutionContext> for Box<ServerContext> {
     fn from(ctx: ExecutionContext) -> Self {
-        let mut server_ctx = Box::new(ServerContext::new());
+        let buffer_policy = ctx.resolve_buffer_policy(default_buffer_policy);
+        let mut server_ctx =
+            Box::new(ServerContext::with_buffer_policy(buffer_policy));
 
-        server_ctx.post_data = ctx.input;
-        server_ctx.log_to_stderr = ctx.log_to_stderr;
+        server_ctx.fill_from(ctx, |s| CString::new(s).ok());
+        server_ctx
+    }
+}
 
-        server_ctx.vars = Some(
-            ctx.server_vars
-                .into_cstring_pairs(),
-        );
+/// Seconds since the Unix epoch, as PHP's request time.
+pub(crate) fn epoch_seconds(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0)
+}
 
-        server_ctx.env_vars = ctx
-            .env_vars
-            .into_iter()
-            .filter_map(|(k, v)| {
-                Some((CString::new(k).ok()?, CString::new(v).ok()?))
-            })
-            .collect();
-
-        server_ctx.ini_overrides = ctx
-            .ini_overrides
-            .into_iter()
-            .filter_map(|(k, v)| {
-                Some((CString::new(k).ok()?, CString::new(v).ok()?))
-            })
-            .collect();
+/// Limit from PHP's "Allowed memory size of N bytes exhausted" error message.
+pub(crate) fn memory_limit_from_message(message: &str) -> Option<usize> {
+    let rest = message.strip_prefix("Allowed memory size of ")?;
+    let (limit, _) = rest.split_once(" bytes exhausted")?;
 
-        server_ctx
+    limit.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_limit_from_message() {
+        assert_eq!(
+            memory_limit_from_message(
+                "Allowed memory size of 2097152 bytes exhausted \
+                 (tried to allocate 4096 bytes)"
+            ),
+            Some(2_097_152)
+        );
+        assert_eq!(
+            memory_limit_from_message(
+                "Uncaught Exception: Allowed memory size of 2097152 bytes \
+                 exhausted in /a.php:3"
+            ),
+            None
+        );
+        assert_eq!(memory_limit_from_message("nope"), None);
     }
 }
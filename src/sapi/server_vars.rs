@@ -89,12 +89,27 @@ impl ServerVars {
     }
 
     pub fn request_time(&mut self) -> &mut Self {
-        let now = SystemTime::now()
+        self.request_time_at(SystemTime::now())
+    }
+
+    /// Sets `REQUEST_TIME` / `REQUEST_TIME_FLOAT` to `time`.
+    pub fn request_time_at(&mut self, time: SystemTime) -> &mut Self {
+        let since_epoch = time
             .duration_since(UNIX_EPOCH)
             .unwrap_or_default();
 
-        self.set("REQUEST_TIME", now.as_secs().to_string())
-            .set("REQUEST_TIME_FLOAT", now.as_secs_f64().to_string())
+        self.set(
+            "REQUEST_TIME",
+            since_epoch
+                .as_secs()
+                .to_string(),
+        )
+        .set(
+            "REQUEST_TIME_FLOAT",
+            since_epoch
+                .as_secs_f64()
+                .to_string(),
+        )
     }
 
     pub fn script_filename(&mut self, path: &Path) -> &mut Self {
@@ -243,6 +258,15 @@ impl ServerVars {
         vars
     }
 
+    /// Value of `key`; the last one set wins, as it does in `$_SERVER`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.vars
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
     pub fn get_content_type(&self) -> Option<&str> {
         self.content_type.as_deref()
     }
@@ -260,40 +284,41 @@ impl ServerVars {
     }
 
     pub fn into_cstring_pairs(self) -> ServerVarsCString {
-        let vars: Vec<(CString, CString)> = self
-            .vars
-            .into_iter()
-            .filter_map(|(k, v)| {
-                Some((CString::new(k).ok()?, CString::new(v).ok()?))
-            })
-            .collect();
+        let mut out = ServerVarsCString::default();
+        self.into_cstring_pairs_with(&mut out, |s| CString::new(s).ok());
+        out
+    }
+
+    /// Converts into `out`, reusing its capacity. `cstring` builds each
+    /// value, which lets callers recycle CString storage.
+    pub(crate) fn into_cstring_pairs_with(
+        self,
+        out: &mut ServerVarsCString,
+        mut cstring: impl FnMut(String) -> Option<CString>,
+    ) {
+        out.vars.clear();
+        out.vars.extend(
+            self.vars
+                .into_iter()
+                .filter_map(|(k, v)| Some((cstring(k)?, cstring(v)?))),
+        );
 
-        let content_type = self
+        out.content_type = self
             .content_type
-            .and_then(|s| CString::new(s).ok());
-
-        let query_string = self
+            .and_then(&mut cstring);
+        out.query_string = self
             .query_string
-            .and_then(|s| CString::new(s).ok());
-
-        let cookie = self
+            .and_then(&mut cstring);
+        out.cookie = self
             .cookie
-            .and_then(|s| CString::new(s).ok());
-
-        let request_method = self
+            .and_then(&mut cstring);
+        out.request_method = self
             .request_method
-            .and_then(|s| CString::new(s).ok());
-
-        ServerVarsCString {
-            vars,
-            content_type,
-            query_string,
-            cookie,
-            request_method,
-        }
+            .and_then(&mut cstring);
     }
 }
 
+#[derive(Default)]
 pub struct ServerVarsCString {
     pub vars: Vec<(CString, CString)>,
     pub content_type: Option<CString>,
@@ -393,6 +418,17 @@ mod tests {
         assert_eq!(map.get("GATEWAY_INTERFACE"), Some(&"CGI/1.1".to_string()));
     }
 
+    #[test]
+    fn test_request_time_at() {
+        let mut vars = ServerVars::web_defaults();
+        vars.request_time_at(
+            UNIX_EPOCH + std::time::Duration::from_millis(1_500),
+        );
+
+        assert_eq!(vars.get("REQUEST_TIME"), Some("1"));
+        assert_eq!(vars.get("REQUEST_TIME_FLOAT"), Some("1.5"));
+    }
+
     #[test]
     fn test_cli_defaults() {
         let vars = ServerVars::cli_defaults();
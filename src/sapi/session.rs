@@ -0,0 +1,383 @@
+//! PHP session storage backed by Rust.
+//!
+//! A [`SessionStore`] set with [`EngineBuilder::with_session_store()`] is
+//! registered with PHP's session extension as the `ripht` save handler and
+//! selected through `session.save_handler`, so `session_start()` reads and
+//! writes through the store instead of PHP's file handler.
+//!
+//! [`EngineBuilder::with_session_store()`]: super::EngineBuilder::with_session_store
+
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int, c_void, CStr};
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "tracing")]
+use tracing::warn;
+
+use super::ffi;
+
+/// Name of the save handler, as used in `session.save_handler`.
+pub(crate) const SAVE_HANDLER: &str = "ripht";
+
+/// Storage backend for PHP sessions.
+///
+/// Called from whichever thread runs the request, so implementations must be
+/// `Send + Sync`. Errors make the corresponding PHP session call fail with a
+/// warning.
+pub trait SessionStore: Send + Sync {
+    /// Called by `session_start()` before the session is read.
+    fn open(&self, _save_path: &str, _session_name: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Session data for `id`, or `None` for a new session.
+    fn read(&self, id: &str) -> io::Result<Option<Vec<u8>>>;
+
+    /// Stores session data. `max_lifetime` is `session.gc_maxlifetime`.
+    fn write(
+        &self,
+        id: &str,
+        data: &[u8],
+        max_lifetime: Duration,
+    ) -> io::Result<()>;
+
+    /// Removes a session, e.g. on `session_destroy()`.
+    fn destroy(&self, id: &str) -> io::Result<()>;
+
+    /// Removes sessions idle for longer than `max_lifetime`, returning how many.
+    fn gc(&self, max_lifetime: Duration) -> io::Result<usize>;
+}
+
+#[derive(Debug)]
+struct Entry {
+    data: Vec<u8>,
+    touched: Instant,
+}
+
+/// [`SessionStore`] keeping sessions in memory.
+///
+/// Clones share the same sessions, so one clone can go to the engine while
+/// another inspects what PHP stored.
+#[derive(Debug, Clone, Default)]
+pub struct InMemorySessionStore {
+    sessions: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+impl InMemorySessionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Raw (serialized) data of session `id`.
+    pub fn get(&self, id: &str) -> Option<Vec<u8>> {
+        self.lock()
+            .get(id)
+            .map(|entry| entry.data.clone())
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Entry>> {
+        self.sessions
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl SessionStore for InMemorySessionStore {
+    fn read(&self, id: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.get(id))
+    }
+
+    fn write(
+        &self,
+        id: &str,
+        data: &[u8],
+        _max_lifetime: Duration,
+    ) -> io::Result<()> {
+        self.lock().insert(
+            id.to_string(),
+            Entry {
+                data: data.to_vec(),
+                touched: Instant::now(),
+            },
+        );
+
+        Ok(())
+    }
+
+    fn destroy(&self, id: &str) -> io::Result<()> {
+        self.lock().remove(id);
+        Ok(())
+    }
+
+    fn gc(&self, max_lifetime: Duration) -> io::Result<usize> {
+        let mut sessions = self.lock();
+        let before = sessions.len();
+
+        sessions.retain(|_, entry| entry.touched.elapsed() <= max_lifetime);
+
+        Ok(before - sessions.len())
+    }
+}
+
+/// Shared store handle kept in the engine configuration.
+#[derive(Clone)]
+pub(crate) struct SessionStoreHandle(pub(crate) Arc<dyn SessionStore>);
+
+impl fmt::Debug for SessionStoreHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SessionStoreHandle(..)")
+    }
+}
+
+static STORE: RwLock<Option<Arc<dyn SessionStore>>> = RwLock::new(None);
+static REGISTERED: AtomicBool = AtomicBool::new(false);
+
+static MODULE: ffi::ps_module = ffi::ps_module {
+    s_name: c"ripht".as_ptr(),
+    s_open: Some(ripht_session_open),
+    s_close: Some(ripht_session_close),
+    s_read: Some(ripht_session_read),
+    s_write: Some(ripht_session_write),
+    s_destroy: Some(ripht_session_destroy),
+    s_gc: Some(ripht_session_gc),
+    s_create_sid: Some(ffi::php_session_create_id),
+    s_validate_sid: Some(ripht_session_validate_sid),
+    s_update_timestamp: Some(ripht_session_write),
+};
+
+/// Makes `store` the backend of the `ripht` save handler.
+///
+/// Must run after module startup: the session extension's handler table only
+/// exists once it has been initialized. PHP has no way to unregister a
+/// handler, so registration happens once per process.
+pub(crate) unsafe fn install(store: Option<&SessionStoreHandle>) {
+    *STORE
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) =
+        store.map(|handle| Arc::clone(&handle.0));
+
+    if store.is_some() && !REGISTERED.swap(true, Ordering::AcqRel) {
+        ffi::php_session_register_module(&MODULE);
+    }
+}
+
+fn store() -> Option<Arc<dyn SessionStore>> {
+    STORE
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+}
+
+/// Runs `f` against the installed store, mapping the outcome to a PHP result.
+unsafe fn with_store(
+    op: &str,
+    f: impl FnOnce(&dyn SessionStore) -> io::Result<()>,
+) -> c_int {
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let store = store().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "no session store installed",
+            )
+        })?;
+
+        f(store.as_ref())
+    }));
+
+    match result {
+        Ok(Ok(())) => ffi::SUCCESS,
+        Ok(Err(_err)) => {
+            #[cfg(feature = "tracing")]
+            warn!(op, error = %_err, "Session store failed");
+            #[cfg(not(feature = "tracing"))]
+            let _ = op;
+
+            ffi::FAILURE
+        }
+        Err(_) => ffi::FAILURE,
+    }
+}
+
+unsafe fn session_id<'a>(key: *const ffi::zend_string) -> io::Result<&'a str> {
+    std::str::from_utf8(ffi::zstr_bytes(key)).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidData, "session id is not UTF-8")
+    })
+}
+
+fn max_lifetime(seconds: ffi::zend_long) -> Duration {
+    Duration::from_secs(seconds.max(0) as u64)
+}
+
+unsafe extern "C" fn ripht_session_open(
+    mod_data: *mut *mut c_void,
+    save_path: *const c_char,
+    session_name: *const c_char,
+) -> c_int {
+    let text = |ptr: *const c_char| {
+        if ptr.is_null() {
+            String::new()
+        } else {
+            CStr::from_ptr(ptr)
+                .to_string_lossy()
+                .into_owned()
+        }
+    };
+    let (save_path, session_name) = (text(save_path), text(session_name));
+
+    let result =
+        with_store("open", |store| store.open(&save_path, &session_name));
+
+    // PHP treats a null `mod_data` after open as a failed handler.
+    if result == ffi::SUCCESS {
+        *mod_data = std::ptr::addr_of!(MODULE) as *mut c_void;
+    }
+
+    result
+}
+
+unsafe extern "C" fn ripht_session_close(mod_data: *mut *mut c_void) -> c_int {
+    *mod_data = std::ptr::null_mut();
+    ffi::SUCCESS
+}
+
+unsafe extern "C" fn ripht_session_read(
+    _mod_data: *mut *mut c_void,
+    key: *mut ffi::zend_string,
+    val: *mut *mut ffi::zend_string,
+    _max_lifetime: ffi::zend_long,
+) -> c_int {
+    let mut data = Vec::new();
+
+    let result = with_store("read", |store| {
+        if let Some(stored) = store.read(session_id(key)?)? {
+            data = stored;
+        }
+        Ok(())
+    });
+
+    if result == ffi::SUCCESS {
+        *val = ffi::zend_string_concat2(
+            data.as_ptr() as *const c_char,
+            data.len(),
+            c"".as_ptr(),
+            0,
+        );
+    }
+
+    result
+}
+
+unsafe extern "C" fn ripht_session_write(
+    _mod_data: *mut *mut c_void,
+    key: *mut ffi::zend_string,
+    val: *mut ffi::zend_string,
+    max_lifetime_secs: ffi::zend_long,
+) -> c_int {
+    with_store("write", |store| {
+        store.write(
+            session_id(key)?,
+            ffi::zstr_bytes(val),
+            max_lifetime(max_lifetime_secs),
+        )
+    })
+}
+
+unsafe extern "C" fn ripht_session_destroy(
+    _mod_data: *mut *mut c_void,
+    key: *mut ffi::zend_string,
+) -> c_int {
+    with_store("destroy", |store| store.destroy(session_id(key)?))
+}
+
+unsafe extern "C" fn ripht_session_gc(
+    _mod_data: *mut *mut c_void,
+    max_lifetime_secs: ffi::zend_long,
+    nrdels: *mut ffi::zend_long,
+) -> ffi::zend_long {
+    let mut removed = 0;
+
+    let result = with_store("gc", |store| {
+        removed = store.gc(max_lifetime(max_lifetime_secs))?;
+        Ok(())
+    });
+
+    if result != ffi::SUCCESS {
+        return -1;
+    }
+
+    if !nrdels.is_null() {
+        *nrdels = removed as ffi::zend_long;
+    }
+
+    removed as ffi::zend_long
+}
+
+/// Used with `session.use_strict_mode`: only ids the store knows are accepted.
+unsafe extern "C" fn ripht_session_validate_sid(
+    _mod_data: *mut *mut c_void,
+    key: *mut ffi::zend_string,
+) -> c_int {
+    with_store("validate_sid", |store| {
+        match store.read(session_id(key)?)? {
+            Some(_) => Ok(()),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_in_memory_store_round_trip() {
+        let store = InMemorySessionStore::new();
+        let shared = store.clone();
+
+        assert_eq!(store.read("abc").unwrap(), None);
+
+        shared
+            .write("abc", b"count|i:1;", Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(store.get("abc").as_deref(), Some(&b"count|i:1;"[..]));
+
+        store.destroy("abc").unwrap();
+        assert!(shared.is_empty());
+    }
+
+    #[test]
+    fn test_in_memory_gc_removes_idle_sessions() {
+        let store = InMemorySessionStore::new();
+
+        store
+            .write("old", b"", Duration::ZERO)
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+
+        assert_eq!(
+            store
+                .gc(Duration::from_secs(3600))
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            store
+                .gc(Duration::ZERO)
+                .unwrap(),
+            1
+        );
+        assert!(store.is_empty());
+    }
+}
@@ -0,0 +1,631 @@
+//! PHP stream wrappers implemented in Rust.
+//!
+//! A [`StreamWrapper`] registered with
+//! [`EngineBuilder::with_stream_wrapper()`] serves a URL scheme such as
+//! `app://` inside every request: `fopen()`, `file_get_contents()`,
+//! `file_put_contents()`, `include`/`require`, `file_exists()`, `unlink()`
+//! and `opendir()`/`scandir()` all reach the wrapper.
+//!
+//! [`EngineBuilder::with_stream_wrapper()`]: super::EngineBuilder::with_stream_wrapper
+
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::fmt;
+use std::io::{self, Cursor, SeekFrom};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+#[cfg(feature = "tracing")]
+use tracing::warn;
+
+use super::ffi;
+
+/// Serves one URL scheme to PHP.
+///
+/// Methods receive the full URL, scheme included, and run on the thread
+/// executing the request. Errors surface in PHP as the usual warnings and
+/// `false` returns. Only [`open()`](StreamWrapper::open) is required.
+pub trait StreamWrapper: Send + Sync {
+    /// Opens `url` with an `fopen()` mode such as `"rb"` or `"w"`.
+    fn open(&self, url: &str, mode: &str)
+        -> io::Result<Box<dyn WrapperStream>>;
+
+    /// Backs `file_exists()`, `is_file()`, `is_dir()`, `filesize()`, ...
+    fn stat(&self, _url: &str) -> io::Result<StreamStat> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn unlink(&self, _url: &str) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Entry names of the directory `url`, for `opendir()` and `scandir()`.
+    fn read_dir(&self, _url: &str) -> io::Result<Vec<String>> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// A stream opened by a [`StreamWrapper`].
+///
+/// Dropped when PHP closes the stream, so writers can commit there.
+pub trait WrapperStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Backs `fstat()`; also gives `file_get_contents()` a size hint.
+    fn stat(&self) -> io::Result<StreamStat> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// In-memory stream, e.g. for contents a wrapper has already loaded.
+impl WrapperStream for Cursor<Vec<u8>> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        io::Read::read(self, buf)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::Write::write(self, buf)
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        io::Seek::seek(self, pos)
+    }
+
+    fn stat(&self) -> io::Result<StreamStat> {
+        Ok(StreamStat::file(self.get_ref().len() as u64))
+    }
+}
+
+/// What a [`StreamWrapper`] reports for `stat()`-style calls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct StreamStat {
+    pub size: u64,
+    pub is_dir: bool,
+    pub modified: Option<SystemTime>,
+}
+
+impl StreamStat {
+    /// A regular file of `size` bytes.
+    pub fn file(size: u64) -> Self {
+        Self {
+            size,
+            is_dir: false,
+            modified: None,
+        }
+    }
+
+    pub fn dir() -> Self {
+        Self {
+            size: 0,
+            is_dir: true,
+            modified: None,
+        }
+    }
+
+    #[must_use]
+    pub fn with_modified(mut self, modified: SystemTime) -> Self {
+        self.modified = Some(modified);
+        self
+    }
+}
+
+/// Shared wrapper handle kept in the engine configuration.
+#[derive(Clone)]
+pub(crate) struct StreamWrapperHandle(pub(crate) Arc<dyn StreamWrapper>);
+
+impl fmt::Debug for StreamWrapperHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StreamWrapperHandle(..)")
+    }
+}
+
+/// A scheme and the PHP wrapper struct pointing at its handler.
+struct Registered {
+    scheme: String,
+    wrapper: Box<ffi::php_stream_wrapper>,
+    // Target of `wrapper.abstract_`.
+    _handler: Box<Arc<dyn StreamWrapper>>,
+}
+
+static REGISTERED: RwLock<Vec<Registered>> = RwLock::new(Vec::new());
+
+// Checked at every request startup, so kept apart from the lock.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+static WRAPPER_OPS: ffi::php_stream_wrapper_ops = ffi::php_stream_wrapper_ops {
+    stream_opener: Some(ripht_wrapper_open),
+    stream_closer: None,
+    stream_stat: None,
+    url_stat: Some(ripht_wrapper_url_stat),
+    dir_opener: Some(ripht_wrapper_dir_open),
+    label: c"ripht".as_ptr(),
+    unlink: Some(ripht_wrapper_unlink),
+    rename: None,
+    stream_mkdir: None,
+    stream_rmdir: None,
+    stream_metadata: None,
+};
+
+static STREAM_OPS: ffi::php_stream_ops = ffi::php_stream_ops {
+    write: Some(ripht_stream_write),
+    read: Some(ripht_stream_read),
+    close: Some(ripht_stream_close),
+    flush: Some(ripht_stream_flush),
+    label: c"ripht".as_ptr(),
+    seek: Some(ripht_stream_seek),
+    cast: None,
+    stat: Some(ripht_stream_stat),
+    set_option: Some(ripht_stream_set_option),
+};
+
+static DIR_OPS: ffi::php_stream_ops = ffi::php_stream_ops {
+    write: None,
+    read: Some(ripht_dir_read),
+    close: Some(ripht_dir_close),
+    flush: None,
+    label: c"ripht dir".as_ptr(),
+    seek: None,
+    cast: None,
+    stat: None,
+    set_option: None,
+};
+
+/// Sets the wrappers requests get; replaces any installed before.
+pub(crate) fn install(wrappers: &[(String, StreamWrapperHandle)]) {
+    let registered = wrappers
+        .iter()
+        .map(|(scheme, handle)| {
+            let handler = Box::new(Arc::clone(&handle.0));
+            let wrapper = Box::new(ffi::php_stream_wrapper {
+                wops: &WRAPPER_OPS,
+                abstract_: &*handler as *const Arc<dyn StreamWrapper>
+                    as *mut c_void,
+                is_url: 0,
+            });
+
+            Registered {
+                scheme: scheme.clone(),
+                wrapper,
+                _handler: handler,
+            }
+        })
+        .collect();
+
+    *REGISTERED
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = registered;
+
+    ACTIVE.store(!wrappers.is_empty(), Ordering::Release);
+}
+
+/// Registers the installed wrappers for this request.
+///
+/// Call after `php_request_startup`; PHP drops the change at shutdown.
+pub(crate) unsafe fn activate() {
+    if !ACTIVE.load(Ordering::Acquire) {
+        return;
+    }
+
+    let registered = REGISTERED
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    for entry in registered.iter() {
+        register(&entry.scheme, &*entry.wrapper);
+    }
+}
+
+/// Makes `wrapper` serve `scheme` for the current request, replacing any
+/// wrapper PHP already has for it.
+pub(crate) unsafe fn register(
+    scheme: &str,
+    wrapper: *const ffi::php_stream_wrapper,
+) {
+    let Some(init) = ffi::zend_string_init_interned else {
+        return;
+    };
+
+    let protocol = init(scheme.as_ptr() as *const _, scheme.len(), false);
+    if protocol.is_null() {
+        return;
+    }
+
+    ffi::php_unregister_url_stream_wrapper_volatile(protocol);
+    let _result = ffi::php_register_url_stream_wrapper_volatile(
+        protocol,
+        wrapper as *mut _,
+    );
+
+    #[cfg(feature = "tracing")]
+    if _result == ffi::FAILURE {
+        warn!(scheme, "Stream wrapper not registered");
+    }
+}
+
+/// Runs a Rust callback, turning a panic into an error.
+fn guard<T>(f: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+    panic::catch_unwind(AssertUnwindSafe(f))
+        .unwrap_or_else(|_| Err(io::Error::other("stream wrapper panicked")))
+}
+
+unsafe fn handler<'a>(
+    wrapper: *const ffi::php_stream_wrapper,
+) -> &'a Arc<dyn StreamWrapper> {
+    &*((*wrapper).abstract_ as *const Arc<dyn StreamWrapper>)
+}
+
+unsafe fn c_str<'a>(ptr: *const c_char) -> std::borrow::Cow<'a, str> {
+    CStr::from_ptr(ptr).to_string_lossy()
+}
+
+unsafe fn log_error(
+    wrapper: *mut ffi::php_stream_wrapper,
+    options: c_int,
+    err: &io::Error,
+) {
+    let message = CString::new(err.to_string()).unwrap_or_default();
+    ffi::php_stream_wrapper_log_error(
+        wrapper,
+        options,
+        c"%s".as_ptr(),
+        message.as_ptr(),
+    );
+}
+
+/// An open file stream; `php_stream.abstract`.
+struct OpenStream {
+    inner: Box<dyn WrapperStream>,
+    // Reported to `feof()` through the liveness check.
+    eof: bool,
+}
+
+unsafe fn open_stream<'a>(stream: *mut ffi::php_stream) -> &'a mut OpenStream {
+    &mut *((*stream).abstract_ as *mut OpenStream)
+}
+
+unsafe extern "C" fn ripht_wrapper_open(
+    wrapper: *mut ffi::php_stream_wrapper,
+    filename: *const c_char,
+    mode: *const c_char,
+    options: c_int,
+    _opened_path: *mut *mut ffi::zend_string,
+    _context: *mut ffi::php_stream_context,
+) -> *mut ffi::php_stream {
+    let handler = handler(wrapper);
+    let url = c_str(filename);
+
+    let inner = match guard(|| handler.open(&url, &c_str(mode))) {
+        Ok(inner) => inner,
+        Err(err) => {
+            log_error(wrapper, options, &err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    let state = Box::into_raw(Box::new(OpenStream { inner, eof: false }));
+    let stream = ffi::_php_stream_alloc(
+        &STREAM_OPS,
+        state as *mut c_void,
+        std::ptr::null(),
+        mode,
+    );
+    if stream.is_null() {
+        drop(Box::from_raw(state));
+    }
+
+    stream
+}
+
+unsafe extern "C" fn ripht_stream_read(
+    stream: *mut ffi::php_stream,
+    buf: *mut c_char,
+    count: usize,
+) -> isize {
+    let state = open_stream(stream);
+    let buf = std::slice::from_raw_parts_mut(buf as *mut u8, count);
+
+    // Fill the buffer so a short read means end of stream.
+    let mut filled = 0;
+    while filled < count {
+        match guard(|| {
+            state
+                .inner
+                .read(&mut buf[filled..])
+        }) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) if filled > 0 => break,
+            Err(_) => return -1,
+        }
+    }
+
+    state.eof = filled < count;
+    filled as isize
+}
+
+unsafe extern "C" fn ripht_stream_write(
+    stream: *mut ffi::php_stream,
+    buf: *const c_char,
+    count: usize,
+) -> isize {
+    let state = open_stream(stream);
+    let buf = std::slice::from_raw_parts(buf as *const u8, count);
+
+    match guard(|| state.inner.write(buf)) {
+        Ok(n) => n as isize,
+        Err(_) => -1,
+    }
+}
+
+unsafe extern "C" fn ripht_stream_flush(stream: *mut ffi::php_stream) -> c_int {
+    let state = open_stream(stream);
+
+    match guard(|| state.inner.flush()) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+unsafe extern "C" fn ripht_stream_seek(
+    stream: *mut ffi::php_stream,
+    offset: i64,
+    whence: c_int,
+    new_offset: *mut i64,
+) -> c_int {
+    let state = open_stream(stream);
+
+    let pos = match whence {
+        ffi::SEEK_SET => SeekFrom::Start(offset.max(0) as u64),
+        ffi::SEEK_CUR => SeekFrom::Current(offset),
+        ffi::SEEK_END => SeekFrom::End(offset),
+        _ => return -1,
+    };
+
+    match guard(|| state.inner.seek(pos)) {
+        Ok(position) => {
+            state.eof = false;
+            *new_offset = position as i64;
+            0
+        }
+        Err(_) => -1,
+    }
+}
+
+unsafe extern "C" fn ripht_stream_stat(
+    stream: *mut ffi::php_stream,
+    ssb: *mut ffi::php_stream_statbuf,
+) -> c_int {
+    let state = open_stream(stream);
+
+    match guard(|| state.inner.stat()) {
+        Ok(stat) => fill_stat(ssb, &stat),
+        Err(_) => -1,
+    }
+}
+
+unsafe extern "C" fn ripht_stream_set_option(
+    stream: *mut ffi::php_stream,
+    option: c_int,
+    _value: c_int,
+    _ptrparam: *mut c_void,
+) -> c_int {
+    if option != ffi::PHP_STREAM_OPTION_CHECK_LIVENESS {
+        return ffi::PHP_STREAM_OPTION_RETURN_NOTIMPL;
+    }
+
+    if open_stream(stream).eof {
+        ffi::PHP_STREAM_OPTION_RETURN_ERR
+    } else {
+        ffi::PHP_STREAM_OPTION_RETURN_OK
+    }
+}
+
+unsafe extern "C" fn ripht_stream_close(
+    stream: *mut ffi::php_stream,
+    _close_handle: c_int,
+) -> c_int {
+    let state = (*stream).abstract_ as *mut OpenStream;
+    (*stream).abstract_ = std::ptr::null_mut();
+
+    if !state.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+            drop(Box::from_raw(state));
+        }));
+    }
+
+    0
+}
+
+unsafe extern "C" fn ripht_wrapper_url_stat(
+    wrapper: *mut ffi::php_stream_wrapper,
+    url: *const c_char,
+    _flags: c_int,
+    ssb: *mut ffi::php_stream_statbuf,
+    _context: *mut ffi::php_stream_context,
+) -> c_int {
+    let handler = handler(wrapper);
+
+    match guard(|| handler.stat(&c_str(url))) {
+        Ok(stat) => fill_stat(ssb, &stat),
+        Err(_) => -1,
+    }
+}
+
+unsafe extern "C" fn ripht_wrapper_unlink(
+    wrapper: *mut ffi::php_stream_wrapper,
+    url: *const c_char,
+    _options: c_int,
+    _context: *mut ffi::php_stream_context,
+) -> c_int {
+    let handler = handler(wrapper);
+    let url = c_str(url);
+
+    match guard(|| handler.unlink(&url)) {
+        Ok(()) => 1,
+        Err(err) => {
+            let message =
+                CString::new(format!("{url}: {err}")).unwrap_or_default();
+            ffi::php_error_docref(
+                std::ptr::null(),
+                ffi::E_WARNING,
+                c"%s".as_ptr(),
+                message.as_ptr(),
+            );
+            0
+        }
+    }
+}
+
+/// An open directory stream; `php_stream.abstract`.
+struct OpenDir {
+    entries: std::vec::IntoIter<String>,
+}
+
+unsafe extern "C" fn ripht_wrapper_dir_open(
+    wrapper: *mut ffi::php_stream_wrapper,
+    filename: *const c_char,
+    mode: *const c_char,
+    options: c_int,
+    _opened_path: *mut *mut ffi::zend_string,
+    _context: *mut ffi::php_stream_context,
+) -> *mut ffi::php_stream {
+    let handler = handler(wrapper);
+
+    let entries = match guard(|| handler.read_dir(&c_str(filename))) {
+        Ok(entries) => entries,
+        Err(err) => {
+            log_error(wrapper, options, &err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    let state = Box::into_raw(Box::new(OpenDir {
+        entries: entries.into_iter(),
+    }));
+    let stream = ffi::_php_stream_alloc(
+        &DIR_OPS,
+        state as *mut c_void,
+        std::ptr::null(),
+        mode,
+    );
+    if stream.is_null() {
+        drop(Box::from_raw(state));
+    }
+
+    stream
+}
+
+/// Fills one `php_stream_dirent`; PHP reads exactly one entry per call.
+unsafe extern "C" fn ripht_dir_read(
+    stream: *mut ffi::php_stream,
+    buf: *mut c_char,
+    count: usize,
+) -> isize {
+    let state = &mut *((*stream).abstract_ as *mut OpenDir);
+
+    // `d_name` leads the struct; zeroing the rest leaves `d_type` unknown.
+    let Some(name) = state
+        .entries
+        .find(|name| name.len() < count && !name.contains('\0'))
+    else {
+        return 0;
+    };
+
+    std::ptr::write_bytes(buf, 0, count);
+    std::ptr::copy_nonoverlapping(
+        name.as_ptr() as *const c_char,
+        buf,
+        name.len(),
+    );
+
+    count as isize
+}
+
+unsafe extern "C" fn ripht_dir_close(
+    stream: *mut ffi::php_stream,
+    _close_handle: c_int,
+) -> c_int {
+    let state = (*stream).abstract_ as *mut OpenDir;
+    (*stream).abstract_ = std::ptr::null_mut();
+
+    if !state.is_null() {
+        drop(Box::from_raw(state));
+    }
+
+    0
+}
+
+#[cfg(unix)]
+unsafe fn fill_stat(
+    ssb: *mut ffi::php_stream_statbuf,
+    stat: &StreamStat,
+) -> c_int {
+    std::ptr::write_bytes(ssb, 0, 1);
+    let sb = &mut (*ssb).sb;
+
+    sb.st_size = stat.size as libc::off_t;
+    sb.st_nlink = 1;
+    sb.st_mode = if stat.is_dir {
+        libc::S_IFDIR | 0o755
+    } else {
+        libc::S_IFREG | 0o644
+    };
+
+    if let Some(modified) = stat.modified {
+        let secs = modified
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        sb.st_mtime = secs as libc::time_t;
+        sb.st_atime = sb.st_mtime;
+        sb.st_ctime = sb.st_mtime;
+    }
+
+    0
+}
+
+#[cfg(not(unix))]
+unsafe fn fill_stat(
+    _ssb: *mut ffi::php_stream_statbuf,
+    _stat: &StreamStat,
+) -> c_int {
+    -1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_stream_reads_writes_and_seeks() {
+        let mut stream: Box<dyn WrapperStream> =
+            Box::new(Cursor::new(b"hello".to_vec()));
+
+        let mut buf = [0u8; 3];
+        assert_eq!(stream.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"hel");
+
+        assert_eq!(
+            stream
+                .seek(SeekFrom::End(0))
+                .unwrap(),
+            5
+        );
+        assert_eq!(stream.write(b"!").unwrap(), 1);
+        assert_eq!(stream.stat().unwrap(), StreamStat::file(6));
+    }
+}
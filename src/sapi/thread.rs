@@ -0,0 +1,202 @@
+//! Dedicated engine thread.
+//!
+//! The NTS engine may only be driven from the thread that started it.
+//! [`PhpThread`] owns that thread and accepts work from any other thread
+//! over a channel, so callers never touch PHP globals directly.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+
+#[cfg(feature = "tracing")]
+use tracing::{debug, error};
+
+use super::{EngineBuilder, ExecutionError, RiphtSapi, SapiError};
+use crate::execution::{ExecutionContext, ExecutionHooks, ExecutionResult};
+
+pub(super) type Job = Box<dyn FnOnce(&RiphtSapi) + Send>;
+
+/// Handle to a thread that owns the PHP engine.
+///
+/// Jobs run one at a time, in submission order. The handle is `Send + Sync`,
+/// so it can be shared (e.g. behind an `Arc`) by any number of threads.
+/// Dropping it shuts the engine down and joins the thread.
+///
+/// ```no_run
+/// use ripht_php_sapi::{PhpThread, WebRequest};
+///
+/// let php = PhpThread::spawn().expect("engine startup");
+///
+/// let ctx = WebRequest::get()
+///     .build("/var/www/index.php")
+///     .expect("build");
+///
+/// let result = std::thread::spawn(move || php.execute(ctx))
+///     .join()
+///     .unwrap()
+///     .expect("execute");
+///
+/// println!("{}", result.body_string());
+/// ```
+pub struct PhpThread {
+    sender: Option<mpsc::Sender<Job>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl PhpThread {
+    /// Starts the engine with default configuration on a new thread.
+    pub fn spawn() -> Result<Self, SapiError> {
+        Self::spawn_with(EngineBuilder::default())
+    }
+
+    /// Starts the engine with `config` on a new thread.
+    ///
+    /// Fails with `AlreadyInitialized` if another thread already runs the engine.
+    pub fn spawn_with(config: EngineBuilder) -> Result<Self, SapiError> {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let (ready_tx, ready_rx) = mpsc::channel::<Result<(), SapiError>>();
+
+        let handle = thread::Builder::new()
+            .name("ripht-php".to_string())
+            .spawn(move || {
+                let sapi = match config.build() {
+                    Ok(sapi) => {
+                        let _ = ready_tx.send(Ok(()));
+                        sapi
+                    }
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                        return;
+                    }
+                };
+
+                #[cfg(feature = "tracing")]
+                debug!("PHP thread started");
+
+                for job in receiver {
+                    run_job(job, &sapi);
+                }
+
+                // The engine belongs to this thread and no job is running.
+                let _ = RiphtSapi::shutdown();
+
+                #[cfg(feature = "tracing")]
+                debug!("PHP thread stopped");
+            })
+            .map_err(|e| SapiError::InitializationFailed(e.to_string()))?;
+
+        match ready_rx.recv() {
+            Ok(Ok(())) => Ok(Self {
+                sender: Some(sender),
+                handle: Some(handle),
+            }),
+            Ok(Err(e)) => {
+                let _ = handle.join();
+                Err(e)
+            }
+            Err(_) => {
+                let _ = handle.join();
+                Err(SapiError::InitializationFailed(
+                    "PHP thread exited during startup".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Runs `f` on the engine thread and returns its result.
+    ///
+    /// A panic inside `f` is resumed on the calling thread.
+    pub fn run<F, R>(&self, f: F) -> Result<R, SapiError>
+    where
+        F: FnOnce(&RiphtSapi) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        submit(
+            self.sender
+                .as_ref()
+                .ok_or(SapiError::NotInitialized)?,
+            f,
+        )
+    }
+
+    pub fn execute(
+        &self,
+        ctx: ExecutionContext,
+    ) -> Result<ExecutionResult, ExecutionError> {
+        self.run(move |sapi| sapi.execute(ctx))?
+    }
+
+    pub fn execute_with_hooks<H>(
+        &self,
+        ctx: ExecutionContext,
+        hooks: H,
+    ) -> Result<ExecutionResult, ExecutionError>
+    where
+        H: ExecutionHooks + Send + 'static,
+    {
+        self.run(move |sapi| sapi.execute_with_hooks(ctx, hooks))?
+    }
+}
+
+/// Sends `f` to the thread behind `sender` and waits for its result.
+///
+/// A panic inside `f` is resumed on the calling thread.
+pub(super) fn submit<F, R>(
+    sender: &mpsc::Sender<Job>,
+    f: F,
+) -> Result<R, SapiError>
+where
+    F: FnOnce(&RiphtSapi) -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let (reply_tx, reply_rx) = mpsc::channel();
+
+    let job: Job = Box::new(move |sapi| {
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| f(sapi)));
+        let _ = reply_tx.send(outcome);
+    });
+
+    sender
+        .send(job)
+        .map_err(|_| SapiError::NotInitialized)?;
+
+    match reply_rx.recv() {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(payload)) => panic::resume_unwind(payload),
+        Err(_) => Err(SapiError::NotInitialized),
+    }
+}
+
+/// Runs a job, keeping the worker alive if it panics.
+pub(super) fn run_job(job: Job, sapi: &RiphtSapi) {
+    if panic::catch_unwind(AssertUnwindSafe(|| job(sapi))).is_err() {
+        #[cfg(feature = "tracing")]
+        error!("Job panicked on PHP thread");
+    }
+}
+
+impl Drop for PhpThread {
+    fn drop(&mut self) {
+        // Closing the channel ends the job loop, which shuts the engine down.
+        drop(self.sender.take());
+
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+    fn assert_send<T: Send>() {}
+
+    #[test]
+    fn test_php_thread_is_shareable() {
+        assert_send_sync::<PhpThread>();
+        assert_send::<ExecutionContext>();
+        assert_send::<ExecutionResult>();
+    }
+}
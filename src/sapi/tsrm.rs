@@ -0,0 +1,88 @@
+//! TSRM (Thread Safe Resource Manager) bookkeeping for ZTS builds.
+//!
+//! In ZTS builds every PHP global lives in per-thread storage allocated by
+//! TSRM. The thread that runs startup gets its storage from
+//! `php_tsrm_startup`; any other thread must allocate its own before touching
+//! the engine and release it when it exits.
+
+use std::cell::Cell;
+use std::ptr;
+use std::sync::{Mutex, MutexGuard};
+
+#[cfg(feature = "tracing")]
+use tracing::trace;
+
+use super::ffi;
+
+// Incremented on every TSRM startup; 0 while TSRM is down. Attachments from
+// an earlier generation were already released by `tsrm_shutdown`.
+static GENERATION: Mutex<u64> = Mutex::new(0);
+
+thread_local! {
+    static ATTACHMENT: Attachment = const { Attachment(Cell::new(0)) };
+}
+
+/// Frees the thread's TSRM storage when the thread exits.
+struct Attachment(Cell<u64>);
+
+impl Drop for Attachment {
+    fn drop(&mut self) {
+        let attached = self.0.take();
+        let generation = generation();
+
+        if attached != 0 && attached == *generation {
+            // SAFETY: The thread allocated storage in the current generation
+            // and is exiting; nothing on it touches PHP globals anymore.
+            unsafe { ffi::ts_free_thread() };
+        }
+    }
+}
+
+fn generation() -> MutexGuard<'static, u64> {
+    GENERATION
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Starts TSRM. Must run on the engine thread before `sapi_startup`.
+pub(crate) unsafe fn startup() {
+    let mut generation = generation();
+
+    ffi::php_tsrm_startup();
+    *generation = generation
+        .wrapping_add(1)
+        .max(1);
+}
+
+/// Shuts TSRM down, releasing every thread's storage.
+pub(crate) unsafe fn shutdown() {
+    let mut generation = generation();
+
+    ffi::tsrm_shutdown();
+    *generation = 0;
+}
+
+/// Allocates TSRM storage for the calling thread if it has none yet.
+///
+/// Must not be called on the thread that ran [`startup`].
+pub(crate) fn attach() {
+    let generation = generation();
+
+    if *generation == 0 {
+        return;
+    }
+
+    let _ = ATTACHMENT.try_with(|attachment| {
+        if attachment.0.get() == *generation {
+            return;
+        }
+
+        #[cfg(feature = "tracing")]
+        trace!("Allocating TSRM storage for thread");
+
+        // SAFETY: TSRM is running (generation != 0, held under the lock) and
+        // allocates storage keyed by the calling thread.
+        unsafe { ffi::ts_resource_ex(0, ptr::null_mut()) };
+        attachment.0.set(*generation);
+    });
+}
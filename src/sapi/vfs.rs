@@ -0,0 +1,345 @@
+//! Virtual filesystem for PHP sources.
+//!
+//! A [`VirtualFs`] set with [`EngineBuilder::with_virtual_fs()`] is consulted
+//! whenever the Zend engine opens a script to compile it: the primary script,
+//! `include`/`require` and their `_once` variants. Paths it doesn't know fall
+//! through to the real filesystem. Other file functions (`file_exists()`,
+//! `fopen()`, ...) still only see the real filesystem.
+//!
+//! [`EngineBuilder::with_virtual_fs()`]: super::EngineBuilder::with_virtual_fs
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int, c_void};
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+#[cfg(feature = "tracing")]
+use tracing::trace;
+
+use super::ffi;
+
+/// Source of PHP files that don't live on the real filesystem.
+///
+/// Paths are absolute and lexically normalized (no `.` or `..`) by the time
+/// they reach the filesystem.
+pub trait VirtualFs: Send + Sync {
+    /// Contents of `path`, or `None` if this filesystem doesn't have it.
+    fn read(&self, path: &Path) -> Option<Cow<'_, [u8]>>;
+
+    fn contains(&self, path: &Path) -> bool {
+        self.read(path).is_some()
+    }
+}
+
+/// [`VirtualFs`] backed by a map of path to contents.
+///
+/// ```
+/// use ripht_php_sapi::MemoryFs;
+///
+/// let fs = MemoryFs::new()
+///     .with_file("/app/index.php", "<?php require __DIR__ . '/lib.php';")
+///     .with_file("/app/lib.php", "<?php echo 'hi';");
+///
+/// assert_eq!(fs.len(), 2);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFs {
+    files: HashMap<PathBuf, Vec<u8>>,
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file. Relative paths are taken relative to `/`.
+    #[must_use]
+    pub fn with_file(
+        mut self,
+        path: impl AsRef<Path>,
+        contents: impl Into<Vec<u8>>,
+    ) -> Self {
+        self.insert(path, contents);
+        self
+    }
+
+    /// Adds or replaces a file, returning the previous contents.
+    pub fn insert(
+        &mut self,
+        path: impl AsRef<Path>,
+        contents: impl Into<Vec<u8>>,
+    ) -> Option<Vec<u8>> {
+        self.files
+            .insert(normalize(path.as_ref()), contents.into())
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+impl VirtualFs for MemoryFs {
+    fn read(&self, path: &Path) -> Option<Cow<'_, [u8]>> {
+        self.files
+            .get(path)
+            .map(|contents| Cow::Borrowed(contents.as_slice()))
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        self.files.contains_key(path)
+    }
+}
+
+/// Shared filesystem handle kept in the engine configuration.
+#[derive(Clone)]
+pub(crate) struct VirtualFsHandle(pub(crate) Arc<dyn VirtualFs>);
+
+impl fmt::Debug for VirtualFsHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("VirtualFsHandle(..)")
+    }
+}
+
+type StreamOpenFn = unsafe extern "C" fn(*mut ffi::zend_file_handle) -> c_int;
+type ResolvePathFn =
+    unsafe extern "C" fn(*mut ffi::zend_string) -> *mut ffi::zend_string;
+
+static FS: RwLock<Option<Arc<dyn VirtualFs>>> = RwLock::new(None);
+
+// PHP's own handlers, captured at install. Only written during module
+// startup, under the engine lifecycle lock.
+static mut ORIGINAL_OPEN: Option<StreamOpenFn> = None;
+static mut ORIGINAL_RESOLVE: Option<ResolvePathFn> = None;
+
+/// Routes Zend's script opener through `fs`.
+///
+/// Must run after module startup, which (re)sets the handlers this wraps.
+pub(crate) unsafe fn install(fs: Option<&VirtualFsHandle>) {
+    *FS.write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) =
+        fs.map(|handle| Arc::clone(&handle.0));
+
+    if fs.is_none() {
+        return;
+    }
+
+    ORIGINAL_OPEN = ffi::zend_stream_open_function;
+    ORIGINAL_RESOLVE = ffi::zend_resolve_path;
+
+    ffi::zend_stream_open_function = Some(ripht_vfs_stream_open);
+    ffi::zend_resolve_path = Some(ripht_vfs_resolve_path);
+}
+
+fn fs() -> Option<Arc<dyn VirtualFs>> {
+    FS.read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+}
+
+/// Whether `path` names a file in the installed virtual filesystem.
+pub(crate) fn contains(path: &Path) -> bool {
+    fs().is_some_and(|fs| fs.contains(&absolute(path)))
+}
+
+/// Lexically resolves `.` and `..`, rooting relative paths at `/`.
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::from("/");
+
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => out.push(part),
+            Component::ParentDir => {
+                out.pop();
+            }
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+        }
+    }
+
+    out
+}
+
+/// Resolves a relative path against the working directory.
+fn absolute(path: &Path) -> PathBuf {
+    match std::env::current_dir() {
+        Ok(cwd) if path.is_relative() => normalize(&cwd.join(path)),
+        _ => normalize(path),
+    }
+}
+
+/// Finds `name` in the virtual filesystem, trying the directory of the
+/// executing script first for relative names, like PHP's include lookup.
+unsafe fn lookup(
+    fs: &dyn VirtualFs,
+    name: *const ffi::zend_string,
+) -> Option<PathBuf> {
+    if name.is_null() {
+        return None;
+    }
+
+    let name = path_from(name);
+
+    if name.is_absolute() {
+        let path = normalize(&name);
+        return fs
+            .contains(&path)
+            .then_some(path);
+    }
+
+    let executing = ffi::zend_get_executed_filename_ex();
+    let from_script = (!executing.is_null())
+        .then(|| {
+            path_from(executing)
+                .parent()
+                .map(|dir| normalize(&dir.join(&name)))
+        })
+        .flatten();
+
+    from_script
+        .into_iter()
+        .chain(Some(absolute(&name)))
+        .find(|path| fs.contains(path))
+}
+
+unsafe fn path_from(name: *const ffi::zend_string) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(ffi::zstr_bytes(name)).into_owned())
+}
+
+unsafe fn zend_string_from(path: &Path) -> *mut ffi::zend_string {
+    let path = path.to_string_lossy();
+
+    ffi::zend_string_concat2(
+        path.as_ptr() as *const _,
+        path.len(),
+        c"".as_ptr(),
+        0,
+    )
+}
+
+unsafe extern "C" fn ripht_vfs_stream_open(
+    handle: *mut ffi::zend_file_handle,
+) -> c_int {
+    let served = fs().is_some_and(|fs| {
+        let Some(path) =
+            lookup(fs.as_ref(), (*handle).filename as *const ffi::zend_string)
+        else {
+            return false;
+        };
+        let Some(contents) = fs.read(&path) else {
+            return false;
+        };
+
+        #[cfg(feature = "tracing")]
+        trace!(path = %path.display(), "Serving script from virtual filesystem");
+
+        // `zend_stream_fixup` reads the script through these callbacks; a
+        // filled-in `buf` alone is skipped when it does the opening.
+        let script = Box::new(Script {
+            contents: contents.into_owned(),
+            pos: 0,
+        });
+
+        (*handle).handle.stream = ffi::zend_stream {
+            handle: Box::into_raw(script) as *mut c_void,
+            isatty: 0,
+            reader: script_read as *mut c_void,
+            fsizer: script_size as *mut c_void,
+            closer: script_close as *mut c_void,
+        };
+        (*handle).handle_type = ffi::ZEND_HANDLE_STREAM;
+
+        // Becomes `__FILE__`, so `__DIR__`-relative includes stay virtual.
+        if (*handle).opened_path.is_null() {
+            (*handle).opened_path =
+                zend_string_from(&path) as *mut _;
+        }
+
+        true
+    });
+
+    if served {
+        return ffi::SUCCESS;
+    }
+
+    match ORIGINAL_OPEN {
+        Some(open) => open(handle),
+        None => ffi::FAILURE,
+    }
+}
+
+/// A virtual script being read by the Zend engine.
+struct Script {
+    contents: Vec<u8>,
+    pos: usize,
+}
+
+unsafe extern "C" fn script_read(
+    handle: *mut c_void,
+    buf: *mut c_char,
+    len: usize,
+) -> isize {
+    let script = &mut *(handle as *mut Script);
+    let rest = &script.contents[script.pos..];
+    let n = rest.len().min(len);
+
+    std::ptr::copy_nonoverlapping(rest.as_ptr(), buf as *mut u8, n);
+    script.pos += n;
+
+    n as isize
+}
+
+unsafe extern "C" fn script_size(handle: *mut c_void) -> usize {
+    (*(handle as *const Script))
+        .contents
+        .len()
+}
+
+/// Called by `zend_destroy_file_handle`.
+unsafe extern "C" fn script_close(handle: *mut c_void) {
+    drop(Box::from_raw(handle as *mut Script));
+}
+
+unsafe extern "C" fn ripht_vfs_resolve_path(
+    name: *mut ffi::zend_string,
+) -> *mut ffi::zend_string {
+    if let Some(path) = fs().and_then(|fs| lookup(fs.as_ref(), name)) {
+        return zend_string_from(&path);
+    }
+
+    match ORIGINAL_RESOLVE {
+        Some(resolve) => resolve(name),
+        None => std::ptr::null_mut(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_resolves_dots() {
+        assert_eq!(
+            normalize(Path::new("/app/./lib/../index.php")),
+            PathBuf::from("/app/index.php")
+        );
+        assert_eq!(normalize(Path::new("app.php")), PathBuf::from("/app.php"));
+        assert_eq!(normalize(Path::new("/../x")), PathBuf::from("/x"));
+    }
+
+    #[test]
+    fn test_memory_fs_normalizes_keys() {
+        let fs = MemoryFs::new().with_file("app/./index.php", "<?php");
+
+        assert!(fs.contains(Path::new("/app/index.php")));
+        assert_eq!(
+            fs.read(Path::new("/app/index.php"))
+                .as_deref(),
+            Some(&b"<?php"[..])
+        );
+    }
+}
@@ -0,0 +1,6 @@
+<?php
+
+echo json_encode([
+    'post' => $_POST,
+    'input' => strlen(file_get_contents('php://input')),
+]);
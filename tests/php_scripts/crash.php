@@ -0,0 +1,10 @@
+<?php
+
+// Kills the executing process with SIGSEGV to simulate an extension crash.
+if (!function_exists('posix_kill')) {
+    echo 'posix unavailable';
+    return;
+}
+
+posix_kill(posix_getpid(), 11);
+echo 'still alive';
@@ -0,0 +1,9 @@
+<?php
+
+header('Content-Type: application/json');
+
+echo json_encode([
+    'request_time' => $_SERVER['REQUEST_TIME'],
+    'request_time_float' => $_SERVER['REQUEST_TIME_FLOAT'],
+    'rolls' => [mt_rand(), mt_rand(1, 100), rand(1, 6)],
+]);
@@ -0,0 +1,15 @@
+<?php
+/**
+ * Early hints test script.
+ *
+ * Flushes a 103 header block before sending the final response.
+ */
+
+http_response_code(103);
+header('Link: </style.css>; rel=preload; as=style');
+flush();
+
+http_response_code(200);
+header('Content-Type: text/plain');
+
+echo "done";
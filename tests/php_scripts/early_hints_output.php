@@ -0,0 +1,12 @@
+<?php
+/**
+ * Early hints without an explicit flush.
+ *
+ * The 103 is still pending when output starts, so it goes out right before
+ * the final response.
+ */
+
+http_response_code(103);
+header('Link: </app.js>; rel=preload; as=script');
+
+echo "body after hints";
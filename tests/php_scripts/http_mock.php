@@ -0,0 +1,32 @@
+<?php
+
+$user = json_decode(file_get_contents('https://api.example.com/users/1'), true);
+$status = $http_response_header[0];
+
+$context = stream_context_create(['http' => [
+    'method' => 'POST',
+    'header' => "Content-Type: application/json\r\nX-Token: secret",
+    'content' => '{"name":"Jane"}',
+]]);
+$created = file_get_contents('https://api.example.com/users', false, $context);
+$createdStatus = $http_response_header[0];
+
+$missing = @file_get_contents('https://api.example.com/users/2');
+
+$ignored = file_get_contents(
+    'https://api.example.com/users/2',
+    false,
+    stream_context_create(['http' => ['ignore_errors' => true]])
+);
+
+$unmatched = @file_get_contents('http://offline.example.com/');
+
+echo json_encode([
+    'user' => $user,
+    'status' => $status,
+    'created' => $created,
+    'created_status' => $createdStatus,
+    'missing' => $missing,
+    'ignored' => $ignored,
+    'unmatched' => $unmatched,
+]);
@@ -0,0 +1,8 @@
+<?php
+
+echo json_encode([
+    'get' => $_GET,
+    'post' => $_POST,
+    'cookie' => $_COOKIE,
+    'agent' => $_SERVER['HTTP_USER_AGENT'] ?? null,
+]);
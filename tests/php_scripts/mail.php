@@ -0,0 +1,17 @@
+<?php
+
+$sent = mail(
+    'alice@example.com, bob@example.com',
+    'Welcome',
+    "Hello from PHP\n",
+    ['From' => 'app@example.com', 'X-Tags' => ['one', 'two']]
+);
+
+$raw = mail(
+    'carol@example.com',
+    'Raw headers',
+    'Second message',
+    "From: app@example.com\r\nReply-To: support@example.com"
+);
+
+echo json_encode(['sent' => $sent, 'raw' => $raw]);
@@ -0,0 +1,14 @@
+<?php
+
+$message = 'Allowed memory size of 1024 bytes exhausted (tried to allocate 8 bytes)';
+
+error_log($message);
+trigger_error($message, E_USER_WARNING);
+
+try {
+    throw new RuntimeException($message);
+} catch (RuntimeException $e) {
+    echo $e->getMessage(), "\n";
+}
+
+echo "done";
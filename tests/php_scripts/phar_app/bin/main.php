@@ -0,0 +1,8 @@
+<?php
+/**
+ * Default entry point, run by the archive stub.
+ */
+
+require dirname(__DIR__) . '/src/greet.php';
+
+echo 'stub: ', greet('phar');
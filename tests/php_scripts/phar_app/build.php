@@ -0,0 +1,18 @@
+<?php
+/**
+ * Rebuilds tests/php_scripts/app.phar from this directory.
+ *
+ * Run: php -d phar.readonly=0 tests/php_scripts/phar_app/build.php
+ */
+
+$out = dirname(__DIR__) . '/app.phar';
+@unlink($out);
+
+$phar = new Phar($out, 0, 'app.phar');
+$phar->buildFromDirectory(__DIR__, '#/(bin|public|src)/#');
+$phar->setStub(
+    "<?php\nPhar::mapPhar('app.phar');\n"
+    . "require 'phar://app.phar/bin/main.php';\n"
+    . "__HALT_COMPILER(); ?>\r\n"
+);
+$phar->setSignatureAlgorithm(Phar::SHA256);
@@ -0,0 +1,11 @@
+<?php
+/**
+ * Web entry point, addressed directly as phar://.../public/index.php.
+ *
+ * Query parameters:
+ *   name - Who to greet (default: web)
+ */
+
+require dirname(__DIR__) . '/src/greet.php';
+
+echo 'entry: ', greet($_GET['name'] ?? 'web');
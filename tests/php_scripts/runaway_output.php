@@ -0,0 +1,12 @@
+<?php
+/**
+ * Writes output forever; only an output limit stops it.
+ */
+
+header('Content-Type: text/plain');
+
+$chunk = str_repeat('x', 1024);
+
+while (true) {
+    echo $chunk;
+}
@@ -0,0 +1,38 @@
+<?php
+
+$result = [];
+
+$result['passwd'] = file_get_contents('/etc/passwd') !== false;
+
+try {
+    $result['uname'] = php_uname();
+} catch (Error $e) {
+    $result['uname'] = $e->getMessage();
+}
+
+try {
+    new ReflectionClass('stdClass');
+    $result['reflection'] = 'allowed';
+} catch (Error $e) {
+    $result['reflection'] = $e->getMessage();
+}
+
+try {
+    new SplObjectStorage();
+    $result['storage'] = 'allowed';
+} catch (Error $e) {
+    $result['storage'] = $e->getMessage();
+}
+
+try {
+    new class extends SplObjectStorage {};
+    $result['storage_subclass'] = 'allowed';
+} catch (Error $e) {
+    $result['storage_subclass'] = $e->getMessage();
+}
+
+$result['allow_url_fopen'] = ini_get('allow_url_fopen');
+$result['upload_max_filesize'] = ini_get('upload_max_filesize');
+
+header('Content-Type: application/json');
+echo json_encode($result);
@@ -0,0 +1,17 @@
+<?php
+/**
+ * Status line test script.
+ *
+ * Sends a raw status line with a custom reason phrase.
+ *
+ * Query parameters:
+ *   override - Also call http_response_code() with this code afterwards
+ */
+
+header("HTTP/1.0 418 I'm a teapot");
+
+if (isset($_GET['override'])) {
+    http_response_code((int)$_GET['override']);
+}
+
+echo "short and stout";
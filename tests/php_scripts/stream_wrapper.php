@@ -0,0 +1,30 @@
+<?php
+
+$config = json_decode(file_get_contents('blob://config/app.json'), true);
+
+file_put_contents('blob://out/report.txt', "line 1\nline 2\n");
+
+$lines = [];
+$handle = fopen('blob://out/report.txt', 'r');
+while (!feof($handle)) {
+    $line = fgets($handle);
+    if ($line !== false) {
+        $lines[] = rtrim($line);
+    }
+}
+fseek($handle, 5);
+$seeked = fread($handle, 1);
+fclose($handle);
+
+echo json_encode([
+    'config' => $config,
+    'lines' => $lines,
+    'seeked' => $seeked,
+    'size' => filesize('blob://out/report.txt'),
+    'exists' => file_exists('blob://config/app.json'),
+    'missing' => @file_get_contents('blob://config/missing.json'),
+    'is_dir' => is_dir('blob://config'),
+    'entries' => scandir('blob://config'),
+    'included' => include 'blob://lib/helper.php',
+    'unlinked' => unlink('blob://config/old.json'),
+]);
@@ -0,0 +1,15 @@
+<?php
+/**
+ * Writes past a small output limit; records whether the rest of the script
+ * and its shutdown function ran.
+ */
+
+$marker = $_GET['marker'];
+
+register_shutdown_function(function () use ($marker) {
+    file_put_contents($marker, "shutdown\n", FILE_APPEND);
+});
+
+echo str_repeat('x', 50000);
+
+file_put_contents($marker, "finished\n", FILE_APPEND);
@@ -0,0 +1,94 @@
+//! `PreforkExecutor` tests.
+//!
+//! `PreforkExecutor::new()` needs a single-threaded process, which the libtest
+//! harness isn't, so these run from a plain `main` on the main thread.
+
+#[cfg(unix)]
+fn main() {
+    use std::path::PathBuf;
+
+    use ripht_php_sapi::{ExecutionError, PreforkExecutor, WebRequest};
+
+    fn php_script_path(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/php_scripts")
+            .join(name)
+    }
+
+    fn test_prefork_worker_crash_is_isolated() {
+        let prefork = PreforkExecutor::new(2).expect("failed to fork workers");
+        assert_eq!(prefork.workers(), 2);
+
+        let exec = WebRequest::get()
+            .build(php_script_path("hello.php"))
+            .expect("failed to build WebRequest");
+
+        let result = prefork
+            .execute(exec)
+            .expect("prefork execution failed");
+        assert!(result
+            .body_string()
+            .contains("Hello"));
+
+        let exec = WebRequest::get()
+            .build(php_script_path("crash.php"))
+            .expect("failed to build WebRequest");
+
+        match prefork.execute(exec) {
+            Err(ExecutionError::WorkerCrashed { signal, .. }) => {
+                assert_eq!(signal, Some(11));
+            }
+            Ok(result) => {
+                assert_eq!(result.body_string(), "posix unavailable");
+            }
+            Err(e) => panic!("unexpected error: {e}"),
+        }
+
+        // Workers are forked by the zygote, so any thread may submit work.
+        std::thread::scope(|scope| {
+            for _ in 0..3 {
+                scope.spawn(|| {
+                    let exec = WebRequest::get()
+                        .build(php_script_path("hello.php"))
+                        .expect("failed to build WebRequest");
+
+                    assert!(prefork
+                        .execute(exec)
+                        .expect("execution after crash failed")
+                        .body_string()
+                        .contains("Hello"));
+                });
+            }
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    fn test_prefork_refuses_multi_threaded_process() {
+        let (tx, rx) = std::sync::mpsc::channel::<()>();
+        let parked = std::thread::spawn(move || rx.recv());
+
+        let err = PreforkExecutor::new(1)
+            .err()
+            .expect("fork with a second thread alive must fail");
+
+        drop(tx);
+        let _ = parked.join();
+
+        assert!(matches!(
+            err,
+            ripht_php_sapi::SapiError::MultipleThreads { threads: 2 }
+        ));
+    }
+
+    test_prefork_worker_crash_is_isolated();
+    println!("test test_prefork_worker_crash_is_isolated ... ok");
+
+    #[cfg(target_os = "linux")]
+    {
+        test_prefork_refuses_multi_threaded_process();
+        println!("test test_prefork_refuses_multi_threaded_process ... ok");
+    }
+}
+
+#[cfg(not(unix))]
+fn main() {}
@@ -1,8 +1,16 @@
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
 use ripht_php_sapi::{
-    ExecutionContext, ExecutionHooks, OutputAction, RiphtSapi, WebRequest,
+    BufferPolicy, CliRequest, EngineBuilder, EngineState, ExecutionContext,
+    ExecutionError, ExecutionHooks, ExecutionResult, HeaderPolicy, HttpMock,
+    InformationalResponse, InputAction, InputSource, MailCollector, MemoryFs,
+    MockResponse, OutputAction, OverflowAction, PhpThread, RequestAction,
+    ResponseHead, ResponseHeader, RiphtSapi, SapiError, SecurityPolicy,
+    SecurityViolation, StreamStat, StreamWrapper, TimingRecorder, WebRequest,
+    WrapperStream,
 };
 
 fn php_script_path(name: &str) -> PathBuf {
@@ -11,6 +19,32 @@ fn php_script_path(name: &str) -> PathBuf {
         .join(name)
 }
 
+/// Restarts the engine with the default configuration when dropped, so a
+/// test that fails midway doesn't leave its engine setup (or a shut down
+/// engine) behind for later tests.
+struct ResetEngine;
+
+impl ResetEngine {
+    /// Restarts the engine with `config` until the guard is dropped.
+    fn restart_with(config: EngineBuilder) -> Self {
+        RiphtSapi::instance()
+            .restart_with(config)
+            .expect("restart failed");
+
+        Self
+    }
+}
+
+impl Drop for ResetEngine {
+    fn drop(&mut self) {
+        let result = RiphtSapi::instance().restart_with(RiphtSapi::builder());
+
+        if !std::thread::panicking() {
+            result.expect("restart with defaults failed");
+        }
+    }
+}
+
 #[test]
 fn execute_hello_php() {
     let php = RiphtSapi::instance();
@@ -336,820 +370,2268 @@ fn test_sapi_initializes() {
 }
 
 #[test]
-fn test_file_not_found() {
-    let req = WebRequest::get().build("/nonexistent/path.php");
+fn test_engine_shutdown_and_restart() {
+    let php = RiphtSapi::instance();
+    let script_path = php_script_path("hello.php");
 
-    assert!(req.is_err());
-}
+    assert!(matches!(
+        RiphtSapi::builder().build(),
+        Err(SapiError::AlreadyInitialized)
+    ));
 
-#[test]
-fn test_get_ini_display_errors() {
-    let php = RiphtSapi::instance();
-    let _ = php.set_ini("display_errors", "0");
+    let engine = ResetEngine;
+    RiphtSapi::shutdown().expect("shutdown failed");
+    assert_eq!(php.state(), EngineState::ShutDown);
 
-    assert_eq!(php.get_ini("display_errors"), Some("0".into()));
-}
+    let exec = WebRequest::get()
+        .build(&script_path)
+        .expect("failed to build WebRequest");
 
-#[test]
-fn test_get_ini_nonexistent() {
-    let php = RiphtSapi::instance();
+    assert!(matches!(
+        php.execute(exec),
+        Err(ExecutionError::NotInitialized)
+    ));
+    assert!(matches!(
+        php.set_ini("display_errors", "0"),
+        Err(SapiError::NotInitialized)
+    ));
+    assert!(RiphtSapi::instance()
+        .get_ini("display_errors")
+        .is_none());
+
+    php.restart_with(RiphtSapi::builder().with_ini("memory_limit", "96M"))
+        .expect("restart failed");
+    assert_eq!(php.state(), EngineState::Running);
+    assert_eq!(
+        php.get_ini("memory_limit")
+            .as_deref(),
+        Some("96M")
+    );
 
-    let value = php.get_ini("this_ini_key_does_not_exist_12345");
-    assert!(value.is_none(), "Non-existent INI should return None");
-}
+    drop(engine);
 
-#[test]
-fn test_set_ini_and_get_ini() {
-    let php = RiphtSapi::instance();
+    let exec = WebRequest::get()
+        .build(&script_path)
+        .expect("failed to build WebRequest");
 
-    let result = php.set_ini("memory_limit", "256M");
-    assert!(result.is_ok(), "set_ini should succeed for valid INI key");
+    let result = php
+        .execute(exec)
+        .expect("execution after restart failed");
 
-    let value = php.get_ini("memory_limit");
-    assert!(value.is_some(), "memory_limit should be readable after set");
-    assert_eq!(
-        value.unwrap(),
-        "256M",
-        "memory_limit should reflect the set value"
-    );
+    assert!(result
+        .body_string()
+        .contains("Hello"));
 }
 
+#[cfg(feature = "session")]
 #[test]
-fn test_set_ini_invalid_key() {
+fn test_session_store_backs_php_sessions() {
     let php = RiphtSapi::instance();
+    let store = ripht_php_sapi::InMemorySessionStore::new();
 
-    let result = php.set_ini("key\0with\0nulls", "value");
-    assert!(
-        result.is_err(),
-        "set_ini should fail for key with null bytes"
+    let engine = ResetEngine::restart_with(
+        RiphtSapi::builder().with_session_store(store.clone()),
     );
-}
 
-#[test]
-fn test_set_ini_invalid_value() {
-    let php = RiphtSapi::instance();
+    let first = php
+        .execute(
+            WebRequest::get()
+                .build(php_script_path("session.php"))
+                .expect("failed to build WebRequest"),
+        )
+        .expect("first request failed");
 
-    let result = php.set_ini("memory_limit", "value\0with\0nulls");
-    assert!(
-        result.is_err(),
-        "set_ini should fail for value with null bytes"
-    );
-}
+    let cookie = first
+        .all_headers()
+        .find(|h| {
+            h.name()
+                .eq_ignore_ascii_case("Set-Cookie")
+        })
+        .and_then(|h| h.value().split(';').next())
+        .expect("session cookie")
+        .to_string();
+    let id = cookie
+        .trim_start_matches("PHPSESSID=")
+        .to_string();
 
-#[test]
-fn test_execution_error_script_not_found() {
-    let php = RiphtSapi::instance();
+    assert_eq!(store.get(&id).as_deref(), Some(&b"visit_count|i:1;"[..]));
 
-    let ctx = ExecutionContext::script("/nonexistent/path/to/script.php");
-    let result = php.execute(ctx);
+    let second = php
+        .execute(
+            WebRequest::get()
+                .with_raw_cookie_header(&cookie)
+                .build(php_script_path("session.php"))
+                .expect("failed to build WebRequest"),
+        )
+        .expect("second request failed");
 
-    assert!(
-        result.is_err(),
-        "execute should fail for nonexistent script"
-    );
-    let err = result.unwrap_err();
-    assert!(
-        err.to_string()
-            .contains("not found"),
-        "Error should mention script not found"
-    );
+    assert!(second
+        .body_string()
+        .contains("\"visit_count\": 2"));
+    assert_eq!(store.len(), 1);
+
+    drop(engine);
 }
 
 #[test]
-fn test_multipart_upload_basic() {
+fn test_virtual_fs_serves_scripts_and_includes() {
     let php = RiphtSapi::instance();
-    let script_path = php_script_path("file_upload.php");
+    let fs = MemoryFs::new()
+        .with_file(
+            "/virtual/app/index.php",
+            "<?php\n\
+             require __DIR__ . '/lib/greet.php';\n\
+             include_once 'lib/greet.php';\n\
+             echo greet('vfs'), ' ', basename(__FILE__);",
+        )
+        .with_file(
+            "/virtual/app/lib/greet.php",
+            "<?php function greet($n) { return \"hello $n\"; }",
+        );
 
-    let boundary = "boundary123";
-    let body = format!(
-        "--{boundary}\r\n\
-Content-Disposition: form-data; name=\"field\"\r\n\r\n\
-test_value\r\n\
---{boundary}--\r\n",
-        boundary = boundary
-    );
+    let engine =
+        ResetEngine::restart_with(RiphtSapi::builder().with_virtual_fs(fs));
 
-    let exec = WebRequest::post()
-        .with_content_type(format!(
-            "multipart/form-data; boundary={}",
-            boundary
-        ))
-        .with_body(body.into_bytes())
-        .build(&script_path)
-        .expect("failed to build multipart WebRequest");
+    let exec = WebRequest::get()
+        .build("/virtual/app/index.php")
+        .expect("virtual script should be accepted");
 
     let result = php
         .execute(exec)
-        .expect("multipart POST request execution failed");
-    assert_eq!(result.status_code(), 200);
+        .expect("execution failed");
 
-    let body_str = result.body_string();
-    let json: serde_json::Value = serde_json::from_str(&body_str)
-        .unwrap_or_else(|_| {
-            panic!("failed to parse JSON response: {}", body_str)
-        });
+    assert_eq!(result.body_string(), "hello vfs index.php");
 
-    assert_eq!(
-        json["post_data"]["field"], "test_value",
-        "POST field should be 'test_value': {}",
-        body_str
-    );
+    let hello = php
+        .execute(
+            WebRequest::get()
+                .build(php_script_path("hello.php"))
+                .expect("failed to build WebRequest"),
+        )
+        .expect("real script should still run");
+    assert!(hello
+        .body_string()
+        .contains("Hello"));
+
+    drop(engine);
+
+    assert!(WebRequest::get()
+        .build("/virtual/app/index.php")
+        .is_err());
 }
 
 #[test]
-fn test_multipart_upload_with_file() {
+fn test_virtual_fs_serves_plain_includes() {
     let php = RiphtSapi::instance();
-    let script_path = php_script_path("file_upload.php");
+    let fs = MemoryFs::new()
+        .with_file(
+            "/virtual/plain/index.php",
+            "<?php\n\
+             $a = include 'parts/value.php';\n\
+             $b = include __DIR__ . '/parts/value.php';\n\
+             echo $a + $b;",
+        )
+        .with_file("/virtual/plain/parts/value.php", "<?php return 21;");
 
-    let boundary = {
-        use std::time::{SystemTime, UNIX_EPOCH};
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_nanos();
-        format!("----multipart-form-boundary-{:x}", timestamp)
-    };
-    let file_content = "Hello, this is test file content!";
-    let body = format!(
-        "--{boundary}\r\n\
-Content-Disposition: form-data; name=\"myfile\"; filename=\"test.txt\"\r\n\
-Content-Type: text/plain\r\n\
-\r\n\
-{file_content}\r\n\
---{boundary}\r\n\
-Content-Disposition: form-data; name=\"description\"\r\n\
-\r\n\
-A test file\r\n\
---{boundary}--\r\n",
-        boundary = boundary,
-        file_content = file_content
-    );
+    let engine =
+        ResetEngine::restart_with(RiphtSapi::builder().with_virtual_fs(fs));
 
-    let exec = WebRequest::post()
-        .with_content_type(format!(
-            "multipart/form-data; boundary={}",
-            boundary
-        ))
-        .with_body(body.into_bytes())
-        .build(&script_path)
-        .expect("failed to build file upload WebRequest");
+    let exec = WebRequest::get()
+        .build("/virtual/plain/index.php")
+        .expect("virtual script should be accepted");
 
     let result = php
         .execute(exec)
-        .expect("file upload request execution failed");
-    assert_eq!(result.status_code(), 200);
+        .expect("execution failed");
 
-    let json: serde_json::Value = serde_json::from_slice(&result.body())
-        .expect("failed to parse response body as JSON");
+    drop(engine);
 
-    assert_eq!(
-        json["post_data"]["description"], "A test file",
-        "POST field 'description' should be set"
-    );
+    assert_eq!(result.body_string(), "42");
+}
 
-    assert!(
-        json["files"]["myfile"].is_object(),
-        "FILES should contain 'myfile' entry"
-    );
+#[cfg(not(php_zts))]
+#[test]
+fn test_execute_from_foreign_thread_fails() {
+    let php = RiphtSapi::instance();
+    let script_path = php_script_path("hello.php");
 
-    let file_entry = &json["files"]["myfile"];
-    assert_eq!(file_entry["name"], "test.txt");
-    assert_eq!(file_entry["error"], 0);
+    let (err, shutdown) = std::thread::spawn(move || {
+        let exec = WebRequest::get()
+            .build(&script_path)
+            .expect("failed to build WebRequest");
 
-    assert_eq!(file_entry["tmp_exists"], true, "Temp file should exist");
-    assert_eq!(
-        file_entry["tmp_readable"], true,
-        "Temp file should be readable"
-    );
+        let err = RiphtSapi::instance()
+            .execute(exec)
+            .expect_err("foreign thread must not execute");
 
-    assert_eq!(
-        file_entry["tmp_content"], file_content,
-        "Temp file content should match uploaded content"
-    );
-    assert_eq!(
-        file_entry["tmp_content_length"],
-        file_content.len(),
-        "Temp file size should match uploaded content length"
-    );
+        (err, RiphtSapi::shutdown())
+    })
+    .join()
+    .unwrap();
+
+    assert!(matches!(err, ExecutionError::WrongThread { .. }));
+    assert!(matches!(shutdown, Err(SapiError::WrongThread { .. })));
+    assert!(php.is_initialized());
+    assert!(php
+        .get_ini("display_errors")
+        .is_some());
 }
 
 #[test]
-fn test_multipart_upload_temp_file_creation() {
+fn test_shutdown_during_request_fails() {
     let php = RiphtSapi::instance();
-    let script_path = php_script_path("file_upload.php");
-
-    let boundary = "boundary456";
-    let file_content = "Test file content for temp file verification";
-    let body = format!(
-        "--{boundary}\r\n\
-Content-Disposition: form-data; name=\"upload\"; filename=\"verify.txt\"\r\n\
-Content-Type: text/plain\r\n\
-\r\n\
-{file_content}\r\n\
---{boundary}--\r\n",
-        boundary = boundary,
-        file_content = file_content
-    );
+    let outcome = Arc::new(std::sync::Mutex::new(None));
 
-    let exec = WebRequest::post()
-        .with_content_type(format!(
-            "multipart/form-data; boundary={}",
-            boundary
-        ))
-        .with_body(body.into_bytes())
-        .build(&script_path)
-        .expect("failed to build temp file upload WebRequest");
+    let exec = WebRequest::get()
+        .build(php_script_path("hello.php"))
+        .expect("failed to build WebRequest");
 
+    let seen = Arc::clone(&outcome);
     let result = php
-        .execute(exec)
-        .expect("temp file upload request execution failed");
+        .execute_streaming(exec, move |_| {
+            seen.lock()
+                .unwrap()
+                .get_or_insert_with(RiphtSapi::shutdown);
+        })
+        .expect("execution failed");
+
     assert_eq!(result.status_code(), 200);
+    assert!(matches!(
+        outcome.lock().unwrap().take(),
+        Some(Err(SapiError::RequestInProgress))
+    ));
+    assert!(php.is_initialized());
+}
 
-    let json: serde_json::Value = serde_json::from_slice(&result.body())
-        .expect("failed to parse response body as JSON");
+#[test]
+fn test_php_thread_executes_jobs_from_other_threads() {
+    let php = RiphtSapi::instance();
+    let engine = ResetEngine;
+    RiphtSapi::shutdown().expect("shutdown failed");
 
-    let upload_tmp_dir = json["upload_tmp_dir"]
-        .as_str()
-        .unwrap_or("");
+    let worker =
+        Arc::new(PhpThread::spawn().expect("failed to spawn PhpThread"));
 
-    let file_entry = &json["files"]["upload"];
-    assert!(
-        file_entry["tmp_name"].is_string(),
-        "Temp file name should be set in $_FILES"
-    );
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let worker = Arc::clone(&worker);
 
-    let tmp_name = file_entry["tmp_name"]
-        .as_str()
-        .expect("tmp_name field should be a string");
+            std::thread::spawn(move || {
+                let exec = WebRequest::get()
+                    .build(php_script_path("hello.php"))
+                    .expect("failed to build WebRequest");
 
-    if !upload_tmp_dir.is_empty() {
-        let normalized_tmp_dir = upload_tmp_dir.trim_end_matches('/');
-        let normalized_tmp_name = tmp_name.trim_end_matches('/');
-        let normalized_tmp_dir_alt = normalized_tmp_dir.replace("/private", "");
+                worker
+                    .execute(exec)
+                    .expect("execution failed")
+            })
+        })
+        .collect();
 
-        assert!(
-            normalized_tmp_name.starts_with(normalized_tmp_dir)
-                || normalized_tmp_name.starts_with(&normalized_tmp_dir_alt)
-                || normalized_tmp_name
-                    .replace("/private", "")
-                    .starts_with(&normalized_tmp_dir_alt),
-            "Temp file should be in upload_tmp_dir: {} (upload_tmp_dir: {})",
-            tmp_name,
-            upload_tmp_dir
-        );
-    } else {
-        assert!(
-            tmp_name.contains("/tmp/")
-                || tmp_name.contains("\\tmp\\")
-                || tmp_name.contains("/var/folders"),
-            "Temp file should be in a temp directory: {}",
-            tmp_name
+    for handle in handles {
+        assert!(handle
+            .join()
+            .unwrap()
+            .body_string()
+            .contains("Hello"));
+    }
+
+    assert!(matches!(
+        PhpThread::spawn(),
+        Err(SapiError::AlreadyInitialized)
+    ));
+
+    drop(worker);
+    assert_eq!(php.state(), EngineState::ShutDown);
+
+    drop(engine);
+    assert!(php.is_initialized());
+}
+
+#[cfg(php_zts)]
+#[test]
+fn test_executor_pool_runs_requests_concurrently() {
+    let pool = Arc::new(
+        ripht_php_sapi::ExecutorPool::new(4).expect("failed to start pool"),
+    );
+    assert_eq!(pool.threads(), 4);
+
+    let handles: Vec<_> = (0..16)
+        .map(|_| {
+            let pool = Arc::clone(&pool);
+
+            std::thread::spawn(move || {
+                let exec = WebRequest::get()
+                    .build(php_script_path("hello.php"))
+                    .expect("failed to build WebRequest");
+
+                pool.execute(exec)
+                    .expect("execution failed")
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        assert!(handle
+            .join()
+            .unwrap()
+            .body_string()
+            .contains("Hello"));
+    }
+}
+
+#[test]
+fn test_reused_executor_recycles_contexts() {
+    let php = RiphtSapi::instance();
+    let executor = php
+        .executor()
+        .expect("failed to create executor");
+
+    for i in 0..3 {
+        let exec = WebRequest::get()
+            .with_uri(format!("/get_params.php?n={i}"))
+            .build(php_script_path("get_params.php"))
+            .expect("failed to build WebRequest");
+
+        let result = executor
+            .execute(exec)
+            .expect("execution failed");
+
+        assert!(result
+            .body_string()
+            .contains(&format!("n={i}")));
+        assert_eq!(result.header_val("Content-Type"), Some("application/json"));
+    }
+
+    let stats = executor.pool_stats();
+    assert_eq!(stats.contexts_created, 1);
+    assert_eq!(stats.contexts_reused, 2);
+    assert!(stats.cstrings_reused > 0);
+}
+
+#[test]
+fn test_output_limit_truncates_or_aborts() {
+    let php = RiphtSapi::instance();
+    let limit = 10_000;
+
+    let build = |overflow| {
+        WebRequest::get()
+            .with_uri("/large_output.php?size=50000")
+            .build(php_script_path("large_output.php"))
+            .expect("failed to build WebRequest")
+            .buffer_policy(
+                BufferPolicy::new()
+                    .with_initial_capacity(4096)
+                    .with_max_size(limit)
+                    .with_overflow(overflow),
+            )
+    };
+
+    let result = php
+        .execute(build(OverflowAction::Truncate))
+        .expect("truncated execution failed");
+
+    assert!(result.is_truncated());
+    assert_eq!(result.body().len(), limit);
+
+    let err = php
+        .execute(build(OverflowAction::Abort))
+        .expect_err("output over the limit should abort");
+
+    assert!(matches!(
+        err,
+        ExecutionError::OutputLimitExceeded { limit: l } if l == limit
+    ));
+
+    let result = php
+        .execute(
+            WebRequest::get()
+                .build(php_script_path("hello.php"))
+                .expect("failed to build WebRequest"),
+        )
+        .expect("engine should serve requests after an abort");
+
+    assert!(!result.is_truncated());
+}
+
+#[test]
+fn test_status_line_keeps_reason_phrase() {
+    let php = RiphtSapi::instance();
+
+    let exec = WebRequest::get()
+        .build(php_script_path("status_line.php"))
+        .expect("failed to build WebRequest");
+
+    let result = php
+        .execute(exec)
+        .expect("execution failed");
+
+    assert_eq!(result.status_code(), 418);
+    assert_eq!(result.reason_phrase(), Some("I'm a teapot"));
+
+    let status_line = result
+        .status_line()
+        .expect("status line should be kept");
+    assert_eq!(status_line.version(), "HTTP/1.0");
+
+    #[cfg(feature = "http")]
+    {
+        use ripht_php_sapi::StatusLine;
+
+        let response: http::Response<Vec<u8>> = result.into();
+        assert_eq!(response.status(), 418);
+        assert_eq!(response.version(), http::Version::HTTP_10);
+        assert_eq!(
+            response
+                .extensions()
+                .get::<StatusLine>()
+                .map(StatusLine::reason),
+            Some("I'm a teapot")
         );
     }
+}
+
+#[test]
+fn test_status_line_dropped_when_code_changes() {
+    let php = RiphtSapi::instance();
+
+    let exec = WebRequest::get()
+        .with_uri("/status_line.php?override=200")
+        .build(php_script_path("status_line.php"))
+        .expect("failed to build WebRequest");
+
+    let result = php
+        .execute(exec)
+        .expect("execution failed");
+
+    assert_eq!(result.status_code(), 200);
+    assert!(result.status_line().is_none());
+}
+
+#[test]
+fn test_early_hints_are_kept_apart_from_final_response() {
+    struct Hints(Arc<std::sync::Mutex<Vec<u16>>>);
+
+    impl ExecutionHooks for Hints {
+        fn on_informational(&mut self, response: &InformationalResponse) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(response.status());
+        }
+    }
+
+    let php = RiphtSapi::instance();
+
+    let exec = WebRequest::get()
+        .with_uri("/early_hints.php")
+        .build(php_script_path("early_hints.php"))
+        .expect("failed to build WebRequest");
+
+    let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let result = php
+        .execute_with_hooks(exec, Hints(seen.clone()))
+        .expect("execution failed");
+
+    assert_eq!(*seen.lock().unwrap(), [103]);
+    assert_eq!(result.status_code(), 200);
+    assert_eq!(result.body_string(), "done");
+
+    let [early] = result.informational() else {
+        panic!("expected one informational response");
+    };
+    assert_eq!(early.status(), 103);
+    assert_eq!(
+        early
+            .header_values("Link")
+            .collect::<Vec<_>>(),
+        ["</style.css>; rel=preload; as=style"]
+    );
+}
+
+#[test]
+fn test_output_after_early_hints_is_kept() {
+    let php = RiphtSapi::instance();
+
+    let exec = WebRequest::get()
+        .build(php_script_path("early_hints_output.php"))
+        .expect("failed to build WebRequest");
+
+    let result = php
+        .execute(exec)
+        .expect("execution failed");
+
+    assert_eq!(result.status_code(), 200);
+    assert_eq!(result.body_string(), "body after hints");
+
+    let [early] = result.informational() else {
+        panic!("expected one informational response");
+    };
+    assert_eq!(early.status(), 103);
+    assert_eq!(
+        early
+            .header_values("Link")
+            .collect::<Vec<_>>(),
+        ["</app.js>; rel=preload; as=script"]
+    );
+}
+
+#[test]
+fn test_phar_runs_default_stub() {
+    let php = RiphtSapi::instance();
+    let archive = php_script_path("app.phar");
+
+    let web = php
+        .execute(
+            WebRequest::get()
+                .build(&archive)
+                .expect("failed to build WebRequest"),
+        )
+        .expect("web execution failed");
+
+    assert_eq!(web.body_string(), "stub: hello phar");
+
+    let cli = php
+        .execute(
+            CliRequest::new()
+                .build(&archive)
+                .expect("failed to build CliRequest"),
+        )
+        .expect("cli execution failed");
+
+    assert_eq!(cli.body_string(), "stub: hello phar");
+}
+
+#[test]
+fn test_phar_runs_internal_entry_point() {
+    let php = RiphtSapi::instance();
+    let entry = format!(
+        "phar://{}/public/index.php",
+        php_script_path("app.phar").display()
+    );
+
+    let exec = WebRequest::get()
+        .with_uri("/public/index.php?name=rust")
+        .build(&entry)
+        .expect("phar entry should be accepted");
+
+    let result = php
+        .execute(exec)
+        .expect("execution failed");
+
+    assert_eq!(result.status_code(), 200);
+    assert_eq!(result.body_string(), "entry: hello rust");
+
+    assert!(WebRequest::get()
+        .build(format!(
+            "phar://{}/public/index.php",
+            php_script_path("missing.phar").display()
+        ))
+        .is_err());
+}
+
+#[test]
+fn test_file_not_found() {
+    let req = WebRequest::get().build("/nonexistent/path.php");
+
+    assert!(req.is_err());
+}
+
+#[test]
+fn test_get_ini_display_errors() {
+    let php = RiphtSapi::instance();
+    let _ = php.set_ini("display_errors", "0");
+
+    assert_eq!(php.get_ini("display_errors"), Some("0".into()));
+}
+
+#[test]
+fn test_get_ini_nonexistent() {
+    let php = RiphtSapi::instance();
+
+    let value = php.get_ini("this_ini_key_does_not_exist_12345");
+    assert!(value.is_none(), "Non-existent INI should return None");
+}
+
+#[test]
+fn test_set_ini_and_get_ini() {
+    let php = RiphtSapi::instance();
+
+    let result = php.set_ini("memory_limit", "256M");
+    assert!(result.is_ok(), "set_ini should succeed for valid INI key");
+
+    let value = php.get_ini("memory_limit");
+    assert!(value.is_some(), "memory_limit should be readable after set");
+    assert_eq!(
+        value.unwrap(),
+        "256M",
+        "memory_limit should reflect the set value"
+    );
+}
+
+#[test]
+fn test_set_ini_invalid_key() {
+    let php = RiphtSapi::instance();
+
+    let result = php.set_ini("key\0with\0nulls", "value");
+    assert!(
+        result.is_err(),
+        "set_ini should fail for key with null bytes"
+    );
+}
+
+#[test]
+fn test_set_ini_invalid_value() {
+    let php = RiphtSapi::instance();
+
+    let result = php.set_ini("memory_limit", "value\0with\0nulls");
+    assert!(
+        result.is_err(),
+        "set_ini should fail for value with null bytes"
+    );
+}
+
+#[test]
+fn test_execution_error_script_not_found() {
+    let php = RiphtSapi::instance();
+
+    let ctx = ExecutionContext::script("/nonexistent/path/to/script.php");
+    let result = php.execute(ctx);
+
+    assert!(
+        result.is_err(),
+        "execute should fail for nonexistent script"
+    );
+    let err = result.unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("not found"),
+        "Error should mention script not found"
+    );
+}
+
+#[test]
+fn test_multipart_upload_basic() {
+    let php = RiphtSapi::instance();
+    let script_path = php_script_path("file_upload.php");
+
+    let boundary = "boundary123";
+    let body = format!(
+        "--{boundary}\r\n\
+Content-Disposition: form-data; name=\"field\"\r\n\r\n\
+test_value\r\n\
+--{boundary}--\r\n",
+        boundary = boundary
+    );
+
+    let exec = WebRequest::post()
+        .with_content_type(format!(
+            "multipart/form-data; boundary={}",
+            boundary
+        ))
+        .with_body(body.into_bytes())
+        .build(&script_path)
+        .expect("failed to build multipart WebRequest");
+
+    let result = php
+        .execute(exec)
+        .expect("multipart POST request execution failed");
+    assert_eq!(result.status_code(), 200);
+
+    let body_str = result.body_string();
+    let json: serde_json::Value = serde_json::from_str(&body_str)
+        .unwrap_or_else(|_| {
+            panic!("failed to parse JSON response: {}", body_str)
+        });
+
+    assert_eq!(
+        json["post_data"]["field"], "test_value",
+        "POST field should be 'test_value': {}",
+        body_str
+    );
+}
+
+#[test]
+fn test_multipart_upload_with_file() {
+    let php = RiphtSapi::instance();
+    let script_path = php_script_path("file_upload.php");
+
+    let boundary = {
+        use std::time::{SystemTime, UNIX_EPOCH};
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        format!("----multipart-form-boundary-{:x}", timestamp)
+    };
+    let file_content = "Hello, this is test file content!";
+    let body = format!(
+        "--{boundary}\r\n\
+Content-Disposition: form-data; name=\"myfile\"; filename=\"test.txt\"\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+{file_content}\r\n\
+--{boundary}\r\n\
+Content-Disposition: form-data; name=\"description\"\r\n\
+\r\n\
+A test file\r\n\
+--{boundary}--\r\n",
+        boundary = boundary,
+        file_content = file_content
+    );
+
+    let exec = WebRequest::post()
+        .with_content_type(format!(
+            "multipart/form-data; boundary={}",
+            boundary
+        ))
+        .with_body(body.into_bytes())
+        .build(&script_path)
+        .expect("failed to build file upload WebRequest");
+
+    let result = php
+        .execute(exec)
+        .expect("file upload request execution failed");
+    assert_eq!(result.status_code(), 200);
+
+    let json: serde_json::Value = serde_json::from_slice(&result.body())
+        .expect("failed to parse response body as JSON");
+
+    assert_eq!(
+        json["post_data"]["description"], "A test file",
+        "POST field 'description' should be set"
+    );
+
+    assert!(
+        json["files"]["myfile"].is_object(),
+        "FILES should contain 'myfile' entry"
+    );
+
+    let file_entry = &json["files"]["myfile"];
+    assert_eq!(file_entry["name"], "test.txt");
+    assert_eq!(file_entry["error"], 0);
+
+    assert_eq!(file_entry["tmp_exists"], true, "Temp file should exist");
+    assert_eq!(
+        file_entry["tmp_readable"], true,
+        "Temp file should be readable"
+    );
+
+    assert_eq!(
+        file_entry["tmp_content"], file_content,
+        "Temp file content should match uploaded content"
+    );
+    assert_eq!(
+        file_entry["tmp_content_length"],
+        file_content.len(),
+        "Temp file size should match uploaded content length"
+    );
+}
+
+#[test]
+fn test_multipart_upload_temp_file_creation() {
+    let php = RiphtSapi::instance();
+    let script_path = php_script_path("file_upload.php");
+
+    let boundary = "boundary456";
+    let file_content = "Test file content for temp file verification";
+    let body = format!(
+        "--{boundary}\r\n\
+Content-Disposition: form-data; name=\"upload\"; filename=\"verify.txt\"\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+{file_content}\r\n\
+--{boundary}--\r\n",
+        boundary = boundary,
+        file_content = file_content
+    );
+
+    let exec = WebRequest::post()
+        .with_content_type(format!(
+            "multipart/form-data; boundary={}",
+            boundary
+        ))
+        .with_body(body.into_bytes())
+        .build(&script_path)
+        .expect("failed to build temp file upload WebRequest");
+
+    let result = php
+        .execute(exec)
+        .expect("temp file upload request execution failed");
+    assert_eq!(result.status_code(), 200);
+
+    let json: serde_json::Value = serde_json::from_slice(&result.body())
+        .expect("failed to parse response body as JSON");
+
+    let upload_tmp_dir = json["upload_tmp_dir"]
+        .as_str()
+        .unwrap_or("");
+
+    let file_entry = &json["files"]["upload"];
+    assert!(
+        file_entry["tmp_name"].is_string(),
+        "Temp file name should be set in $_FILES"
+    );
+
+    let tmp_name = file_entry["tmp_name"]
+        .as_str()
+        .expect("tmp_name field should be a string");
+
+    if !upload_tmp_dir.is_empty() {
+        let normalized_tmp_dir = upload_tmp_dir.trim_end_matches('/');
+        let normalized_tmp_name = tmp_name.trim_end_matches('/');
+        let normalized_tmp_dir_alt = normalized_tmp_dir.replace("/private", "");
+
+        assert!(
+            normalized_tmp_name.starts_with(normalized_tmp_dir)
+                || normalized_tmp_name.starts_with(&normalized_tmp_dir_alt)
+                || normalized_tmp_name
+                    .replace("/private", "")
+                    .starts_with(&normalized_tmp_dir_alt),
+            "Temp file should be in upload_tmp_dir: {} (upload_tmp_dir: {})",
+            tmp_name,
+            upload_tmp_dir
+        );
+    } else {
+        assert!(
+            tmp_name.contains("/tmp/")
+                || tmp_name.contains("\\tmp\\")
+                || tmp_name.contains("/var/folders"),
+            "Temp file should be in a temp directory: {}",
+            tmp_name
+        );
+    }
+
+    assert_eq!(
+        file_entry["tmp_content"], file_content,
+        "Temp file content should match uploaded content"
+    );
+}
+
+#[test]
+fn test_session_basic() {
+    let php = RiphtSapi::instance();
+    let script_path = php_script_path("session.php");
+
+    let exec1 = WebRequest::get()
+        .build(&script_path)
+        .expect("failed to build first session WebRequest");
+    let result1 = php
+        .execute(exec1)
+        .expect("first session request execution failed");
+    assert_eq!(result1.status_code(), 200);
+
+    let body1 = result1.body_string();
+    assert!(
+        body1.contains("session_id"),
+        "Response should contain session_id: {}",
+        body1
+    );
+    assert!(
+        body1.contains("\"visit_count\":1")
+            || body1.contains("\"visit_count\": 1"),
+        "First request should have visit_count=1: {}",
+        body1
+    );
+
+    let session_cookie = result1
+        .all_headers()
+        .find(|h| {
+            h.name()
+                .eq_ignore_ascii_case("Set-Cookie")
+        })
+        .and_then(|h| {
+            if h.value()
+                .starts_with("PHPSESSID=")
+            {
+                h.value()
+                    .split(';')
+                    .next()
+                    .map(|s| s.to_string())
+            } else {
+                None
+            }
+        });
+
+    if let Some(cookie_val) = session_cookie {
+        let exec2 = WebRequest::get()
+            .with_raw_cookie_header(&cookie_val)
+            .build(&script_path)
+            .expect("failed to build second session WebRequest");
+        let result2 = php
+            .execute(exec2)
+            .expect("second session request execution failed");
+
+        let body2 = result2.body_string();
+        assert!(
+            body2.contains("\"visit_count\":2")
+                || body2.contains("\"visit_count\": 2"),
+            "Second request should have visit_count=2: {}",
+            body2
+        );
+    }
+}
+
+#[test]
+fn test_head_request_method() {
+    let php = RiphtSapi::instance();
+    let script_path = php_script_path("hello.php");
+
+    let exec = WebRequest::head()
+        .build(&script_path)
+        .expect("failed to build HEAD WebRequest");
+    let result = php
+        .execute(exec)
+        .expect("HEAD request execution failed");
+
+    assert_eq!(result.status_code(), 200);
+}
+
+#[test]
+fn test_options_request_method() {
+    let php = RiphtSapi::instance();
+    let script_path = php_script_path("hello.php");
+
+    let exec = WebRequest::options()
+        .build(&script_path)
+        .expect("failed to build OPTIONS WebRequest");
+    let result = php
+        .execute(exec)
+        .expect("OPTIONS request execution failed");
+    assert_eq!(result.status_code(), 200);
+}
+
+#[test]
+fn test_streaming_sse_output() {
+    let php = RiphtSapi::instance();
+    let script_path = php_script_path("streaming.php");
+
+    let chunks = Arc::new(std::sync::Mutex::new(Vec::<Vec<u8>>::new()));
+    let chunks_clone = Arc::clone(&chunks);
+
+    let exec = WebRequest::get()
+        .build(&script_path)
+        .expect("failed to build streaming WebRequest");
+    let result = php
+        .execute_streaming(exec, move |chunk| {
+            chunks_clone
+                .lock()
+                .unwrap()
+                .push(chunk.to_vec());
+        })
+        .expect("SSE streaming request execution failed");
+
+    assert_eq!(result.status_code(), 200);
+
+    let received_chunks = chunks.lock().unwrap();
+    assert!(
+        received_chunks.len() > 1,
+        "Should receive multiple chunks, got {}",
+        received_chunks.len()
+    );
+
+    assert!(
+        result.body().is_empty(),
+        "Response body should be empty when streaming (data sent to callback)"
+    );
+
+    let combined: Vec<u8> = received_chunks
+        .iter()
+        .flat_map(|c| c.iter().copied())
+        .collect();
+    let combined_str = String::from_utf8_lossy(&combined);
+    assert!(
+        combined_str.contains("Chunk 1"),
+        "Streamed output should contain Chunk 1"
+    );
+    assert!(
+        combined_str.contains("Chunk 5"),
+        "Streamed output should contain Chunk 5"
+    );
+    assert!(
+        combined_str.contains("[DONE]"),
+        "Streamed output should contain [DONE]"
+    );
+}
+
+#[test]
+fn test_streaming_large_output() {
+    let php = RiphtSapi::instance();
+    let script_path = php_script_path("large_output.php");
+
+    let chunks = Arc::new(std::sync::Mutex::new(Vec::<Vec<u8>>::new()));
+    let chunks_clone = Arc::clone(&chunks);
+
+    let exec = WebRequest::get()
+        .build(&script_path)
+        .expect("failed to build large output streaming WebRequest");
+    let result = php
+        .execute_streaming(exec, move |chunk| {
+            chunks_clone
+                .lock()
+                .unwrap()
+                .push(chunk.to_vec());
+        })
+        .expect("large output streaming request execution failed");
+
+    assert_eq!(result.status_code(), 200);
+
+    assert!(
+        result.body().is_empty(),
+        "Response body should be empty when streaming"
+    );
+
+    let received_chunks = chunks.lock().unwrap();
+    assert!(
+        !received_chunks.is_empty(),
+        "Should receive at least one chunk"
+    );
+
+    let callback_total: usize = received_chunks
+        .iter()
+        .map(|c| c.len())
+        .sum();
+    assert!(
+        callback_total >= 1024 * 1024,
+        "Should receive 1MB+ via callback, got {} bytes",
+        callback_total
+    );
+
+    drop(received_chunks);
+
+    let exec2 = WebRequest::get()
+        .build(&script_path)
+        .expect("failed to build non-streaming WebRequest");
+    let result2 = php
+        .execute(exec2)
+        .expect("non-streaming large output request execution failed");
+    assert!(
+        result2.body().len() >= 1024 * 1024,
+        "Non-streaming should buffer the full output: {} bytes",
+        result2.body().len()
+    );
+}
+
+// Tests for internal SAPI state (post_read flag, server_context cleanup)
+// have been moved to src/sapi/callbacks.rs unit tests since they require
+// access to internal FFI types.
+
+#[test]
+fn test_header_edge_cases_duplicate_set_cookie_headers() {
+    let php = RiphtSapi::instance();
+    let script_path = php_script_path("header_edge_cases.php");
+
+    let exec = WebRequest::get()
+        .with_uri("/header_edge_cases.php?test=duplicate")
+        .build(&script_path)
+        .expect("failed to build header edge cases WebRequest");
+
+    let result = php
+        .execute(exec)
+        .expect("header edge cases (duplicate) request execution failed");
+
+    let set_cookies = result.header_vals("Set-Cookie");
+    assert_eq!(
+        set_cookies.len(),
+        3,
+        "Expected 3 Set-Cookie headers, got {:?}",
+        set_cookies
+    );
+
+    assert!(set_cookies
+        .iter()
+        .any(|v| v.contains("a=1")));
+    assert!(set_cookies
+        .iter()
+        .any(|v| v.contains("b=2")));
+    assert!(set_cookies
+        .iter()
+        .any(|v| v.contains("c=3")));
+}
+
+#[test]
+fn test_header_edge_cases_header_remove() {
+    let php = RiphtSapi::instance();
+    let script_path = php_script_path("header_edge_cases.php");
+
+    let exec = WebRequest::get()
+        .with_uri("/header_edge_cases.php?test=remove")
+        .build(&script_path)
+        .expect("failed to build header remove WebRequest");
+
+    let result = php
+        .execute(exec)
+        .expect("header edge cases (remove) request execution failed");
+
+    assert!(
+        result
+            .header_val("X-To-Remove")
+            .is_none(),
+        "X-To-Remove should not be present after header_remove()"
+    );
+
+    let kept = result
+        .header_val("X-Kept")
+        .map(|v| v.contains("still here"))
+        .unwrap_or(false);
+    assert!(kept, "X-Kept should be present");
+}
+
+#[test]
+fn test_status_codes_and_redirect_location_header() {
+    let php = RiphtSapi::instance();
+
+    let status_script = php_script_path("status_codes.php");
+    let exec_201 = WebRequest::get()
+        .with_uri("/status_codes.php?code=201&method=code")
+        .build(&status_script)
+        .expect("failed to build status 201 WebRequest");
+
+    let result_201 = php
+        .execute(exec_201)
+        .expect("status_codes.php (201) request execution failed");
+    assert_eq!(result_201.status_code(), 201);
+
+    let exec_307 = WebRequest::get()
+        .with_uri("/status_codes.php?code=307&method=header")
+        .build(&status_script)
+        .expect("failed to build status 307 WebRequest");
+
+    let result_307 = php
+        .execute(exec_307)
+        .expect("status_codes.php (307) request execution failed");
+    assert_eq!(result_307.status_code(), 307);
+
+    let redirect_script = php_script_path("redirect_handling.php");
+    let exec_redirect = WebRequest::get()
+        .with_uri("/redirect_handling.php?type=301")
+        .build(&redirect_script)
+        .expect("failed to build redirect WebRequest");
+
+    let result_redirect = php
+        .execute(exec_redirect)
+        .expect("redirect_handling.php request execution failed");
+
+    assert_eq!(result_redirect.status_code(), 301);
+
+    let location = result_redirect
+        .header_val("Location")
+        .expect("redirect response missing Location header");
+    assert!(
+        location.contains("/redirected.php"),
+        "Expected Location to contain /redirected.php, got: {}",
+        location
+    );
+}
+
+#[test]
+fn test_binary_output_byte_integrity() {
+    let php = RiphtSapi::instance();
+    let script_path = php_script_path("binary_output.php");
+
+    let exec = WebRequest::get()
+        .build(&script_path)
+        .expect("failed to build binary output WebRequest");
+
+    let result = php
+        .execute(exec)
+        .expect("binary output request execution failed");
+
+    assert_eq!(result.body().len(), 256, "Expected 256 bytes");
+    assert_eq!(result.body()[0], 0);
+    assert_eq!(result.body()[1], 1);
+    assert_eq!(result.body()[255], 255);
+}
+
+#[test]
+fn test_webrequest_shaping_via_superglobals() {
+    let php = RiphtSapi::instance();
+    let script_path = php_script_path("superglobals.php");
+
+    let body = b"raw-body-123".to_vec();
+    let path_info = "/extra/path";
+    let document_root = std::env::temp_dir().join("ripht_sapi_docroot");
+
+    let exec = WebRequest::post()
+        .with_uri("/superglobals.php?alpha=1")
+        .with_header("X-Foo-Bar", "baz")
+        .with_https(true)
+        .with_document_root(&document_root)
+        .with_path_info(path_info)
+        // Intentionally omit Content-Type + Content-Length to exercise defaults
+        .with_body(body.clone())
+        .build(&script_path)
+        .expect("failed to build superglobals WebRequest");
+
+    let result = php
+        .execute(exec)
+        .expect("superglobals.php request execution failed");
+
+    let json: serde_json::Value = serde_json::from_slice(&result.body())
+        .expect("failed to parse superglobals response as JSON");
+
+    // Header mapping: X-Foo-Bar => HTTP_X_FOO_BAR => appears as X_FOO_BAR in HTTP_HEADERS
+    assert_eq!(json["HTTP_HEADERS"]["X_FOO_BAR"], "baz");
+
+    // HTTPS shaping
+    assert_eq!(json["SERVER"]["REQUEST_SCHEME"], "https");
+    assert_eq!(json["SERVER"]["HTTPS"], "on");
+
+    // PATH_INFO / PATH_TRANSLATED shaping
+    assert_eq!(json["SERVER"]["PATH_INFO"], path_info);
+    let expected_translated =
+        format!("{}{}", document_root.to_string_lossy(), path_info);
+    assert_eq!(json["SERVER"]["PATH_TRANSLATED"], expected_translated);
+
+    // Default Content-Type / Content-Length behavior when body is present
+    assert_eq!(json["SERVER"]["CONTENT_TYPE"], "application/octet-stream");
+    assert_eq!(json["SERVER"]["CONTENT_LENGTH"], body.len().to_string());
+}
+
+#[test]
+fn test_hook_tuple_combines_header_policy_and_timing() {
+    let php = RiphtSapi::instance();
+
+    let timing = TimingRecorder::new();
+    let policy = HeaderPolicy::new().with_denied_header("x-another-header");
+
+    let exec = WebRequest::get()
+        .build(php_script_path("headers.php"))
+        .expect("failed to build WebRequest");
+
+    let result = php
+        .execute_with_hooks(exec, (timing.clone(), policy.clone()))
+        .expect("execute_with_hooks() failed");
+
+    assert_eq!(result.header_val("X-Custom-Header"), Some("test-value"));
+    assert!(result
+        .header_val("X-Another-Header")
+        .is_none());
+    assert_eq!(policy.rejected().len(), 1);
+
+    let timings = timing
+        .last()
+        .expect("timings should be recorded");
+    assert!(timings.total >= timings.script);
+}
+
+#[test]
+fn test_request_hook_can_mutate_or_short_circuit() {
+    let php = RiphtSapi::instance();
+
+    struct Maintenance {
+        enabled: bool,
+    }
+
+    impl ExecutionHooks for Maintenance {
+        fn on_request_received(
+            &mut self,
+            ctx: &mut ExecutionContext,
+        ) -> RequestAction {
+            if !self.enabled {
+                ctx.env_vars
+                    .push(("TEST_ENV_KEY".into(), "from-hook".into()));
+                return RequestAction::Continue;
+            }
+
+            RequestAction::Respond(ExecutionResult::new(
+                503,
+                b"maintenance".to_vec(),
+                vec![ResponseHeader::new("Retry-After", "120")],
+                Vec::new(),
+            ))
+        }
+    }
+
+    let build = || {
+        WebRequest::get()
+            .build(php_script_path("env_vars.php"))
+            .expect("failed to build WebRequest")
+    };
+
+    let result = php
+        .execute_with_hooks(build(), Maintenance { enabled: true })
+        .expect("short-circuited request failed");
+
+    assert_eq!(result.status_code(), 503);
+    assert_eq!(result.body_string(), "maintenance");
+    assert_eq!(result.header_val("Retry-After"), Some("120"));
+
+    let result = php
+        .execute_with_hooks(build(), Maintenance { enabled: false })
+        .expect("mutated request failed");
+
+    let json: serde_json::Value =
+        serde_json::from_str(&result.body_string()).expect("invalid JSON");
+    assert_eq!(json["TEST_ENV_KEY"], "from-hook");
+}
+
+struct RewriteHead;
+
+impl ExecutionHooks for RewriteHead {
+    fn on_response_head(&mut self, head: &mut ResponseHead) {
+        if let Some(location) = head.header("Location") {
+            let location = format!("https://example.com{location}");
+            head.set_header("Location", location);
+        }
+
+        if head.status() == 302 {
+            head.set_status(303);
+        }
+
+        head.append_header("X-Frame-Options", "DENY");
+    }
+}
+
+#[test]
+fn test_response_head_hook_rewrites_headers_and_status() {
+    let php = RiphtSapi::instance();
+
+    let exec = WebRequest::get()
+        .with_uri("/redirect_handling.php?type=302")
+        .build(php_script_path("redirect_handling.php"))
+        .expect("failed to build WebRequest");
 
+    let result = php
+        .execute_with_hooks(exec, RewriteHead)
+        .expect("execute_with_hooks() failed");
+
+    assert_eq!(result.status_code(), 303);
     assert_eq!(
-        file_entry["tmp_content"], file_content,
-        "Temp file content should match uploaded content"
+        result.header_val("Location"),
+        Some("https://example.com/temporary.php")
     );
+    assert_eq!(result.header_val("X-Frame-Options"), Some("DENY"));
 }
 
 #[test]
-fn test_session_basic() {
+fn test_streamed_response_gets_rewritten_head_first() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
     let php = RiphtSapi::instance();
-    let script_path = php_script_path("session.php");
+    let events = Rc::new(RefCell::new(Vec::<String>::new()));
 
-    let exec1 = WebRequest::get()
+    struct RecordHead(Rc<RefCell<Vec<String>>>);
+
+    impl ExecutionHooks for RecordHead {
+        fn on_response_head(&mut self, head: &mut ResponseHead) {
+            head.set_header("Content-Type", "text/event-stream");
+            self.0
+                .borrow_mut()
+                .push("head".into());
+        }
+    }
+
+    let exec = WebRequest::get()
+        .build(php_script_path("streaming.php"))
+        .expect("failed to build WebRequest");
+
+    let chunks = Rc::clone(&events);
+    let result = php
+        .execute_streaming_with_hooks(
+            exec,
+            move |_chunk: &[u8]| {
+                chunks
+                    .borrow_mut()
+                    .push("chunk".into());
+            },
+            RecordHead(Rc::clone(&events)),
+        )
+        .expect("streaming execution failed");
+
+    let events = events.borrow();
+    assert_eq!(
+        events
+            .first()
+            .map(String::as_str),
+        Some("head")
+    );
+    assert!(events.len() > 1);
+    assert_eq!(result.header_val("Content-Type"), Some("text/event-stream"));
+}
+
+#[test]
+fn test_execute_with_hooks_can_filter_headers_and_handle_output() {
+    let php = RiphtSapi::instance();
+    let script_path = php_script_path("headers.php");
+
+    struct FilterAndCaptureHooks {
+        captured: Arc<std::sync::Mutex<Vec<u8>>>,
+    }
+
+    impl ExecutionHooks for FilterAndCaptureHooks {
+        fn on_header(&mut self, name: &str, _value: &str) -> bool {
+            !name.eq_ignore_ascii_case("X-Another-Header")
+        }
+
+        fn on_output(&mut self, data: &[u8]) -> OutputAction {
+            self.captured
+                .lock()
+                .unwrap()
+                .extend_from_slice(data);
+            OutputAction::Done
+        }
+    }
+
+    let captured = Arc::new(std::sync::Mutex::new(Vec::<u8>::new()));
+
+    let exec = WebRequest::get()
         .build(&script_path)
-        .expect("failed to build first session WebRequest");
-    let result1 = php
-        .execute(exec1)
-        .expect("first session request execution failed");
-    assert_eq!(result1.status_code(), 200);
+        .expect("failed to build hooks test WebRequest");
+
+    let result = php
+        .execute_with_hooks(
+            exec,
+            FilterAndCaptureHooks {
+                captured: Arc::clone(&captured),
+            },
+        )
+        .expect("execute_with_hooks() failed");
 
-    let body1 = result1.body_string();
     assert!(
-        body1.contains("session_id"),
-        "Response should contain session_id: {}",
-        body1
+        result.body().is_empty(),
+        "Body should be empty when hooks handle output"
     );
+
+    assert!(result
+        .header_val("X-Custom-Header")
+        .is_some());
     assert!(
-        body1.contains("\"visit_count\":1")
-            || body1.contains("\"visit_count\": 1"),
-        "First request should have visit_count=1: {}",
-        body1
+        result
+            .header_val("X-Another-Header")
+            .is_none(),
+        "X-Another-Header should be filtered out by hooks"
     );
 
-    let session_cookie = result1
-        .all_headers()
-        .find(|h| {
-            h.name()
-                .eq_ignore_ascii_case("Set-Cookie")
-        })
-        .and_then(|h| {
-            if h.value()
-                .starts_with("PHPSESSID=")
-            {
-                h.value()
-                    .split(';')
-                    .next()
-                    .map(|s| s.to_string())
-            } else {
-                None
-            }
-        });
+    let captured_bytes = captured
+        .lock()
+        .unwrap()
+        .clone();
+    assert!(!captured_bytes.is_empty(), "Expected captured output");
 
-    if let Some(cookie_val) = session_cookie {
-        let exec2 = WebRequest::get()
-            .with_raw_cookie_header(&cookie_val)
-            .build(&script_path)
-            .expect("failed to build second session WebRequest");
-        let result2 = php
-            .execute(exec2)
-            .expect("second session request execution failed");
+    let captured_json: serde_json::Value =
+        serde_json::from_slice(&captured_bytes)
+            .expect("captured output should be valid JSON");
+    assert_eq!(captured_json["method"], "GET");
+}
+
+#[test]
+fn test_env_vars_visible_via_getenv() {
+    let php = RiphtSapi::instance();
+    let script_path = php_script_path("env_vars.php");
+
+    let exec = WebRequest::get()
+        .with_env("TEST_ENV_KEY", "hello-env")
+        .build(&script_path)
+        .expect("failed to build env vars WebRequest");
+
+    let result = php
+        .execute(exec)
+        .expect("env_vars.php request execution failed");
+
+    let json: serde_json::Value = serde_json::from_slice(&result.body())
+        .expect("failed to parse env vars response as JSON");
+
+    assert_eq!(json["TEST_ENV_KEY"], "hello-env");
+    assert!(json["MISSING_ENV_KEY"].is_null());
+}
+
+#[test]
+fn test_request_scoped_ini_overrides_apply_and_do_not_leak() {
+    let php = RiphtSapi::instance();
+    let script_path = php_script_path("ini_overrides.php");
+
+    let base_exec = WebRequest::get()
+        .build(&script_path)
+        .expect("failed to build baseline INI WebRequest");
+    let base_result = php
+        .execute(base_exec)
+        .expect("ini_overrides.php baseline request execution failed");
+
+    let base_json: serde_json::Value =
+        serde_json::from_slice(&base_result.body())
+            .expect("failed to parse baseline INI response as JSON");
+
+    let base_display_errors = base_json["display_errors"]
+        .as_str()
+        .unwrap_or("")
+        .to_string();
+
+    // Flip the value to exercise request-scoped ini overrides.
+    let override_value = if base_display_errors.is_empty() {
+        "1"
+    } else {
+        "0"
+    };
+
+    let exec = WebRequest::get()
+        .with_ini("display_errors", override_value)
+        .build(&script_path)
+        .expect("failed to build INI override WebRequest");
+
+    let result = php
+        .execute(exec)
+        .expect("ini_overrides.php override request execution failed");
 
-        let body2 = result2.body_string();
+    let json: serde_json::Value = serde_json::from_slice(&result.body())
+        .expect("failed to parse INI override response as JSON");
+
+    let got = json["display_errors"]
+        .as_str()
+        .unwrap_or("");
+    if override_value == "0" {
+        // For boolean directives PHP may report off as an empty string.
         assert!(
-            body2.contains("\"visit_count\":2")
-                || body2.contains("\"visit_count\": 2"),
-            "Second request should have visit_count=2: {}",
-            body2
+            got.is_empty() || got == "0",
+            "Expected display_errors to be off, got: {:?}",
+            got
         );
+    } else {
+        assert_eq!(got, "1");
     }
+
+    // Verify no leak into subsequent requests.
+    let after_exec = WebRequest::get()
+        .build(&script_path)
+        .expect("failed to build follow-up INI WebRequest");
+    let after_result = php
+        .execute(after_exec)
+        .expect("ini_overrides.php follow-up request execution failed");
+
+    let after_json: serde_json::Value =
+        serde_json::from_slice(&after_result.body())
+            .expect("failed to parse follow-up INI response as JSON");
+
+    let after_display_errors = after_json["display_errors"]
+        .as_str()
+        .unwrap_or("");
+    assert_eq!(
+        after_display_errors, base_display_errors,
+        "display_errors should not leak across requests"
+    );
 }
 
 #[test]
-fn test_head_request_method() {
+fn test_security_policy_blocks_and_reports() {
     let php = RiphtSapi::instance();
-    let script_path = php_script_path("hello.php");
+    let script_path = php_script_path("security_policy.php");
+    let scripts_dir = script_path
+        .parent()
+        .unwrap()
+        .to_path_buf();
 
-    let exec = WebRequest::head()
+    let policy = SecurityPolicy::new()
+        .with_open_basedir(&scripts_dir)
+        .with_disabled_function("php_uname")
+        .with_disabled_class("ReflectionClass")
+        .with_disabled_class("SplObjectStorage")
+        .with_allow_url_fopen(false)
+        .with_max_upload_size(1024 * 1024);
+
+    let exec = WebRequest::get()
         .build(&script_path)
-        .expect("failed to build HEAD WebRequest");
+        .expect("failed to build WebRequest")
+        .security_policy(policy);
     let result = php
         .execute(exec)
-        .expect("HEAD request execution failed");
+        .expect("security_policy.php execution failed");
 
-    assert_eq!(result.status_code(), 200);
+    let json: serde_json::Value = serde_json::from_slice(&result.body())
+        .expect("failed to parse security policy response as JSON");
+    assert_eq!(json["passwd"], false);
+    assert_eq!(
+        json["uname"],
+        "php_uname() has been disabled by the security policy"
+    );
+    assert_eq!(
+        json["reflection"],
+        "Class ReflectionClass has been disabled by the security policy"
+    );
+    // No constructor to intercept; blocked at instantiation.
+    assert_eq!(
+        json["storage"],
+        "Class SplObjectStorage has been disabled by the security policy"
+    );
+    assert_eq!(json["storage_subclass"], json["storage"]);
+    assert_eq!(json["allow_url_fopen"], "0");
+    assert_eq!(json["upload_max_filesize"], "1048576");
+
+    let violations = result.security_violations();
+    assert!(violations.contains(&SecurityViolation::DisabledFunction {
+        name: "php_uname".to_string()
+    }));
+    assert!(violations.contains(&SecurityViolation::DisabledClass {
+        name: "ReflectionClass".to_string()
+    }));
+    assert!(violations.contains(&SecurityViolation::OpenBasedir {
+        path: "/etc/passwd".to_string()
+    }));
+
+    // Nothing carries over to a request without a policy.
+    let after = php
+        .execute(
+            WebRequest::get()
+                .build(&script_path)
+                .expect("failed to build follow-up WebRequest"),
+        )
+        .expect("follow-up execution failed");
+    let after_json: serde_json::Value = serde_json::from_slice(&after.body())
+        .expect("failed to parse follow-up response as JSON");
+
+    assert_eq!(after_json["reflection"], "allowed");
+    assert_eq!(after_json["storage"], "allowed");
+    assert_eq!(after_json["storage_subclass"], "allowed");
+    assert_ne!(after_json["upload_max_filesize"], "1048576");
+    assert!(after
+        .security_violations()
+        .is_empty());
 }
 
 #[test]
-fn test_options_request_method() {
+fn test_request_policy_covers_previously_compiled_script() {
     let php = RiphtSapi::instance();
-    let script_path = php_script_path("hello.php");
+    let script_path = php_script_path("security_policy.php");
+    let request = || {
+        WebRequest::get()
+            .build(&script_path)
+            .expect("failed to build WebRequest")
+    };
+
+    // Compiled (and cached, with opcache) before any policy disables calls.
+    let before = php
+        .execute(request())
+        .expect("unrestricted execution failed");
+    assert!(before
+        .security_violations()
+        .is_empty());
 
-    let exec = WebRequest::options()
-        .build(&script_path)
-        .expect("failed to build OPTIONS WebRequest");
     let result = php
-        .execute(exec)
-        .expect("OPTIONS request execution failed");
-    assert_eq!(result.status_code(), 200);
+        .execute(request().security_policy(
+            SecurityPolicy::new().with_disabled_function("php_uname"),
+        ))
+        .expect("restricted execution failed");
+
+    let json: serde_json::Value = serde_json::from_slice(&result.body())
+        .expect("failed to parse security policy response as JSON");
+    assert_eq!(
+        json["uname"],
+        "php_uname() has been disabled by the security policy"
+    );
 }
 
 #[test]
-fn test_streaming_sse_output() {
+fn test_policy_disabling_uninterceptable_function_is_rejected() {
     let php = RiphtSapi::instance();
-    let script_path = php_script_path("streaming.php");
-
-    let chunks = Arc::new(std::sync::Mutex::new(Vec::<Vec<u8>>::new()));
-    let chunks_clone = Arc::clone(&chunks);
-
     let exec = WebRequest::get()
-        .build(&script_path)
-        .expect("failed to build streaming WebRequest");
-    let result = php
-        .execute_streaming(exec, move |chunk| {
-            chunks_clone
-                .lock()
-                .unwrap()
-                .push(chunk.to_vec());
-        })
-        .expect("SSE streaming request execution failed");
+        .build(php_script_path("hello.php"))
+        .expect("failed to build WebRequest")
+        .security_policy(
+            SecurityPolicy::new().with_disabled_functions(["exec", "in_array"]),
+        );
 
-    assert_eq!(result.status_code(), 200);
+    let err = php
+        .execute(exec)
+        .expect_err("in_array() can't be intercepted");
 
-    let received_chunks = chunks.lock().unwrap();
     assert!(
-        received_chunks.len() > 1,
-        "Should receive multiple chunks, got {}",
-        received_chunks.len()
+        matches!(&err, ExecutionError::UnenforceablePolicy(reason) if reason.contains("in_array")),
+        "unexpected error: {err:?}"
     );
 
-    assert!(
-        result.body().is_empty(),
-        "Response body should be empty when streaming (data sent to callback)"
-    );
+    let err = php
+        .restart_with(RiphtSapi::builder().with_security_policy(
+            SecurityPolicy::new().with_disabled_function("strlen"),
+        ))
+        .expect_err("strlen() can't be intercepted");
 
-    let combined: Vec<u8> = received_chunks
-        .iter()
-        .flat_map(|c| c.iter().copied())
-        .collect();
-    let combined_str = String::from_utf8_lossy(&combined);
-    assert!(
-        combined_str.contains("Chunk 1"),
-        "Streamed output should contain Chunk 1"
-    );
-    assert!(
-        combined_str.contains("Chunk 5"),
-        "Streamed output should contain Chunk 5"
-    );
-    assert!(
-        combined_str.contains("[DONE]"),
-        "Streamed output should contain [DONE]"
-    );
+    assert!(matches!(err, SapiError::UnenforceablePolicy(_)));
+    assert!(php.is_initialized());
 }
 
 #[test]
-fn test_streaming_large_output() {
+fn test_memory_usage_and_limit() {
     let php = RiphtSapi::instance();
-    let script_path = php_script_path("large_output.php");
-
-    let chunks = Arc::new(std::sync::Mutex::new(Vec::<Vec<u8>>::new()));
-    let chunks_clone = Arc::clone(&chunks);
+    let script_path = php_script_path("memory_intensive.php");
+    let request = |query: &str| {
+        WebRequest::get()
+            .with_uri(format!("/?{query}"))
+            .build(&script_path)
+            .expect("failed to build WebRequest")
+    };
 
-    let exec = WebRequest::get()
-        .build(&script_path)
-        .expect("failed to build large output streaming WebRequest");
     let result = php
-        .execute_streaming(exec, move |chunk| {
-            chunks_clone
-                .lock()
-                .unwrap()
-                .push(chunk.to_vec());
-        })
-        .expect("large output streaming request execution failed");
+        .execute(request("action=allocate&size=2000"))
+        .expect("memory_intensive.php execution failed");
+    let usage = result.memory_usage();
 
-    assert_eq!(result.status_code(), 200);
+    assert!(usage.peak >= 2_000_000, "peak too low: {usage:?}");
+    assert!(usage.current <= usage.peak);
 
-    assert!(
-        result.body().is_empty(),
-        "Response body should be empty when streaming"
-    );
+    let limit = 4 * 1024 * 1024;
+    let err = php
+        .execute(request("action=allocate&size=10000").memory_limit(limit))
+        .expect_err("allocating past the memory limit should fail");
 
-    let received_chunks = chunks.lock().unwrap();
     assert!(
-        !received_chunks.is_empty(),
-        "Should receive at least one chunk"
+        matches!(
+            err,
+            ExecutionError::MemoryLimitExceeded { limit: l, peak_usage }
+                if l == limit && peak_usage > 0
+        ),
+        "unexpected error: {err:?}"
     );
 
-    let callback_total: usize = received_chunks
-        .iter()
-        .map(|c| c.len())
-        .sum();
+    // Detected from the fatal error itself, not from what gets logged.
+    let err = php
+        .execute(
+            request("action=allocate&size=10000")
+                .memory_limit(limit)
+                .ini("log_errors", "0"),
+        )
+        .expect_err("allocating past the memory limit should fail");
     assert!(
-        callback_total >= 1024 * 1024,
-        "Should receive 1MB+ via callback, got {} bytes",
-        callback_total
+        matches!(err, ExecutionError::MemoryLimitExceeded { limit: l, .. } if l == limit),
+        "unexpected error: {err:?}"
     );
 
-    drop(received_chunks);
-
-    let exec2 = WebRequest::get()
-        .build(&script_path)
-        .expect("failed to build non-streaming WebRequest");
-    let result2 = php
-        .execute(exec2)
-        .expect("non-streaming large output request execution failed");
-    assert!(
-        result2.body().len() >= 1024 * 1024,
-        "Non-streaming should buffer the full output: {} bytes",
-        result2.body().len()
-    );
+    // The limit doesn't carry over.
+    let result = php
+        .execute(request("action=allocate&size=10000"))
+        .expect("request without a memory limit failed");
+    assert_eq!(result.status_code(), 200);
 }
 
-// Tests for internal SAPI state (post_read flag, server_context cleanup)
-// have been moved to src/sapi/callbacks.rs unit tests since they require
-// access to internal FFI types.
-
 #[test]
-fn test_header_edge_cases_duplicate_set_cookie_headers() {
+fn test_memory_limit_message_from_script_is_not_an_error() {
     let php = RiphtSapi::instance();
-    let script_path = php_script_path("header_edge_cases.php");
-
     let exec = WebRequest::get()
-        .with_uri("/header_edge_cases.php?test=duplicate")
-        .build(&script_path)
-        .expect("failed to build header edge cases WebRequest");
+        .build(php_script_path("memory_message.php"))
+        .expect("failed to build WebRequest");
 
     let result = php
         .execute(exec)
-        .expect("header edge cases (duplicate) request execution failed");
-
-    let set_cookies = result.header_vals("Set-Cookie");
-    assert_eq!(
-        set_cookies.len(),
-        3,
-        "Expected 3 Set-Cookie headers, got {:?}",
-        set_cookies
-    );
+        .expect("logging the memory limit message should not fail");
 
-    assert!(set_cookies
-        .iter()
-        .any(|v| v.contains("a=1")));
-    assert!(set_cookies
-        .iter()
-        .any(|v| v.contains("b=2")));
-    assert!(set_cookies
-        .iter()
-        .any(|v| v.contains("c=3")));
+    assert_eq!(result.status_code(), 200);
+    assert!(result
+        .body_string()
+        .ends_with("done"));
 }
 
 #[test]
-fn test_header_edge_cases_header_remove() {
+fn test_execution_metrics_break_down_the_request() {
     let php = RiphtSapi::instance();
-    let script_path = php_script_path("header_edge_cases.php");
-
     let exec = WebRequest::get()
-        .with_uri("/header_edge_cases.php?test=remove")
-        .build(&script_path)
-        .expect("failed to build header remove WebRequest");
+        .build(php_script_path("hello.php"))
+        .expect("failed to build WebRequest");
 
     let result = php
         .execute(exec)
-        .expect("header edge cases (remove) request execution failed");
+        .expect("hello.php execution failed");
+    let metrics = result.metrics();
 
+    assert!(metrics.script > Duration::ZERO);
+    assert!(metrics.send_headers > Duration::ZERO);
     assert!(
-        result
-            .header_val("X-To-Remove")
-            .is_none(),
-        "X-To-Remove should not be present after header_remove()"
+        metrics.total
+            >= metrics.context
+                + metrics.startup
+                + metrics.script
+                + metrics.shutdown
     );
-
-    let kept = result
-        .header_val("X-Kept")
-        .map(|v| v.contains("still here"))
-        .unwrap_or(false);
-    assert!(kept, "X-Kept should be present");
+    assert_eq!(metrics.body_bytes, result.body().len());
+    assert_eq!(metrics.output_bytes, result.body().len());
+    assert!(metrics.header_bytes > 0);
 }
 
 #[test]
-fn test_status_codes_and_redirect_location_header() {
+fn test_output_limit_stops_runaway_script() {
     let php = RiphtSapi::instance();
+    let limit = 64 * 1024;
+    let build = || {
+        WebRequest::get()
+            .build(php_script_path("runaway_output.php"))
+            .expect("failed to build WebRequest")
+            .max_response_size(limit)
+    };
 
-    let status_script = php_script_path("status_codes.php");
-    let exec_201 = WebRequest::get()
-        .with_uri("/status_codes.php?code=201&method=code")
-        .build(&status_script)
-        .expect("failed to build status 201 WebRequest");
+    let truncating = || {
+        build().buffer_policy(
+            BufferPolicy::new().with_overflow(OverflowAction::Truncate),
+        )
+    };
 
-    let result_201 = php
-        .execute(exec_201)
-        .expect("status_codes.php (201) request execution failed");
-    assert_eq!(result_201.status_code(), 201);
+    let result = php
+        .execute(truncating())
+        .expect("runaway script should stop at the limit");
 
-    let exec_307 = WebRequest::get()
-        .with_uri("/status_codes.php?code=307&method=header")
-        .build(&status_script)
-        .expect("failed to build status 307 WebRequest");
+    assert!(result.is_truncated());
+    assert_eq!(result.body().len(), limit);
 
-    let result_307 = php
-        .execute(exec_307)
-        .expect("status_codes.php (307) request execution failed");
-    assert_eq!(result_307.status_code(), 307);
+    let streamed = Arc::new(std::sync::Mutex::new(0usize));
+    let counter = Arc::clone(&streamed);
+    let result = php
+        .execute_streaming(truncating(), move |chunk| {
+            *counter.lock().unwrap() += chunk.len();
+        })
+        .expect("runaway streaming script should stop at the limit");
 
-    let redirect_script = php_script_path("redirect_handling.php");
-    let exec_redirect = WebRequest::get()
-        .with_uri("/redirect_handling.php?type=301")
-        .build(&redirect_script)
-        .expect("failed to build redirect WebRequest");
+    assert!(result.is_truncated());
+    assert_eq!(*streamed.lock().unwrap(), limit);
 
-    let result_redirect = php
-        .execute(exec_redirect)
-        .expect("redirect_handling.php request execution failed");
+    let err = php
+        .execute(build())
+        .expect_err("the default overflow action aborts");
+
+    assert!(matches!(
+        err,
+        ExecutionError::OutputLimitExceeded { limit: l } if l == limit
+    ));
+}
+
+#[test]
+fn test_max_response_size_uses_engine_policy_at_execution() {
+    let php = RiphtSapi::instance();
+    let limit = 10_000;
+
+    // Built under the default engine, executed after a restart.
+    let exec = WebRequest::get()
+        .with_uri("/large_output.php?size=50000")
+        .build(php_script_path("large_output.php"))
+        .expect("failed to build WebRequest")
+        .max_response_size(limit);
+
+    let engine =
+        ResetEngine::restart_with(RiphtSapi::builder().with_buffer_policy(
+            BufferPolicy::new().with_overflow(OverflowAction::Truncate),
+        ));
 
-    assert_eq!(result_redirect.status_code(), 301);
+    let result = php
+        .execute(exec)
+        .expect("the restarted engine's policy should truncate");
 
-    let location = result_redirect
-        .header_val("Location")
-        .expect("redirect response missing Location header");
-    assert!(
-        location.contains("/redirected.php"),
-        "Expected Location to contain /redirected.php, got: {}",
-        location
-    );
+    assert!(result.is_truncated());
+    assert_eq!(result.body().len(), limit);
+
+    drop(engine);
 }
 
 #[test]
-fn test_binary_output_byte_integrity() {
+fn test_truncated_script_stops_but_runs_shutdown_functions() {
     let php = RiphtSapi::instance();
-    let script_path = php_script_path("binary_output.php");
+    let limit = 1024;
+    let marker = std::env::temp_dir()
+        .join(format!("ripht_sapi_truncated_{}.txt", std::process::id()));
+    let _ = std::fs::remove_file(&marker);
 
     let exec = WebRequest::get()
-        .build(&script_path)
-        .expect("failed to build binary output WebRequest");
+        .with_uri(format!("/?marker={}", marker.display()))
+        .build(php_script_path("truncated_side_effect.php"))
+        .expect("failed to build WebRequest")
+        .buffer_policy(
+            BufferPolicy::new()
+                .with_max_size(limit)
+                .with_overflow(OverflowAction::Truncate),
+        );
 
     let result = php
         .execute(exec)
-        .expect("binary output request execution failed");
+        .expect("truncated execution failed");
 
-    assert_eq!(result.body().len(), 256, "Expected 256 bytes");
-    assert_eq!(result.body()[0], 0);
-    assert_eq!(result.body()[1], 1);
-    assert_eq!(result.body()[255], 255);
+    assert!(result.is_truncated());
+    assert_eq!(result.body().len(), limit);
+
+    let recorded = std::fs::read_to_string(&marker)
+        .expect("shutdown function should have written the marker");
+    let _ = std::fs::remove_file(&marker);
+
+    assert_eq!(recorded, "shutdown\n");
 }
 
 #[test]
-fn test_webrequest_shaping_via_superglobals() {
+fn test_pinned_request_time_and_random_seed() {
     let php = RiphtSapi::instance();
-    let script_path = php_script_path("superglobals.php");
+    let pinned =
+        std::time::UNIX_EPOCH + Duration::from_millis(1_700_000_000_500);
+    let run = || {
+        let exec = WebRequest::get()
+            .build(php_script_path("deterministic.php"))
+            .expect("failed to build WebRequest")
+            .request_time(pinned)
+            .random_seed(1234);
+        let result = php
+            .execute(exec)
+            .expect("deterministic.php execution failed");
 
-    let body = b"raw-body-123".to_vec();
-    let path_info = "/extra/path";
-    let document_root = std::env::temp_dir().join("ripht_sapi_docroot");
+        serde_json::from_slice::<serde_json::Value>(&result.body())
+            .expect("failed to parse deterministic response as JSON")
+    };
 
-    let exec = WebRequest::post()
-        .with_uri("/superglobals.php?alpha=1")
-        .with_header("X-Foo-Bar", "baz")
-        .with_https(true)
-        .with_document_root(&document_root)
-        .with_path_info(path_info)
-        // Intentionally omit Content-Type + Content-Length to exercise defaults
-        .with_body(body.clone())
-        .build(&script_path)
-        .expect("failed to build superglobals WebRequest");
+    let first = run();
+    let second = run();
+
+    assert_eq!(first["request_time"], 1_700_000_000);
+    assert_eq!(first["request_time_float"], 1_700_000_000.5);
+    assert_eq!(first, second);
+}
+
+#[test]
+fn test_mail_is_captured_by_sink() {
+    let php = RiphtSapi::instance();
+    let collector = MailCollector::new();
 
+    let engine = ResetEngine::restart_with(
+        RiphtSapi::builder().with_mail_sink(collector.clone()),
+    );
+
+    let exec = WebRequest::get()
+        .build(php_script_path("mail.php"))
+        .expect("failed to build WebRequest");
     let result = php
         .execute(exec)
-        .expect("superglobals.php request execution failed");
-
-    let json: serde_json::Value = serde_json::from_slice(&result.body())
-        .expect("failed to parse superglobals response as JSON");
+        .expect("mail.php execution failed");
 
-    // Header mapping: X-Foo-Bar => HTTP_X_FOO_BAR => appears as X_FOO_BAR in HTTP_HEADERS
-    assert_eq!(json["HTTP_HEADERS"]["X_FOO_BAR"], "baz");
+    drop(engine);
 
-    // HTTPS shaping
-    assert_eq!(json["SERVER"]["REQUEST_SCHEME"], "https");
-    assert_eq!(json["SERVER"]["HTTPS"], "on");
+    assert_eq!(result.body_string(), r#"{"sent":true,"raw":true}"#);
 
-    // PATH_INFO / PATH_TRANSLATED shaping
-    assert_eq!(json["SERVER"]["PATH_INFO"], path_info);
-    let expected_translated =
-        format!("{}{}", document_root.to_string_lossy(), path_info);
-    assert_eq!(json["SERVER"]["PATH_TRANSLATED"], expected_translated);
+    let mail = result.mail();
+    assert_eq!(mail.len(), 2);
+    assert_eq!(mail[0].to(), ["alice@example.com", "bob@example.com"]);
+    assert_eq!(mail[0].subject(), "Welcome");
+    assert_eq!(mail[0].body(), "Hello from PHP\n");
+    assert_eq!(mail[0].header("from"), Some("app@example.com"));
+    assert_eq!(
+        mail[0]
+            .headers()
+            .iter()
+            .filter(|(name, _)| name == "X-Tags")
+            .count(),
+        2
+    );
+    assert_eq!(mail[1].header("Reply-To"), Some("support@example.com"));
 
-    // Default Content-Type / Content-Length behavior when body is present
-    assert_eq!(json["SERVER"]["CONTENT_TYPE"], "application/octet-stream");
-    assert_eq!(json["SERVER"]["CONTENT_LENGTH"], body.len().to_string());
+    assert_eq!(collector.take(), mail);
 }
 
 #[test]
-fn test_execute_with_hooks_can_filter_headers_and_handle_output() {
+fn test_http_mock_answers_stream_requests() {
     let php = RiphtSapi::instance();
-    let script_path = php_script_path("headers.php");
+    let mock = HttpMock::new()
+        .with_response(
+            "GET",
+            "https://api.example.com/users/1",
+            MockResponse::ok(r#"{"id":1}"#)
+                .with_header("Content-Type", "application/json"),
+        )
+        .with_response(
+            "POST",
+            "https://api.example.com/users",
+            MockResponse::new(201).with_body("created"),
+        )
+        .with_response(
+            "GET",
+            "https://api.example.com/users/2",
+            MockResponse::new(404).with_body("nope"),
+        );
 
-    struct FilterAndCaptureHooks {
-        captured: Arc<std::sync::Mutex<Vec<u8>>>,
+    let engine = ResetEngine::restart_with(
+        RiphtSapi::builder().with_http_mock(mock.clone()),
+    );
+
+    let exec = WebRequest::get()
+        .build(php_script_path("http_mock.php"))
+        .expect("failed to build WebRequest");
+    let result = php
+        .execute(exec)
+        .expect("http_mock.php execution failed");
+
+    drop(engine);
+
+    let body: serde_json::Value =
+        serde_json::from_str(&result.body_string()).expect("invalid JSON");
+    assert_eq!(body["user"]["id"], 1);
+    assert_eq!(body["status"], "HTTP/1.1 200 OK");
+    assert_eq!(body["created"], "created");
+    assert_eq!(body["created_status"], "HTTP/1.1 201 Created");
+    assert_eq!(body["missing"], false);
+    assert_eq!(body["ignored"], "nope");
+    assert_eq!(body["unmatched"], false);
+
+    let calls = mock.take_calls();
+    assert_eq!(calls.len(), 5);
+    assert_eq!(calls[1].method(), "POST");
+    assert_eq!(calls[1].url(), "https://api.example.com/users");
+    assert_eq!(calls[1].header("x-token"), Some("secret"));
+    assert_eq!(calls[1].body(), br#"{"name":"Jane"}"#);
+    assert_eq!(calls[4].url(), "http://offline.example.com/");
+}
+
+/// `blob://` wrapper over a shared map, for the stream wrapper test.
+#[derive(Clone, Default)]
+struct BlobStore {
+    blobs: Arc<std::sync::Mutex<HashMap<String, Vec<u8>>>>,
+}
+
+impl BlobStore {
+    fn key(url: &str) -> &str {
+        url.strip_prefix("blob://")
+            .unwrap_or(url)
     }
 
-    impl ExecutionHooks for FilterAndCaptureHooks {
-        fn on_header(&mut self, name: &str, _value: &str) -> bool {
-            !name.eq_ignore_ascii_case("X-Another-Header")
-        }
+    fn insert(&self, key: &str, contents: &str) {
+        self.blobs
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), contents.into());
+    }
 
-        fn on_output(&mut self, data: &[u8]) -> OutputAction {
-            self.captured
-                .lock()
-                .unwrap()
-                .extend_from_slice(data);
-            OutputAction::Done
-        }
+    fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.blobs
+            .lock()
+            .unwrap()
+            .get(key)
+            .cloned()
     }
 
-    let captured = Arc::new(std::sync::Mutex::new(Vec::<u8>::new()));
+    fn children(&self, dir: &str) -> Vec<String> {
+        let prefix = format!("{dir}/");
+        self.blobs
+            .lock()
+            .unwrap()
+            .keys()
+            .filter_map(|key| key.strip_prefix(&prefix))
+            .map(String::from)
+            .collect()
+    }
+}
 
-    let exec = WebRequest::get()
-        .build(&script_path)
-        .expect("failed to build hooks test WebRequest");
+/// Stores its contents in the `BlobStore` when PHP closes it.
+struct BlobWriter {
+    store: BlobStore,
+    key: String,
+    contents: Vec<u8>,
+}
 
-    let result = php
-        .execute_with_hooks(
-            exec,
-            FilterAndCaptureHooks {
-                captured: Arc::clone(&captured),
-            },
-        )
-        .expect("execute_with_hooks() failed");
+impl WrapperStream for BlobWriter {
+    fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+        Ok(0)
+    }
 
-    assert!(
-        result.body().is_empty(),
-        "Body should be empty when hooks handle output"
-    );
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.contents
+            .extend_from_slice(buf);
+        Ok(buf.len())
+    }
+}
 
-    assert!(result
-        .header_val("X-Custom-Header")
-        .is_some());
-    assert!(
-        result
-            .header_val("X-Another-Header")
-            .is_none(),
-        "X-Another-Header should be filtered out by hooks"
-    );
+impl Drop for BlobWriter {
+    fn drop(&mut self) {
+        self.store
+            .blobs
+            .lock()
+            .unwrap()
+            .insert(self.key.clone(), std::mem::take(&mut self.contents));
+    }
+}
 
-    let captured_bytes = captured
-        .lock()
-        .unwrap()
-        .clone();
-    assert!(!captured_bytes.is_empty(), "Expected captured output");
+impl StreamWrapper for BlobStore {
+    fn open(
+        &self,
+        url: &str,
+        mode: &str,
+    ) -> std::io::Result<Box<dyn WrapperStream>> {
+        let key = Self::key(url);
+
+        if mode.starts_with('r') {
+            let contents = self
+                .get(key)
+                .ok_or(std::io::ErrorKind::NotFound)?;
+            return Ok(Box::new(std::io::Cursor::new(contents)));
+        }
 
-    let captured_json: serde_json::Value =
-        serde_json::from_slice(&captured_bytes)
-            .expect("captured output should be valid JSON");
-    assert_eq!(captured_json["method"], "GET");
+        Ok(Box::new(BlobWriter {
+            store: self.clone(),
+            key: key.to_string(),
+            contents: Vec::new(),
+        }))
+    }
+
+    fn stat(&self, url: &str) -> std::io::Result<StreamStat> {
+        let key = Self::key(url);
+
+        match self.get(key) {
+            Some(contents) => Ok(StreamStat::file(contents.len() as u64)),
+            None if !self.children(key).is_empty() => Ok(StreamStat::dir()),
+            None => Err(std::io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn unlink(&self, url: &str) -> std::io::Result<()> {
+        self.blobs
+            .lock()
+            .unwrap()
+            .remove(Self::key(url))
+            .map(drop)
+            .ok_or_else(|| std::io::ErrorKind::NotFound.into())
+    }
+
+    fn read_dir(&self, url: &str) -> std::io::Result<Vec<String>> {
+        Ok(self.children(Self::key(url)))
+    }
 }
 
 #[test]
-fn test_env_vars_visible_via_getenv() {
+fn test_stream_wrapper_serves_php_file_functions() {
     let php = RiphtSapi::instance();
-    let script_path = php_script_path("env_vars.php");
+    let store = BlobStore::default();
+    store.insert("config/app.json", r#"{"debug":true}"#);
+    store.insert("config/old.json", "{}");
+    store.insert("lib/helper.php", "<?php return strtoupper('helper');");
 
-    let exec = WebRequest::get()
-        .with_env("TEST_ENV_KEY", "hello-env")
-        .build(&script_path)
-        .expect("failed to build env vars WebRequest");
+    let engine = ResetEngine::restart_with(
+        RiphtSapi::builder().with_stream_wrapper("blob", store.clone()),
+    );
 
+    let exec = WebRequest::get()
+        .build(php_script_path("stream_wrapper.php"))
+        .expect("failed to build WebRequest");
     let result = php
         .execute(exec)
-        .expect("env_vars.php request execution failed");
-
-    let json: serde_json::Value = serde_json::from_slice(&result.body())
-        .expect("failed to parse env vars response as JSON");
+        .expect("stream_wrapper.php execution failed");
+
+    drop(engine);
+
+    let body: serde_json::Value =
+        serde_json::from_str(&result.body_string()).expect("invalid JSON");
+    assert_eq!(body["config"]["debug"], true);
+    assert_eq!(body["lines"], serde_json::json!(["line 1", "line 2"]));
+    assert_eq!(body["seeked"], "1");
+    assert_eq!(body["size"], 14);
+    assert_eq!(body["exists"], true);
+    assert_eq!(body["missing"], false);
+    assert_eq!(body["is_dir"], true);
+    assert_eq!(body["entries"], serde_json::json!(["app.json", "old.json"]));
+    assert_eq!(body["included"], "HELPER");
+    assert_eq!(body["unlinked"], true);
 
-    assert_eq!(json["TEST_ENV_KEY"], "hello-env");
-    assert!(json["MISSING_ENV_KEY"].is_null());
+    assert_eq!(
+        store.get("out/report.txt"),
+        Some(b"line 1\nline 2\n".to_vec())
+    );
+    assert_eq!(store.get("config/old.json"), None);
 }
 
 #[test]
-fn test_request_scoped_ini_overrides_apply_and_do_not_leak() {
+fn test_input_filter_accepts_rejects_and_rewrites() {
     let php = RiphtSapi::instance();
-    let script_path = php_script_path("ini_overrides.php");
-
-    let base_exec = WebRequest::get()
-        .build(&script_path)
-        .expect("failed to build baseline INI WebRequest");
-    let base_result = php
-        .execute(base_exec)
-        .expect("ini_overrides.php baseline request execution failed");
-
-    let base_json: serde_json::Value =
-        serde_json::from_slice(&base_result.body())
-            .expect("failed to parse baseline INI response as JSON");
-
-    let base_display_errors = base_json["display_errors"]
-        .as_str()
-        .unwrap_or("")
-        .to_string();
 
-    // Flip the value to exercise request-scoped ini overrides.
-    let override_value = if base_display_errors.is_empty() {
-        "1"
-    } else {
-        "0"
+    let filter = |source: InputSource, name: &str, value: &[u8]| {
+        let text = String::from_utf8_lossy(value);
+
+        if text.contains("<script") || name == "tracking" {
+            InputAction::Reject
+        } else if source == InputSource::Get {
+            InputAction::Replace(
+                text.trim()
+                    .as_bytes()
+                    .to_vec(),
+            )
+        } else if name == "HTTP_USER_AGENT" {
+            InputAction::Replace(
+                text.to_lowercase()
+                    .into_bytes(),
+            )
+        } else {
+            InputAction::Accept
+        }
     };
 
-    let exec = WebRequest::get()
-        .with_ini("display_errors", override_value)
-        .build(&script_path)
-        .expect("failed to build INI override WebRequest");
+    let engine = ResetEngine::restart_with(
+        RiphtSapi::builder().with_input_filter(filter),
+    );
 
+    let exec = WebRequest::post()
+        .with_uri("/?q=%20hello%20&evil=%3Cscript%3Ealert(1)%3C%2Fscript%3E")
+        .with_content_type("application/x-www-form-urlencoded")
+        .with_body(b"name=Jane&comment=%3Cscript%3Ex%3C%2Fscript%3E".to_vec())
+        .with_cookie("session", "abc")
+        .with_cookie("tracking", "xyz")
+        .with_header("User-Agent", "Mozilla/TEST")
+        .build(php_script_path("input_filter.php"))
+        .expect("failed to build WebRequest");
     let result = php
         .execute(exec)
-        .expect("ini_overrides.php override request execution failed");
+        .expect("input_filter.php execution failed");
 
-    let json: serde_json::Value = serde_json::from_slice(&result.body())
-        .expect("failed to parse INI override response as JSON");
+    drop(engine);
 
-    let got = json["display_errors"]
-        .as_str()
-        .unwrap_or("");
-    if override_value == "0" {
-        // For boolean directives PHP may report off as an empty string.
-        assert!(
-            got.is_empty() || got == "0",
-            "Expected display_errors to be off, got: {:?}",
-            got
-        );
-    } else {
-        assert_eq!(got, "1");
-    }
+    let body: serde_json::Value =
+        serde_json::from_str(&result.body_string()).expect("invalid JSON");
+    assert_eq!(body["get"], serde_json::json!({ "q": "hello" }));
+    assert_eq!(body["post"], serde_json::json!({ "name": "Jane" }));
+    assert_eq!(body["cookie"], serde_json::json!({ "session": "abc" }));
+    assert_eq!(body["agent"], "mozilla/test");
+}
 
-    // Verify no leak into subsequent requests.
-    let after_exec = WebRequest::get()
-        .build(&script_path)
-        .expect("failed to build follow-up INI WebRequest");
-    let after_result = php
-        .execute(after_exec)
-        .expect("ini_overrides.php follow-up request execution failed");
+#[cfg(feature = "serde")]
+#[test]
+fn test_body_parsers_fill_post() {
+    use ripht_php_sapi::{JsonBodyParser, PostValue};
 
-    let after_json: serde_json::Value =
-        serde_json::from_slice(&after_result.body())
-            .expect("failed to parse follow-up INI response as JSON");
+    let php = RiphtSapi::instance();
 
-    let after_display_errors = after_json["display_errors"]
-        .as_str()
-        .unwrap_or("");
+    // `key=value` lines, as a stand-in for a binary format.
+    let lines = |_: &str, body: &[u8]| {
+        let fields = String::from_utf8_lossy(body)
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.to_string(), PostValue::from(value)))
+            .collect();
+        Ok(PostValue::Map(fields))
+    };
+
+    let engine = ResetEngine::restart_with(
+        RiphtSapi::builder()
+            .with_body_parser("application/json", JsonBodyParser)
+            .with_body_parser("text/x-lines", lines),
+    );
+
+    let json = br#"{"user":{"name":"Jane","roles":["admin",7]},"active":true}"#;
+    let exec = WebRequest::post()
+        .with_content_type("application/json; charset=utf-8")
+        .with_body(json.to_vec())
+        .build(php_script_path("body_parser.php"))
+        .expect("failed to build WebRequest");
+    let json_result = php
+        .execute(exec)
+        .expect("body_parser.php execution failed");
+
+    let exec = WebRequest::post()
+        .with_content_type("text/x-lines")
+        .with_body(b"a=1\nb=two\n".to_vec())
+        .build(php_script_path("body_parser.php"))
+        .expect("failed to build WebRequest");
+    let lines_result = php
+        .execute(exec)
+        .expect("body_parser.php execution failed");
+
+    drop(engine);
+
+    let body: serde_json::Value =
+        serde_json::from_str(&json_result.body_string()).expect("invalid JSON");
     assert_eq!(
-        after_display_errors, base_display_errors,
-        "display_errors should not leak across requests"
+        body["post"],
+        serde_json::json!({
+            "active": true,
+            "user": { "name": "Jane", "roles": ["admin", 7] },
+        })
     );
+    assert_eq!(body["input"], json.len());
+
+    let body: serde_json::Value =
+        serde_json::from_str(&lines_result.body_string())
+            .expect("invalid JSON");
+    assert_eq!(body["post"], serde_json::json!({ "a": "1", "b": "two" }));
 }